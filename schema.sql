@@ -2,7 +2,11 @@ CREATE TABLE IF NOT EXISTS chat_bots (
           id INTEGER PRIMARY KEY NOT NULL,
           behavior TEXT NOT NULL,
           openai_model TEXT NOT NULL,
-          mock_model TEXT NOT NULL
+          mock_model TEXT NOT NULL,
+          persona_id INTEGER,
+          llm_service TEXT,
+          retention_days INTEGER,
+          group_context BOOLEAN NOT NULL DEFAULT false
       );
 
 CREATE UNIQUE INDEX IF NOT EXISTS unique_index_chat_bot_ids
@@ -11,7 +15,11 @@ CREATE UNIQUE INDEX IF NOT EXISTS unique_index_chat_bot_ids
 CREATE TABLE IF NOT EXISTS chat_threads (
           id INTEGER PRIMARY KEY NOT NULL,
           is_current BOOLEAN,
-          chat_id INTEGER NOT NULL
+          chat_id INTEGER NOT NULL,
+          persona_id INTEGER,
+          parent_message_id INTEGER,
+          title TEXT,
+          imported_at DATETIME
       );
 
 CREATE UNIQUE INDEX IF NOT EXISTS idx_one_current_thread_per_chat ON chat_threads(chat_id) WHERE is_current;
@@ -22,7 +30,147 @@ CREATE TABLE IF NOT EXISTS chat_messages (
     chat_id INTEGER NOT NULL,
     chat_thread_id INTEGER NOT NULL,
     user_role TEXT NOT NULL,
-    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')),
+    telegram_message_id INTEGER,
+    earlier_telegram_message_ids TEXT,
+    parent_message_id INTEGER,
+    sender_name TEXT,
+    llm_service TEXT,
+    tokens INTEGER,
+    stopped_at DATETIME
 );
 
 CREATE INDEX IF NOT EXISTS idx_chat_messages_inserted_at ON chat_messages (inserted_at);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS chat_messages_fts USING fts5(
+    content,
+    content='chat_messages',
+    content_rowid='id',
+    tokenize='unicode61 remove_diacritics 2'
+);
+
+CREATE TRIGGER IF NOT EXISTS chat_messages_fts_insert AFTER INSERT ON chat_messages BEGIN
+    INSERT INTO chat_messages_fts (rowid, content) SELECT new.id, new.content WHERE substr(new.content, 1, 4) <> 'enc:';
+END;
+
+CREATE TRIGGER IF NOT EXISTS chat_messages_fts_delete AFTER DELETE ON chat_messages BEGIN
+    INSERT INTO chat_messages_fts (chat_messages_fts, rowid, content) SELECT 'delete', old.id, old.content WHERE substr(old.content, 1, 4) <> 'enc:';
+END;
+
+CREATE TRIGGER IF NOT EXISTS chat_messages_fts_update AFTER UPDATE OF content ON chat_messages BEGIN
+    INSERT INTO chat_messages_fts (chat_messages_fts, rowid, content) SELECT 'delete', old.id, old.content WHERE substr(old.content, 1, 4) <> 'enc:';
+    INSERT INTO chat_messages_fts (rowid, content) SELECT new.id, new.content WHERE substr(new.content, 1, 4) <> 'enc:';
+END;
+
+CREATE TABLE IF NOT EXISTS personas (
+    id INTEGER PRIMARY KEY NOT NULL,
+    chat_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    system_prompt TEXT NOT NULL,
+    llm_service TEXT,
+    completion_model TEXT,
+    temperature REAL,
+    max_tokens INTEGER,
+    examples TEXT,
+    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_personas_chat_id_name ON personas (chat_id, name);
+
+CREATE TABLE IF NOT EXISTS documents (
+    id INTEGER PRIMARY KEY NOT NULL,
+    chat_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    embedding_model TEXT NOT NULL,
+    chunk_count INTEGER NOT NULL,
+    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_documents_chat_id ON documents (chat_id);
+
+CREATE TABLE IF NOT EXISTS document_chunks (
+    id INTEGER PRIMARY KEY NOT NULL,
+    document_id INTEGER NOT NULL,
+    chat_id INTEGER NOT NULL,
+    chunk_index INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    embedding BLOB NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_document_chunks_chat_id ON document_chunks (chat_id);
+
+CREATE TABLE IF NOT EXISTS memories (
+    id INTEGER PRIMARY KEY NOT NULL,
+    chat_id INTEGER NOT NULL,
+    content TEXT NOT NULL,
+    source TEXT NOT NULL,
+    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_memories_chat_id_content ON memories (chat_id, content);
+
+CREATE TABLE IF NOT EXISTS model_comparisons (
+    id INTEGER PRIMARY KEY NOT NULL,
+    chat_id INTEGER NOT NULL,
+    prompt TEXT NOT NULL,
+    winner_index INTEGER,
+    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+);
+
+CREATE INDEX IF NOT EXISTS idx_model_comparisons_chat_id ON model_comparisons (chat_id);
+
+CREATE TABLE IF NOT EXISTS model_comparison_answers (
+    id INTEGER PRIMARY KEY NOT NULL,
+    comparison_id INTEGER NOT NULL,
+    answer_index INTEGER NOT NULL,
+    llm_service TEXT NOT NULL,
+    completion_model TEXT NOT NULL,
+    content TEXT NOT NULL,
+    failed BOOLEAN NOT NULL,
+    latency_ms INTEGER NOT NULL,
+    prompt_tokens INTEGER,
+    completion_tokens INTEGER
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_model_comparison_answers_index ON model_comparison_answers (comparison_id, answer_index);
+
+CREATE TABLE IF NOT EXISTS users (
+    id INTEGER PRIMARY KEY NOT NULL,
+    first_name TEXT NOT NULL,
+    username TEXT,
+    status TEXT NOT NULL,
+    invite_code_id INTEGER,
+    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')),
+    messages_per_minute INTEGER,
+    daily_messages INTEGER,
+    daily_tokens INTEGER,
+    last_seen_at DATETIME
+);
+
+CREATE TABLE IF NOT EXISTS invite_codes (
+    id INTEGER PRIMARY KEY NOT NULL,
+    code TEXT NOT NULL,
+    created_by INTEGER NOT NULL,
+    max_uses INTEGER NOT NULL,
+    uses INTEGER NOT NULL DEFAULT 0,
+    inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_invite_codes_code ON invite_codes (code);
+
+CREATE TABLE IF NOT EXISTS daily_usage (
+    subject TEXT NOT NULL,
+    subject_id INTEGER NOT NULL,
+    day TEXT NOT NULL,
+    messages INTEGER NOT NULL DEFAULT 0,
+    tokens INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (subject, subject_id, day)
+);
+
+CREATE TABLE IF NOT EXISTS rate_limit_buckets (
+    subject TEXT NOT NULL,
+    subject_id INTEGER NOT NULL,
+    tokens REAL NOT NULL,
+    updated_at REAL NOT NULL,
+    PRIMARY KEY (subject, subject_id)
+);
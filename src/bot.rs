@@ -1,27 +1,66 @@
+use crate::code_attachments;
 use crate::config::Config;
 use crate::db::chat_bot;
 use crate::db::chat_message;
 use crate::db::chat_thread;
+use crate::db::persona::{NewPersona, Persona};
+use crate::knowledge;
 use crate::llm;
+use crate::llm::behavior_template;
+use crate::llm::behavior_template::BehaviorContext;
+use crate::llm::embeddings;
 use crate::llm::groq;
 use crate::llm::groq::GroqCompletionModel;
 use crate::llm::llm_thread_message;
+use crate::llm::llm_thread_message::LLMThreadMessage;
 use crate::llm::mock;
 use crate::llm::mock::MockCompletionModel;
 use crate::llm::openai;
 use crate::llm::openai::OpenAICompletionModel;
+use crate::llm::GenerationParams;
 use crate::llm::LLMServiceKind;
+use crate::retention;
+use crate::telegram_html;
+use crate::thread_title;
+use crate::usage_limits;
+use crate::web_page;
 use anyhow::{anyhow, Context, Result};
 use mobot::*;
-use mobot::{api::InlineKeyboardButton, api::SendMessageRequest};
+use mobot::{
+    api::DeleteMessageRequest, api::EditMessageBase, api::EditMessageTextRequest,
+    api::InlineKeyboardButton, api::SendMessageRequest,
+};
 use sqlx::{Pool, Sqlite};
 use std::sync::Arc;
 use std::{collections::HashMap, env};
 use tokio::sync::RwLock;
 
+mod access;
+mod admin;
+mod answers;
+mod chat_queue;
+mod compare;
+mod documents;
+mod edits;
+mod export;
+mod groups;
+mod import;
+mod inline;
+mod limits;
+mod memory;
+mod persona;
+mod privacy;
+mod progress;
+mod search;
+mod summarize;
+mod telegram_client;
+mod threads;
+
 #[derive(Clone)]
 enum UserChatState {
     WaitingBehaviorInput,
+    WaitingPersonaPrompt(NewPersona),
+    WaitingThreadTitle(i64),
     Default,
 }
 
@@ -29,7 +68,12 @@ enum UserChatState {
 struct RunningBotState {
     db_pool: Option<Pool<Sqlite>>,
     user_chat_state: Arc<RwLock<HashMap<i64, UserChatState>>>,
+    pending_imports: Arc<RwLock<HashMap<i64, import::PendingImport>>>,
+    inline_queries: Arc<RwLock<inline::InlineQueries>>,
+    chat_queues: Arc<chat_queue::ChatQueues>,
     config: Config,
+    http_client: reqwest::Client,
+    bot_user: Option<api::User>,
 }
 
 async fn handle_get_version(
@@ -45,15 +89,24 @@ fn buttons_for_completion_models(
     let buttons: Vec<InlineKeyboardButton> = match llm_service_kind {
         LLMServiceKind::Mock => mock::all_completions()
             .iter()
-            .map(|m| api::InlineKeyboardButton::from(m.as_str()).with_callback_data(m.as_str()))
+            .map(|m| {
+                api::InlineKeyboardButton::from(m.as_str())
+                    .with_callback_data(format!("model:{}", m.as_str()))
+            })
             .collect(),
         LLMServiceKind::OpenAI => openai::all_completions()
             .iter()
-            .map(|m| api::InlineKeyboardButton::from(m.as_str()).with_callback_data(m.as_str()))
+            .map(|m| {
+                api::InlineKeyboardButton::from(m.as_str())
+                    .with_callback_data(format!("model:{}", m.as_str()))
+            })
             .collect(),
         LLMServiceKind::Groq => groq::all_completions()
             .iter()
-            .map(|m| api::InlineKeyboardButton::from(m.as_str()).with_callback_data(m.as_str()))
+            .map(|m| {
+                api::InlineKeyboardButton::from(m.as_str())
+                    .with_callback_data(format!("model:{}", m.as_str()))
+            })
             .collect(),
     };
 
@@ -66,6 +119,17 @@ async fn handle_set_completion_model(
 ) -> Result<Action, anyhow::Error> {
     let chat_id = e.update.chat_id()?;
     let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let chat_bot = current_chat_bot(&db, &state, chat_id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let llm_service = chat_llm_service(&state.config, &chat_bot);
 
     e.api
         .send_message(
@@ -73,11 +137,11 @@ async fn handle_set_completion_model(
                 chat_id,
                 format!(
                     "Choose the completion model for {:?}:",
-                    state.config.llm_service.to_string()
+                    llm_service.to_string()
                 ),
             )
             .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(
-                buttons_for_completion_models(state.config.llm_service),
+                buttons_for_completion_models(llm_service),
             )),
         )
         .await?;
@@ -94,17 +158,12 @@ async fn handle_get_completion_model(e: Event, state: State<RunningBotState>) ->
         .cloned()
         .ok_or_else(|| anyhow!("Database pool not available"))?;
 
-    let chat_bot = chat_bot::get_or_create_chat_bot(&db, message.chat.id)
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
         .await
         .context("Failed to get or create chat bot")?;
 
-    let llm_service = state.config.llm_service;
-
-    let current_completion_model = match llm_service {
-        LLMServiceKind::Mock => chat_bot.mock_model,
-        LLMServiceKind::OpenAI => chat_bot.openai_model,
-        LLMServiceKind::Groq => chat_bot.groq_model,
-    };
+    let llm_service = chat_llm_service(&state.config, &chat_bot);
+    let current_completion_model = current_completion_model(&chat_bot, llm_service);
 
     Ok(Action::ReplyText(format!(
         "Bot uses {:?} with {:?} completion model",
@@ -113,12 +172,517 @@ async fn handle_get_completion_model(e: Event, state: State<RunningBotState>) ->
     )))
 }
 
+fn current_completion_model(chat_bot: &chat_bot::ChatBot, llm_service: LLMServiceKind) -> String {
+    match llm_service {
+        LLMServiceKind::Mock => chat_bot.mock_model.clone(),
+        LLMServiceKind::OpenAI => chat_bot.openai_model.clone(),
+        LLMServiceKind::Groq => chat_bot.groq_model.clone(),
+    }
+}
+
+// The provider picked for the chat, e.g. by a model comparison, as long as its
+// credentials are still configured.
+fn chat_llm_service(config: &Config, chat_bot: &chat_bot::ChatBot) -> LLMServiceKind {
+    chat_bot
+        .llm_service
+        .as_ref()
+        .and_then(|s| s.parse::<LLMServiceKind>().ok())
+        .filter(|s| config.is_llm_service_available(*s))
+        .unwrap_or(config.llm_service)
+}
+
+struct ChatLLMSettings {
+    llm_service: LLMServiceKind,
+    completion_model: String,
+    generation_params: GenerationParams,
+}
+
+// The chat's bot, which starts with the catalog's default behavior.
+async fn current_chat_bot(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_id: i64,
+) -> Result<chat_bot::ChatBot> {
+    chat_bot::get_or_create_chat_bot(db, chat_id, state.config.persona_catalog.default_behavior())
+        .await
+}
+
+async fn active_persona(
+    db: &Pool<Sqlite>,
+    chat_bot: &chat_bot::ChatBot,
+) -> Result<Option<Persona>> {
+    match chat_bot.persona_id {
+        Some(persona_id) => crate::db::persona::get_persona(db, chat_bot.id, persona_id)
+            .await
+            .context("Failed to get the active persona"),
+        None => Ok(None),
+    }
+}
+
+// The persona's preferred provider is only used when its credentials are
+// configured, otherwise the chat falls back to the bot-wide provider.
+fn chat_llm_settings(
+    config: &Config,
+    chat_bot: &chat_bot::ChatBot,
+    persona: Option<&Persona>,
+) -> ChatLLMSettings {
+    let persona_llm_service = persona
+        .and_then(|p| p.llm_service.as_ref())
+        .and_then(|s| s.parse::<LLMServiceKind>().ok())
+        .filter(|s| config.is_llm_service_available(*s));
+
+    let llm_service = persona_llm_service.unwrap_or(chat_llm_service(config, chat_bot));
+
+    let completion_model = persona
+        .and_then(|p| p.completion_model.clone())
+        .filter(|m| llm::is_valid_completion_model(llm_service, m))
+        .unwrap_or_else(|| current_completion_model(chat_bot, llm_service));
+
+    let generation_params = GenerationParams {
+        temperature: persona.and_then(|p| p.temperature),
+        max_tokens: persona
+            .and_then(|p| p.max_tokens)
+            .and_then(|t| u16::try_from(t).ok()),
+    };
+
+    ChatLLMSettings {
+        llm_service,
+        completion_model,
+        generation_params,
+    }
+}
+
+fn behavior_context_for_message(message: &api::Message, model: &str) -> BehaviorContext {
+    behavior_context_for_chat(&message.chat, message.from.as_ref(), model)
+}
+
+fn behavior_context_for_chat(
+    chat: &api::Chat,
+    user: Option<&api::User>,
+    model: &str,
+) -> BehaviorContext {
+    let mut behavior_context = BehaviorContext::new(model);
+
+    behavior_context.user_first_name = user.map(|u| u.first_name.clone());
+    behavior_context.language = user.and_then(|u| u.language_code.clone());
+    behavior_context.chat_title = chat.title.clone().or_else(|| chat.first_name.clone());
+
+    behavior_context
+}
+
+struct ThreadAnswer {
+    chat_message_id: i64,
+    content: String,
+}
+
+// The answer with the provider and the tokens it took, which are recorded on
+// the stored answer.
+struct GeneratedAnswer {
+    content: String,
+    llm_service: LLMServiceKind,
+    used_tokens: i64,
+}
+
+impl GeneratedAnswer {
+    async fn record_usage(&self, db: &Pool<Sqlite>, chat_message_id: i64) -> Result<()> {
+        chat_message::add_message_usage(
+            db,
+            chat_message_id,
+            self.llm_service.as_str(),
+            self.used_tokens,
+        )
+        .await
+    }
+}
+
+// Asks the chat's model to answer the thread messages, followed by an
+// instruction that isn't stored in the thread when there is one, and appends
+// the sources of the document excerpts the answer was based on. The used
+// tokens are counted against the user's and the chat's quotas.
+async fn generate_answer(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_bot: &chat_bot::ChatBot,
+    user_id: Option<i64>,
+    behavior_context: impl Fn(&str) -> BehaviorContext,
+    chat_messages: &[chat_message::ChatMessage],
+    instruction: Option<&str>,
+) -> Result<GeneratedAnswer> {
+    let active_persona = active_persona(db, chat_bot).await?;
+    let llm_settings = chat_llm_settings(&state.config, chat_bot, active_persona.as_ref());
+    let behavior_context = behavior_context(&llm_settings.completion_model);
+
+    let retrieval_query = chat_messages
+        .iter()
+        .rev()
+        .find(|m| m.user_role == "user")
+        .map(|m| m.content.as_str())
+        .unwrap_or_default();
+
+    let embeddings_service = embeddings::new_embeddings_service(&state.config.embeddings);
+    let retrieved_chunks = knowledge::retrieve(
+        db,
+        embeddings_service.as_ref(),
+        chat_bot.id,
+        retrieval_query,
+    )
+    .await
+    .context("Failed to retrieve the document excerpts")?;
+
+    let mut thread_messages = llm_thread_message::build_llm_payload(
+        db,
+        chat_bot.id,
+        chat_messages,
+        &behavior_context,
+        &retrieved_chunks,
+    )
+    .await
+    .context("Failed to get LLM payload.")?;
+
+    if let Some(instruction) = instruction {
+        thread_messages.push(LLMThreadMessage {
+            message: instruction.to_string(),
+            role: "user".to_string(),
+        });
+    }
+
+    let llm_api_client = llm::new_llm_service(
+        llm_settings.llm_service,
+        &llm_settings.completion_model,
+        state.config.groq_api_key.clone(),
+        llm_settings.generation_params,
+    )?;
+
+    let completion = llm_api_client
+        .get_completion(thread_messages.clone())
+        .await?;
+
+    let used_tokens =
+        usage_limits::used_tokens(&thread_messages, &completion.content, completion.usage);
+    limits::record_tokens(db, state, user_id, chat_bot.id, used_tokens).await?;
+
+    let content = match knowledge::sources_footer(&retrieved_chunks) {
+        Some(footer) => format!("{}\n\n{}", completion.content, footer),
+        None => completion.content,
+    };
+
+    Ok(GeneratedAnswer {
+        content,
+        llm_service: llm_settings.llm_service,
+        used_tokens,
+    })
+}
+
+// Stores the user content in the current thread, asks the chat's model for an
+// answer and stores the answer. Returns None when the answer was stopped with
+// /stop, and the user message is marked as stopped.
+async fn answer_in_current_thread(
+    api: &API,
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_bot: &chat_bot::ChatBot,
+    message: &api::Message,
+    user_content: &str,
+) -> Result<Option<ThreadAnswer>> {
+    let current_chat_thread = chat_thread::get_or_create_chat_thread(db, chat_bot.id)
+        .await
+        .context("Failed to get the current chat thread")?;
+
+    chat_thread::set_chat_thread_persona(db, current_chat_thread.id, chat_bot.persona_id)
+        .await
+        .context("Failed to record the thread persona")?;
+
+    let new_chat_message_id = chat_message::insert_new_message(
+        db,
+        user_content,
+        chat_bot.id,
+        current_chat_thread.id,
+        "user",
+        Some(message.message_id),
+        groups::sender_name(message).as_deref(),
+    )
+    .await
+    .context("Failed to insert a new chat message")?;
+
+    let chat_messages = chat_message::get_chat_thread_history(db, current_chat_thread.id)
+        .await
+        .context("Failed to get the thread")?;
+
+    let answer = progress::with_progress(
+        api,
+        chat_bot.id,
+        &progress::THINKING,
+        state.chat_queues.until_stopped(
+            chat_bot.id,
+            generate_answer(
+                db,
+                state,
+                chat_bot,
+                message.from.as_ref().map(|u| u.id),
+                |model| behavior_context_for_message(message, model),
+                &chat_messages,
+                None,
+            ),
+        ),
+    )
+    .await;
+
+    let Some(answer) = answer.transpose()? else {
+        chat_message::set_message_stopped(db, new_chat_message_id).await?;
+
+        return Ok(None);
+    };
+
+    let chat_message_id = chat_message::insert_new_message(
+        db,
+        &answer.content,
+        chat_bot.id,
+        current_chat_thread.id,
+        "assistant",
+        None,
+        None,
+    )
+    .await
+    .context("Failed to insert a new chat message")?;
+
+    answer.record_usage(db, chat_message_id).await?;
+
+    // The group messages kept as context precede the first answer too.
+    let is_first_exchange = !chat_messages
+        .iter()
+        .any(|m| m.chat_thread_id == current_chat_thread.id && m.user_role == "assistant");
+
+    if current_chat_thread.title.is_none() && is_first_exchange {
+        let active_persona = active_persona(db, chat_bot).await?;
+        let llm_settings = chat_llm_settings(&state.config, chat_bot, active_persona.as_ref());
+        let llm_api_client = llm::new_llm_service(
+            llm_settings.llm_service,
+            &llm_settings.completion_model,
+            state.config.groq_api_key.clone(),
+            GenerationParams::default(),
+        )?;
+
+        let subjects = limits::limit_subjects(
+            db,
+            &state.config,
+            message.from.as_ref().map(|u| u.id),
+            chat_bot.id,
+        )
+        .await?;
+
+        thread_title::spawn_thread_title_generation(
+            db.clone(),
+            llm_api_client,
+            subjects,
+            current_chat_thread.id,
+        );
+    }
+
+    Ok(Some(ThreadAnswer {
+        chat_message_id,
+        content: answer.content,
+    }))
+}
+
+// The answer's Markdown rendered as Telegram HTML, in as many messages as
+// needed.
+fn answer_messages(content: &str) -> Vec<String> {
+    telegram_html::split_message(
+        &telegram_html::markdown_to_html(content),
+        telegram_html::MAX_MESSAGE_LENGTH,
+    )
+}
+
+// Sends the messages of an answer, the buttons going with the last one.
+// Returns the ids of the messages.
+async fn send_answer_messages(
+    api: &API,
+    chat_id: i64,
+    messages: &[String],
+    reply_to_message_id: Option<i64>,
+) -> Result<Vec<i64>> {
+    let mut message_ids = vec![];
+
+    for (index, message) in messages.iter().enumerate() {
+        let mut request =
+            SendMessageRequest::new(chat_id, message.clone()).with_parse_mode(api::ParseMode::HTML);
+
+        if index == 0 {
+            request.reply_to_message_id = reply_to_message_id;
+        }
+
+        if index + 1 == messages.len() {
+            request = request.with_reply_markup(answers::answer_keyboard());
+        }
+
+        message_ids.push(api.send_message(&request).await?.message_id);
+    }
+
+    if message_ids.is_empty() {
+        return Err(anyhow!("The answer is empty"));
+    }
+
+    Ok(message_ids)
+}
+
+async fn send_attachments(
+    api: &API,
+    state: &RunningBotState,
+    chat_id: i64,
+    attachments: Vec<code_attachments::Attachment>,
+) -> Result<()> {
+    for attachment in attachments {
+        progress::with_progress(
+            api,
+            chat_id,
+            &progress::UPLOADING,
+            telegram_client::send_document(
+                &state.http_client,
+                &state.config.telegram_token,
+                chat_id,
+                &attachment.file_name,
+                attachment.mime_type,
+                attachment.content.into_bytes(),
+                None,
+            ),
+        )
+        .await
+        .with_context(|| format!("Failed to send {}", attachment.file_name))?;
+    }
+
+    Ok(())
+}
+
+// Sends the answer with the buttons to change it, and keeps the id of the
+// Telegram message to find the answer when a button is pressed. The large code
+// blocks follow as files, while the stored answer keeps them.
+async fn send_answer(
+    api: &API,
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_id: i64,
+    answer: &ThreadAnswer,
+    reply_to_message_id: Option<i64>,
+) -> Result<Action> {
+    let parts = code_attachments::extract_attachments(&answer.content);
+    let telegram_message_ids = send_answer_messages(
+        api,
+        chat_id,
+        &answer_messages(&parts.text),
+        reply_to_message_id,
+    )
+    .await?;
+
+    chat_message::set_telegram_message_ids(db, answer.chat_message_id, &telegram_message_ids)
+        .await?;
+    send_attachments(api, state, chat_id, parts.attachments).await?;
+
+    Ok(Action::Done)
+}
+
+// Replaces the messages of the previous answer by the new answer, editing
+// the parts that changed. The extra parts of the previous answer are deleted,
+// and the extra parts of the new one follow in new messages. The buttons go
+// with the last part.
+async fn edit_answer(
+    api: &API,
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_id: i64,
+    previous_answer: &chat_message::ChatMessage,
+    answer: &ThreadAnswer,
+) -> Result<()> {
+    let parts = code_attachments::extract_attachments(&answer.content);
+    let messages = answer_messages(&parts.text);
+
+    if messages.is_empty() {
+        return Ok(());
+    }
+
+    let previous_ids = previous_answer.telegram_message_ids();
+    // The previous parts are only compared when all of their ids are known.
+    let previous_messages = Some(answer_messages(
+        &code_attachments::extract_attachments(&previous_answer.content).text,
+    ))
+    .filter(|previous_messages| previous_messages.len() == previous_ids.len())
+    .unwrap_or_default();
+
+    let mut telegram_message_ids = vec![];
+
+    for (index, (message, previous_id)) in messages.iter().zip(&previous_ids).enumerate() {
+        let is_last = index + 1 == messages.len();
+        let was_last = index + 1 == previous_ids.len();
+
+        // Telegram refuses to edit a message without changing it, and the
+        // buttons are dropped from a message edited without them.
+        if previous_messages.get(index) != Some(message) || is_last != was_last {
+            let mut base = EditMessageBase::new()
+                .with_chat_id(chat_id)
+                .with_message_id(*previous_id)
+                .with_parse_mode(api::ParseMode::HTML);
+
+            if is_last {
+                base = base.with_reply_markup(answers::answer_keyboard());
+            }
+
+            api.edit_message_text(&EditMessageTextRequest {
+                base,
+                text: message.clone(),
+            })
+            .await?;
+        }
+
+        telegram_message_ids.push(*previous_id);
+    }
+
+    for previous_id in previous_ids.iter().skip(messages.len()) {
+        api.delete_message(&DeleteMessageRequest::new(chat_id, *previous_id))
+            .await?;
+    }
+
+    if telegram_message_ids.len() < messages.len() {
+        telegram_message_ids.extend(
+            send_answer_messages(api, chat_id, &messages[telegram_message_ids.len()..], None)
+                .await?,
+        );
+    }
+
+    chat_message::set_telegram_message_ids(db, answer.chat_message_id, &telegram_message_ids)
+        .await?;
+
+    send_attachments(api, state, chat_id, parts.attachments).await
+}
+
+// The callback payloads are "<kind>:<value>", except for the model buttons
+// sent before the kinds were introduced, which carry the bare model name.
 async fn handle_chat_callback(
     e: Event,
     state: State<RunningBotState>,
+) -> Result<Action, anyhow::Error> {
+    let data = e.update.data().unwrap_or("no callback data").to_string();
+
+    match data.split_once(':') {
+        Some(("answer", action)) => match action.parse::<answers::AnswerAction>() {
+            Ok(action) => answers::handle_answer_callback(e, state, action).await,
+            Err(_) => {
+                e.acknowledge_callback(Some(format!("Unknown action: {}", action)))
+                    .await?;
+                Ok(Action::Done)
+            }
+        },
+        Some(("model", completion_model)) => {
+            handle_model_callback(e, state, completion_model).await
+        }
+        _ => handle_model_callback(e, state, &data).await,
+    }
+}
+
+async fn handle_model_callback(
+    e: Event,
+    state: State<RunningBotState>,
+    btn: &str,
 ) -> Result<Action, anyhow::Error> {
     let state = state.get().read().await;
-    let btn = e.update.data().unwrap_or("no callback data");
     let response = format!("Okay: {}", btn);
     let chat_id = e.update.chat_id()?;
 
@@ -128,14 +692,14 @@ async fn handle_chat_callback(
         .cloned()
         .ok_or_else(|| anyhow!("Database pool not available"))?;
 
-    let chat_bot = chat_bot::get_or_create_chat_bot(&db, chat_id)
+    let chat_bot = current_chat_bot(&db, &state, chat_id)
         .await
         .context("Failed to get or create chat bot")?;
 
     e.acknowledge_callback(Some(response)).await?;
     e.remove_inline_keyboard().await?;
 
-    match state.config.llm_service {
+    match chat_llm_service(&state.config, &chat_bot) {
         LLMServiceKind::Mock => {
             let comp_model_parsed = btn.to_string().parse::<MockCompletionModel>();
 
@@ -198,15 +762,16 @@ async fn handle_chat_callback(
 async fn handle_any(e: Event, state: State<RunningBotState>) -> Result<Action, anyhow::Error> {
     match e.update {
         Update::Message(message) => {
-            let message_content = message.text.unwrap();
+            let message_content = message.text.clone().unwrap();
             let state = state.get().read().await;
+            let _turn = state.chat_queues.wait_turn(message.chat.id).await;
             let db = state
                 .db_pool
                 .as_ref()
                 .cloned()
                 .ok_or_else(|| anyhow!("Database pool not available"))?;
 
-            let chat_bot = chat_bot::get_or_create_chat_bot(&db, message.chat.id)
+            let chat_bot = current_chat_bot(&db, &state, message.chat.id)
                 .await
                 .context("Failed to get or create chat bot")?;
 
@@ -220,6 +785,25 @@ async fn handle_any(e: Event, state: State<RunningBotState>) -> Result<Action, a
 
             match user_chat_state_value {
                 UserChatState::WaitingBehaviorInput => {
+                    let active_persona = active_persona(&db, &chat_bot).await?;
+                    let llm_settings =
+                        chat_llm_settings(&state.config, &chat_bot, active_persona.as_ref());
+                    let behavior_context =
+                        behavior_context_for_message(&message, &llm_settings.completion_model);
+                    let unknown_variables = behavior_template::unknown_variables(&message_content);
+
+                    if !unknown_variables.is_empty() {
+                        return Ok(Action::ReplyText(format!(
+                            "Unknown template variables: {}. Available variables: {}. Please enter the behavior again.",
+                            unknown_variables
+                                .iter()
+                                .map(|v| format!("{{{}}}", v))
+                                .collect::<Vec<String>>()
+                                .join(", "),
+                            available_template_variables()
+                        )));
+                    }
+
                     chat_bot::set_chat_bot_behavior(&db, chat_bot.id, &message_content)
                         .await
                         .context("Failed to set the new chat bot behavior.")?;
@@ -228,72 +812,127 @@ async fn handle_any(e: Event, state: State<RunningBotState>) -> Result<Action, a
                     user_chat_state_write_lock.insert(chat_bot.id, UserChatState::Default);
 
                     Ok(Action::ReplyText(format!(
-                        "Defined the new bot behavior as: '{}'",
-                        message_content
+                        "Defined the new bot behavior as: '{}'\n\nPreview: '{}'",
+                        message_content,
+                        behavior_template::render(&message_content, &behavior_context)
                     )))
                 }
-                UserChatState::Default => {
-                    let current_chat_thread =
-                        chat_thread::get_or_create_chat_thread(&db, message.chat.id)
-                            .await
-                            .context("Failed to get the current chat thread")?;
-
-                    let _new_chat_message_id = chat_message::insert_new_message(
+                UserChatState::WaitingPersonaPrompt(new_persona) => {
+                    persona::save_persona_prompt(
                         &db,
-                        &message_content,
+                        &state,
                         message.chat.id,
-                        current_chat_thread.id,
-                        "user",
+                        new_persona,
+                        &message_content,
                     )
                     .await
-                    .context("Failed to insert a new chat message")?;
-
-                    let thread_messages = llm_thread_message::build_llm_thread_payload(
+                }
+                UserChatState::WaitingThreadTitle(thread_id) => {
+                    threads::save_thread_title(
                         &db,
+                        &state,
                         message.chat.id,
-                        current_chat_thread.id,
+                        thread_id,
+                        &message_content,
                     )
                     .await
-                    .context("Failed to get LLM payload.")?;
-
-                    let llm_api_client: Box<dyn llm::LLMService> = match state.config.llm_service {
-                        llm::LLMServiceKind::OpenAI => Box::new(llm::openai::OpenAI {
-                            completion_model: llm::openai::OpenAICompletionModel::Gpt3_5turbo,
-                        }),
-                        llm::LLMServiceKind::Groq => Box::new(llm::groq::Groq {
-                            completion_model: llm::groq::GroqCompletionModel::Llama3_70b,
-                            api_key: state.config.groq_api_key.clone().unwrap(),
-                        }),
-                        _ => Box::new(llm::mock::Mock {
-                            completion_model: llm::mock::MockCompletionModel::Bright,
-                        }),
+                }
+                UserChatState::Default => {
+                    // In a group, only the messages addressed to the bot are
+                    // answered.
+                    let message_content = if groups::is_group_chat(&message.chat) {
+                        match groups::addressed_prompt(
+                            &db,
+                            &state,
+                            &chat_bot,
+                            &message,
+                            &message_content,
+                        )
+                        .await?
+                        {
+                            Some(prompt) if prompt.is_empty() => {
+                                return Ok(groups::empty_prompt_reply())
+                            }
+                            Some(prompt) => prompt,
+                            None => {
+                                return groups::keep_as_context(
+                                    &db,
+                                    &chat_bot,
+                                    &message,
+                                    &message_content,
+                                )
+                                .await
+                            }
+                        }
+                    } else {
+                        message_content
                     };
 
-                    let maybe_answer = llm_api_client.get_answer(thread_messages);
+                    if let Some(reply) = limits::check_limits(
+                        &db,
+                        &state,
+                        message.from.as_ref().map(|u| u.id),
+                        message.chat.id,
+                    )
+                    .await?
+                    {
+                        return Ok(Action::ReplyText(reply));
+                    }
+
+                    if web_page::is_single_url(&message_content) {
+                        return summarize::summarize_url(
+                            &e.api,
+                            &db,
+                            &state,
+                            &chat_bot,
+                            &message,
+                            message_content.trim(),
+                        )
+                        .await;
+                    }
 
-                    match maybe_answer.await {
-                        Ok(content) => {
-                            let _new_chat_message_id = chat_message::insert_new_message(
+                    match answer_in_current_thread(
+                        &e.api,
+                        &db,
+                        &state,
+                        &chat_bot,
+                        &message,
+                        &message_content,
+                    )
+                    .await
+                    {
+                        Ok(Some(answer)) => {
+                            send_answer(
+                                &e.api,
                                 &db,
-                                &content,
+                                &state,
                                 message.chat.id,
-                                current_chat_thread.id,
-                                "assistant",
+                                &answer,
+                                groups::reply_to(&message),
                             )
                             .await
-                            .context("Failed to insert a new chat message")?;
-
-                            Ok(Action::ReplyText(content))
                         }
+                        Ok(None) => Ok(Action::Done),
                         Err(e) => Ok(Action::ReplyText(format!("Error: {:?}", e))),
                     }
                 }
             }
         }
-        Update::EditedMessage(message) => Ok(Action::ReplyText(format!(
-            "Edited message: {}",
-            message.text.unwrap()
-        ))),
+        Update::EditedMessage(message) => {
+            let state = state.get().read().await;
+            let _turn = state.chat_queues.wait_turn(message.chat.id).await;
+            let db = state
+                .db_pool
+                .as_ref()
+                .cloned()
+                .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+            let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+                .await
+                .context("Failed to get or create chat bot")?;
+
+            edits::reanswer_edited_message(&e.api, &db, &state, &chat_bot, &message).await
+        }
         _ => Ok(Action::ReplyText("Anyhow!".into())),
     }
 }
@@ -302,20 +941,74 @@ async fn handle_start_new_thread(e: Event, state: State<RunningBotState>) -> Res
     let message = e.update.get_new().context("Failed to get new update")?;
     let chat_id = message.chat.id;
     let state = state.get().read().await;
+    let _turn = state.chat_queues.wait_turn(chat_id).await;
     let db = state
         .db_pool
         .as_ref()
         .cloned()
         .ok_or_else(|| anyhow!("Database pool not available"))?;
 
-    let chat_bot = chat_bot::get_or_create_chat_bot(&db, chat_id).await?;
+    let chat_bot = current_chat_bot(&db, &state, chat_id).await?;
     let chat_thread = chat_thread::close_chat_thread(&db, chat_id).await?;
 
     match chat_thread {
-        Some(chat_thread_id) => Ok(Action::ReplyText(format!(
-            "The thread number {:?} for bot {:?} has been closed. Start a new one!",
-            chat_thread_id, chat_bot.id
-        ))),
+        Some(chat_thread) => {
+            let mut reply = format!(
+                "The thread number {:?} for bot {:?} has been closed. Start a new one!",
+                chat_thread.id, chat_bot.id
+            );
+
+            if state.config.auto_memories {
+                let user_id = message.from.as_ref().map(|u| u.id);
+
+                // The extraction is a request to the model, so it's limited like
+                // a message.
+                if let Some(limited) = limits::check_limits(&db, &state, user_id, chat_id).await? {
+                    return Ok(Action::ReplyText(format!(
+                        "{}\n\nNo new facts were remembered. {}",
+                        reply, limited
+                    )));
+                }
+
+                let subjects = limits::limit_subjects(&db, &state.config, user_id, chat_id).await?;
+                let active_persona = active_persona(&db, &chat_bot).await?;
+                let llm_settings =
+                    chat_llm_settings(&state.config, &chat_bot, active_persona.as_ref());
+                let llm_api_client = llm::new_llm_service(
+                    llm_settings.llm_service,
+                    &llm_settings.completion_model,
+                    state.config.groq_api_key.clone(),
+                    GenerationParams::default(),
+                )?;
+
+                // The thread is already closed, so a failed extraction is only logged.
+                match crate::memory::extract_thread_memories(
+                    &db,
+                    llm_api_client.as_ref(),
+                    &subjects,
+                    chat_id,
+                    chat_thread.id,
+                )
+                .await
+                {
+                    Ok(new_memories) if !new_memories.is_empty() => {
+                        reply = format!(
+                            "{}\n\nI'll remember:\n{}",
+                            reply,
+                            new_memories
+                                .iter()
+                                .map(|m| format!("- {}", m.content))
+                                .collect::<Vec<String>>()
+                                .join("\n")
+                        );
+                    }
+                    Ok(_) => {}
+                    Err(e) => println!("Failed to extract memories for chat {}: {:?}", chat_id, e),
+                }
+            }
+
+            Ok(Action::ReplyText(reply))
+        }
         None => Ok(Action::ReplyText(format!(
             "The bot with id {:?} doesn't have active threads. Start a new one!",
             chat_bot.id
@@ -323,6 +1016,18 @@ async fn handle_start_new_thread(e: Event, state: State<RunningBotState>) -> Res
     }
 }
 
+// /stop doesn't wait for its turn, since it stops the work of the current one.
+async fn handle_stop(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+
+    if state.chat_queues.stop(message.chat.id) {
+        Ok(Action::ReplyText("Stopped.".to_string()))
+    } else {
+        Ok(Action::ReplyText("There's no answer to stop.".to_string()))
+    }
+}
+
 async fn handle_get_behavior(e: Event, state: State<RunningBotState>) -> Result<Action> {
     let message = e.update.get_new().context("Failed to get new update")?;
     let state = state.get().read().await;
@@ -332,10 +1037,17 @@ async fn handle_get_behavior(e: Event, state: State<RunningBotState>) -> Result<
         .cloned()
         .ok_or_else(|| anyhow!("Database pool not available"))?;
 
-    let chat_bot = chat_bot::get_or_create_chat_bot(&db, message.chat.id)
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
         .await
         .context("Failed to get or create chat bot")?;
 
+    if let Some(persona) = active_persona(&db, &chat_bot).await? {
+        return Ok(Action::ReplyText(format!(
+            "The active persona '{}' defines the bot behavior as follows: '{:?}'. Use the /persona command to switch it.",
+            persona.name, persona.system_prompt
+        )));
+    }
+
     Ok(Action::ReplyText(format!(
         "The current bot behavior is defined as follows: '{:?}'. Use the /set_behavior command to change it.",
         chat_bot.behavior
@@ -351,57 +1063,177 @@ async fn handle_set_behavior(e: Event, state: State<RunningBotState>) -> Result<
         .cloned()
         .ok_or_else(|| anyhow!("Database pool not available"))?;
 
-    let chat_bot = chat_bot::get_or_create_chat_bot(&db, message.chat.id)
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
         .await
         .context("Failed to get or create chat bot")?;
 
     let mut user_chat_state_write_lock = state.user_chat_state.write().await;
     user_chat_state_write_lock.insert(chat_bot.id, UserChatState::WaitingBehaviorInput);
 
-    Ok(Action::ReplyText(
-        "Please enter the desired chat bot behavior in the next message. Example: 'You are a helpful assistant.'".to_string()
-    ))
+    Ok(Action::ReplyText(format!(
+        "Please enter the desired chat bot behavior in the next message. Example: 'You are a helpful assistant. Today is {{weekday}}, {{date}}.' Available variables: {}",
+        available_template_variables()
+    )))
+}
+
+fn available_template_variables() -> String {
+    behavior_template::TEMPLATE_VARIABLES
+        .iter()
+        .map(|v| format!("{{{}}}", v))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+// The commands are read once per message and dispatched from here, since
+// mobot compiles a regex matcher again for every message it's tried on. The
+// commands without an argument don't match when text follows them, and the
+// unknown commands go on to handle_any.
+async fn handle_command(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let text = message.text.clone().unwrap_or_default();
+    let bot_username = state
+        .get()
+        .read()
+        .await
+        .bot_user
+        .as_ref()
+        .and_then(|bot_user| bot_user.username.clone());
+
+    let Some(command) = groups::parse_command(&text, bot_username.as_deref()) else {
+        return Ok(Action::Next);
+    };
+    let exact = command.argument.is_empty();
+
+    match command.name {
+        "start" => access::handle_start(e, state).await,
+        "invite" => access::handle_invite(e, state).await,
+        "stats" => admin::handle_stats(e, state).await,
+        "broadcast" => admin::handle_broadcast(e, state).await,
+        "ban" => admin::handle_ban(e, state).await,
+        "unban" => admin::handle_unban(e, state).await,
+        "approve" => admin::handle_approve(e, state).await,
+        "user" => admin::handle_user(e, state).await,
+        "quota" => limits::handle_quota(e, state).await,
+        "limit" => limits::handle_limit(e, state).await,
+        "version" if exact => handle_get_version(e, state).await,
+        "get_behavior" if exact => handle_get_behavior(e, state).await,
+        "set_behavior" if exact => handle_set_behavior(e, state).await,
+        "get_model" if exact => handle_get_completion_model(e, state).await,
+        "set_model" if exact => handle_set_completion_model(e, state).await,
+        "new" if exact => handle_start_new_thread(e, state).await,
+        "stop" if exact => handle_stop(e, state).await,
+        "fork" => threads::handle_fork(e, state).await,
+        "threads" if exact => threads::handle_threads(e, state).await,
+        "export" => export::handle_export(e, state).await,
+        "search" => search::handle_search(e, state).await,
+        "persona_add" => persona::handle_persona_add(e, state).await,
+        "persona_list" if exact => persona::handle_persona_list(e, state).await,
+        "persona_delete" if exact => persona::handle_persona_delete(e, state).await,
+        "persona" if exact => persona::handle_persona_switch(e, state).await,
+        "catalog" if exact => persona::handle_catalog(e, state).await,
+        "docs" if exact => documents::handle_docs(e, state).await,
+        "remember" => memory::handle_remember(e, state).await,
+        "memories" if exact => memory::handle_memories(e, state).await,
+        "forget" if exact => memory::handle_forget(e, state).await,
+        "forget_me" if exact => privacy::handle_forget_me(e, state).await,
+        "ask" => groups::handle_ask(e, state).await,
+        "group_context" => groups::handle_group_context(e, state).await,
+        "retention" => privacy::handle_retention(e, state).await,
+        "summarize_url" => summarize::handle_summarize_url(e, state).await,
+        "compare" => compare::handle_compare(e, state).await,
+        _ => Ok(Action::Next),
+    }
 }
 
 pub async fn start_bot(db_pool: &Pool<Sqlite>, config: Config) {
-    let client = Client::new(config.telegram_token.to_string());
+    let client = Client::new(config.telegram_token.to_string())
+        .with_post_handler(telegram_client::TelegramPost::new(&config.telegram_token));
     let user_chat_state: Arc<RwLock<HashMap<i64, UserChatState>>> =
         Arc::new(RwLock::new(HashMap::new()));
 
+    // The bot's username tells the group messages mentioning the bot apart.
+    let bot_user = API::new(
+        Client::new(config.telegram_token.to_string())
+            .with_post_handler(telegram_client::TelegramPost::new(&config.telegram_token)),
+    )
+    .get_me()
+    .await
+    .map_err(|e| println!("Failed to get the bot's user: {:?}", e))
+    .ok();
+
     let state = RunningBotState {
         db_pool: Some(db_pool.clone()),
         user_chat_state,
+        pending_imports: Arc::new(RwLock::new(HashMap::new())),
+        inline_queries: Arc::new(RwLock::new(inline::InlineQueries::default())),
+        chat_queues: Arc::new(chat_queue::ChatQueues::default()),
         config,
+        http_client: web_page::new_http_client(),
+        bot_user,
     };
 
+    retention::spawn_retention_task(db_pool.clone(), state.config.retention_days);
+
     let mut router = Router::<RunningBotState>::new(client).with_state(state);
 
+    // The access check comes first for every kind of update.
+    router.add_route(Route::Message(Matcher::Any), access::handle_access);
+    router.add_route(Route::Message(Matcher::Document), access::handle_access);
+    router.add_route(Route::EditedMessage(Matcher::Any), access::handle_access);
+    router.add_route(Route::CallbackQuery(Matcher::Any), access::handle_access);
+    router.add_route(Route::InlineQuery(Matcher::Any), access::handle_access);
+    router.add_route(Route::Message(Matcher::Prefix("/".into())), handle_command);
     router.add_route(
-        Route::Message(Matcher::Exact("/version".into())),
-        handle_get_version,
+        Route::Message(Matcher::Document),
+        documents::handle_document,
     );
+    router.add_route(Route::Message(Matcher::Any), handle_any);
+    router.add_route(Route::EditedMessage(Matcher::Any), handle_any);
     router.add_route(
-        Route::Message(Matcher::Exact("/get_behavior".into())),
-        handle_get_behavior,
+        Route::CallbackQuery(Matcher::Prefix("persona:".into())),
+        persona::handle_persona_callback,
     );
     router.add_route(
-        Route::Message(Matcher::Exact("/set_behavior".into())),
-        handle_set_behavior,
+        Route::CallbackQuery(Matcher::Prefix("persona_delete:".into())),
+        persona::handle_persona_delete_callback,
     );
     router.add_route(
-        Route::Message(Matcher::Exact("/get_model".into())),
-        handle_get_completion_model,
+        Route::CallbackQuery(Matcher::Prefix("catalog:".into())),
+        persona::handle_catalog_callback,
     );
     router.add_route(
-        Route::Message(Matcher::Exact("/set_model".into())),
-        handle_set_completion_model,
+        Route::CallbackQuery(Matcher::Prefix("doc_delete:".into())),
+        documents::handle_document_delete_callback,
     );
     router.add_route(
-        Route::Message(Matcher::Exact("/new".into())),
-        handle_start_new_thread,
+        Route::CallbackQuery(Matcher::Prefix("memory_delete:".into())),
+        memory::handle_memory_delete_callback,
+    );
+    router.add_route(
+        Route::CallbackQuery(Matcher::Prefix("compare:".into())),
+        compare::handle_compare_callback,
+    );
+    router.add_route(
+        Route::CallbackQuery(Matcher::Prefix("threads:".into())),
+        threads::handle_threads_callback,
+    );
+    router.add_route(
+        Route::CallbackQuery(Matcher::Prefix("import:".into())),
+        import::handle_import_callback,
+    );
+    router.add_route(
+        Route::CallbackQuery(Matcher::Prefix("search:".into())),
+        search::handle_search_callback,
+    );
+    router.add_route(
+        Route::CallbackQuery(Matcher::Prefix("forget_me:".into())),
+        privacy::handle_forget_me_callback,
     );
-    router.add_route(Route::Message(Matcher::Any), handle_any);
     router.add_route(Route::CallbackQuery(Matcher::Any), handle_chat_callback);
+    router.add_route(
+        Route::InlineQuery(Matcher::Any),
+        inline::handle_inline_query,
+    );
     router.start().await;
 }
 
@@ -0,0 +1,273 @@
+use super::{groups, RunningBotState};
+use crate::config::{AccessMode, Config};
+use crate::db::invite_code;
+use crate::db::user;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::AnswerInlineQuery;
+use mobot::*;
+use rand::Rng;
+
+const INVITE_CODE_LENGTH: usize = 10;
+// Without the characters that are easily mistaken for one another.
+const INVITE_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz23456789";
+const MAX_INVITE_CODE_USES: i64 = 1000;
+
+pub fn is_admin(config: &Config, user_id: i64) -> bool {
+    config.admins.contains(&user_id)
+}
+
+// The admins and the allowed users and chats are let in whatever their status,
+// and the blocked users are refused in every mode.
+pub fn is_authorized(config: &Config, user: &user::User, chat_id: i64) -> bool {
+    if is_admin(config, user.id) {
+        return true;
+    }
+
+    if user.status == user::STATUS_BLOCKED {
+        return false;
+    }
+
+    if config.allowed_ids.contains(&user.id) || config.allowed_ids.contains(&chat_id) {
+        return true;
+    }
+
+    match config.access_mode {
+        AccessMode::Open => true,
+        AccessMode::Allowlist | AccessMode::Invite => user.status == user::STATUS_ACTIVE,
+    }
+}
+
+fn refusal(config: &Config, user: &user::User) -> String {
+    if user.status == user::STATUS_BLOCKED {
+        return "Sorry, you don't have access to this bot.".to_string();
+    }
+
+    match config.access_mode {
+        AccessMode::Invite => {
+            "Sorry, this bot is invite-only. Send /start <code> with your invite code to get access."
+                .to_string()
+        }
+        _ => format!(
+            "Sorry, this bot is private. Ask its admin to give you access with your user id {}.",
+            user.id
+        ),
+    }
+}
+
+// Only /start itself goes through, with or without an invite code, and not
+// the other commands or texts that start with it.
+fn is_start_command(text: &str, bot_username: Option<&str>) -> bool {
+    groups::parse_command(text, bot_username).is_some_and(|command| command.name == "start")
+}
+
+// Runs before every other handler, so the unauthorized users are refused
+// before any of their chat data is stored. /start goes through to redeem an
+// invite code.
+pub async fn handle_access(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let (from, chat_id) = match &e.update {
+        Update::Message(message) | Update::EditedMessage(message) => {
+            (message.from.as_ref(), message.chat.id)
+        }
+        Update::CallbackQuery(query) => (
+            Some(&query.from),
+            query
+                .message
+                .as_ref()
+                .map_or(query.from.id, |message| message.chat.id),
+        ),
+        Update::InlineQuery(query) => (Some(&query.from), query.from.id),
+        _ => return Ok(Action::Next),
+    };
+
+    let Some(from) = from else {
+        return Ok(Action::Done);
+    };
+
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let user =
+        user::get_or_create_user(&db, from.id, &from.first_name, from.username.as_deref()).await?;
+
+    if is_authorized(&state.config, &user, chat_id) {
+        return Ok(Action::Next);
+    }
+
+    let bot_username = state
+        .bot_user
+        .as_ref()
+        .and_then(|bot_user| bot_user.username.as_deref());
+
+    match &e.update {
+        Update::Message(message)
+            if message
+                .text
+                .as_deref()
+                .is_some_and(|text| is_start_command(text, bot_username)) =>
+        {
+            Ok(Action::Next)
+        }
+        // The refusals aren't sent to the groups, where the bot may read every
+        // message.
+        Update::Message(message) if message.chat.id == from.id => {
+            Ok(Action::ReplyText(refusal(&state.config, &user)))
+        }
+        Update::CallbackQuery(_) => {
+            e.acknowledge_callback(Some(refusal(&state.config, &user)))
+                .await?;
+            Ok(Action::Done)
+        }
+        Update::InlineQuery(query) => {
+            e.api
+                .answer_inline_query(&AnswerInlineQuery::new(query.id.clone()))
+                .await?;
+            Ok(Action::Done)
+        }
+        _ => Ok(Action::Done),
+    }
+}
+
+pub async fn handle_start(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let Some(from) = message.from.as_ref() else {
+        return Ok(Action::Done);
+    };
+
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let code = groups::command_argument(&message_content, "/start")
+        .split_whitespace()
+        .next();
+
+    let user =
+        user::get_or_create_user(&db, from.id, &from.first_name, from.username.as_deref()).await?;
+
+    if is_authorized(&state.config, &user, message.chat.id) {
+        return Ok(Action::ReplyText(format!(
+            "Hi {}! Send me a message to start a conversation, or /new to start a new thread.",
+            from.first_name
+        )));
+    }
+
+    // The invite codes only let users in in the invite mode.
+    let Some(code) = code.filter(|_| state.config.access_mode == AccessMode::Invite) else {
+        return Ok(Action::ReplyText(refusal(&state.config, &user)));
+    };
+
+    match invite_code::redeem_invite_code(&db, code, user.id).await? {
+        Some(_) => Ok(Action::ReplyText(format!(
+            "Welcome, {}! Your invite code was accepted. Send me a message to start a conversation.",
+            from.first_name
+        ))),
+        None if user.status == user::STATUS_BLOCKED => {
+            Ok(Action::ReplyText(refusal(&state.config, &user)))
+        }
+        None => Ok(Action::ReplyText(
+            "This invite code is invalid or was already used.".to_string(),
+        )),
+    }
+}
+
+fn new_invite_code() -> String {
+    let mut rng = rand::thread_rng();
+
+    (0..INVITE_CODE_LENGTH)
+        .map(|_| INVITE_CODE_ALPHABET[rng.gen_range(0..INVITE_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+// /invite mints a single-use code, and /invite <uses> a code for that many
+// users.
+pub async fn handle_invite(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let Some(from) = message
+        .from
+        .as_ref()
+        .filter(|u| is_admin(&state.config, u.id))
+    else {
+        return Ok(Action::ReplyText(
+            "Only the admins can create invite codes.".to_string(),
+        ));
+    };
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let argument = groups::command_argument(&message_content, "/invite");
+
+    let max_uses = match argument {
+        "" => 1,
+        uses => match uses.parse::<i64>() {
+            Ok(uses) if (1..=MAX_INVITE_CODE_USES).contains(&uses) => uses,
+            _ => {
+                return Ok(Action::ReplyText(format!(
+                    "Please, specify a number of uses between 1 and {}. Example: /invite 5",
+                    MAX_INVITE_CODE_USES
+                )))
+            }
+        },
+    };
+
+    let invite_code =
+        invite_code::create_invite_code(&db, &new_invite_code(), from.id, max_uses).await?;
+
+    let uses = if max_uses == 1 {
+        "one user".to_string()
+    } else {
+        format!("{} users", max_uses)
+    };
+
+    let link = state
+        .bot_user
+        .as_ref()
+        .and_then(|bot_user| bot_user.username.as_ref())
+        .map(|username| {
+            format!(
+                "\n\nOr share the link: https://t.me/{}?start={}",
+                username, invite_code.code
+            )
+        })
+        .unwrap_or_default();
+
+    Ok(Action::ReplyText(format!(
+        "New invite code for {}: {}\n\nIt's redeemed by sending the bot /start {}{}",
+        uses, invite_code.code, invite_code.code, link
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lets_only_the_start_command_through() {
+        let bot_username = Some("helper_bot");
+
+        assert!(is_start_command("/start", bot_username));
+        assert!(is_start_command("/start@helper_bot", bot_username));
+        assert!(is_start_command("/start AbCdEf2345", bot_username));
+        assert!(is_start_command(
+            "/start@helper_bot AbCdEf2345",
+            bot_username
+        ));
+        assert!(!is_start_command("/startx hi", bot_username));
+        assert!(!is_start_command("/start_anything", bot_username));
+        assert!(!is_start_command("/start@other_bot", bot_username));
+        assert!(!is_start_command("start", bot_username));
+    }
+}
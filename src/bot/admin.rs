@@ -0,0 +1,453 @@
+use super::access::is_admin;
+use super::{chat_llm_service, current_completion_model, groups, limits, RunningBotState};
+use crate::db::chat_bot;
+use crate::db::stats;
+use crate::db::user;
+use crate::llm::LLMServiceKind;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::SendMessageRequest;
+use mobot::*;
+use sqlx::{Pool, Sqlite};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+const STATS_DAYS: u32 = 7;
+// Telegram allows about 30 messages per second across all the chats.
+const BROADCAST_DELAY: Duration = Duration::from_millis(50);
+const MAX_REPORTED_FAILURES: usize = 10;
+
+fn admin_only(state: &RunningBotState, message: &api::Message) -> Option<Action> {
+    if message
+        .from
+        .as_ref()
+        .is_some_and(|u| is_admin(&state.config, u.id))
+    {
+        None
+    } else {
+        Some(Action::ReplyText(
+            "Only the admins can use this command.".to_string(),
+        ))
+    }
+}
+
+fn provider_name(llm_service: &str) -> String {
+    llm_service
+        .parse::<LLMServiceKind>()
+        .map(|s| s.to_string())
+        .unwrap_or_else(|_| llm_service.to_string())
+}
+
+pub async fn handle_stats(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    if let Some(refusal) = admin_only(&state, message) {
+        return Ok(refusal);
+    }
+
+    let chat_counts = stats::get_chat_counts(&db).await?;
+    let user_counts = user::count_users_by_status(&db).await?;
+    let daily_messages = stats::get_daily_messages(&db, STATS_DAYS).await?;
+    let daily_tokens = stats::get_daily_tokens(&db, STATS_DAYS).await?;
+
+    let mut days: BTreeMap<String, (i64, i64, Vec<String>)> = BTreeMap::new();
+
+    for day in daily_messages {
+        let entry = days.entry(day.day).or_default();
+        entry.0 = day.user_messages;
+        entry.1 = day.answers;
+    }
+
+    for day in daily_tokens {
+        days.entry(day.day).or_default().2.push(format!(
+            "{} {}",
+            provider_name(&day.llm_service),
+            day.tokens
+        ));
+    }
+
+    let users = if user_counts.is_empty() {
+        "none".to_string()
+    } else {
+        user_counts
+            .iter()
+            .map(|(status, count)| format!("{} {}", count, status))
+            .collect::<Vec<String>>()
+            .join(", ")
+    };
+
+    let daily_lines = if days.is_empty() {
+        "No messages.".to_string()
+    } else {
+        days.iter()
+            .rev()
+            .map(|(day, (user_messages, answers, tokens))| {
+                format!(
+                    "{}: {} messages, {} answers, tokens: {}",
+                    day,
+                    user_messages,
+                    answers,
+                    if tokens.is_empty() {
+                        "none".to_string()
+                    } else {
+                        tokens.join(", ")
+                    }
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    };
+
+    Ok(Action::ReplyText(format!(
+        "Chats: {} known, {} active today, {} in the last {} days\nUsers: {}\n\nThe last {} days (UTC):\n{}",
+        chat_counts.known,
+        chat_counts.active_today,
+        chat_counts.active_this_week,
+        STATS_DAYS,
+        users,
+        STATS_DAYS,
+        daily_lines
+    )))
+}
+
+// Telegram answers the flood errors with "Too Many Requests: retry after N".
+fn retry_after(error: &anyhow::Error) -> Option<Duration> {
+    let error = error.to_string();
+    let seconds = error.split("retry after ").nth(1)?;
+
+    seconds
+        .split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+async fn send_broadcast(api: &API, chat_id: i64, text: &str) -> Result<()> {
+    let request = SendMessageRequest::new(chat_id, text);
+
+    match api.send_message(&request).await {
+        Ok(_) => Ok(()),
+        Err(error) => match retry_after(&error) {
+            Some(delay) => {
+                tokio::time::sleep(delay).await;
+                api.send_message(&request).await?;
+                Ok(())
+            }
+            None => Err(error),
+        },
+    }
+}
+
+// The messages are sent in the background one at a time, and the report is
+// sent to the admin's chat at the end.
+async fn broadcast(api: Arc<API>, chat_ids: Vec<i64>, text: String, report_chat_id: i64) {
+    let mut delivered = 0;
+    let mut failures: Vec<String> = vec![];
+
+    for chat_id in &chat_ids {
+        match send_broadcast(&api, *chat_id, &text).await {
+            Ok(()) => delivered += 1,
+            Err(error) => failures.push(format!("{}: {}", chat_id, error)),
+        }
+
+        tokio::time::sleep(BROADCAST_DELAY).await;
+    }
+
+    let mut report = format!(
+        "The broadcast was delivered to {} of {} chats.",
+        delivered,
+        chat_ids.len()
+    );
+
+    if !failures.is_empty() {
+        report = format!(
+            "{}\n\nFailed:\n{}",
+            report,
+            failures
+                .iter()
+                .take(MAX_REPORTED_FAILURES)
+                .cloned()
+                .collect::<Vec<String>>()
+                .join("\n")
+        );
+
+        if failures.len() > MAX_REPORTED_FAILURES {
+            report = format!(
+                "{}\n…and {} more",
+                report,
+                failures.len() - MAX_REPORTED_FAILURES
+            );
+        }
+    }
+
+    if let Err(e) = api
+        .send_message(&SendMessageRequest::new(report_chat_id, report))
+        .await
+    {
+        println!("Failed to send the broadcast report: {:?}", e);
+    }
+}
+
+pub async fn handle_broadcast(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    if let Some(refusal) = admin_only(&state, message) {
+        return Ok(refusal);
+    }
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let text = groups::command_argument(&message_content, "/broadcast");
+
+    if text.is_empty() {
+        return Ok(Action::ReplyText(
+            "Please, specify the message to send to every chat. Example: /broadcast The bot will be down for maintenance tonight.".to_string(),
+        ));
+    }
+
+    let chat_ids = chat_bot::get_chat_bot_ids(&db).await?;
+
+    tokio::spawn(broadcast(
+        e.api.clone(),
+        chat_ids.clone(),
+        text.to_string(),
+        message.chat.id,
+    ));
+
+    Ok(Action::ReplyText(format!(
+        "Sending the message to {} chats…",
+        chat_ids.len()
+    )))
+}
+
+fn user_id_argument(message: &api::Message, command: &str) -> Option<i64> {
+    groups::command_argument(message.text.as_deref().unwrap_or_default(), command)
+        .parse::<i64>()
+        .ok()
+}
+
+fn user_label(user: &user::User) -> String {
+    match &user.username {
+        Some(username) => format!("{} (@{}, {})", user.first_name, username, user.id),
+        None => format!("{} ({})", user.first_name, user.id),
+    }
+}
+
+pub async fn handle_ban(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    if let Some(refusal) = admin_only(&state, message) {
+        return Ok(refusal);
+    }
+
+    let Some(user_id) = user_id_argument(message, "/ban") else {
+        return Ok(Action::ReplyText(
+            "Please, specify the user id. Example: /ban 123456789".to_string(),
+        ));
+    };
+
+    if is_admin(&state.config, user_id) {
+        return Ok(Action::ReplyText(
+            "The admins can't be banned. Remove them from ADMINS first.".to_string(),
+        ));
+    }
+
+    match user::set_user_status(&db, user_id, user::STATUS_BLOCKED).await? {
+        Some(user) => Ok(Action::ReplyText(format!(
+            "Banned {}. The bot ignores them from now on.",
+            user_label(&user)
+        ))),
+        None => Ok(Action::ReplyText(format!(
+            "The user {} hasn't contacted the bot yet.",
+            user_id
+        ))),
+    }
+}
+
+pub async fn handle_unban(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    if let Some(refusal) = admin_only(&state, message) {
+        return Ok(refusal);
+    }
+
+    let Some(user_id) = user_id_argument(message, "/unban") else {
+        return Ok(Action::ReplyText(
+            "Please, specify the user id. Example: /unban 123456789".to_string(),
+        ));
+    };
+
+    let Some(user) = user::get_user(&db, user_id).await? else {
+        return Ok(Action::ReplyText(format!(
+            "The user {} hasn't contacted the bot yet.",
+            user_id
+        )));
+    };
+
+    match user::unblock_user(&db, user.id).await? {
+        Some(user) => Ok(Action::ReplyText(format!(
+            "Unbanned {}. Their status is {} now.",
+            user_label(&user),
+            user.status
+        ))),
+        None => Ok(Action::ReplyText(format!(
+            "{} isn't banned.",
+            user_label(&user)
+        ))),
+    }
+}
+
+// Lets a pending user in, in the allowlist and invite modes.
+pub async fn handle_approve(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    if let Some(refusal) = admin_only(&state, message) {
+        return Ok(refusal);
+    }
+
+    let Some(user_id) = user_id_argument(message, "/approve") else {
+        return Ok(Action::ReplyText(
+            "Please, specify the user id. Example: /approve 123456789".to_string(),
+        ));
+    };
+
+    let Some(user) = user::get_user(&db, user_id).await? else {
+        return Ok(Action::ReplyText(format!(
+            "The user {} hasn't contacted the bot yet.",
+            user_id
+        )));
+    };
+
+    match user.status.as_str() {
+        user::STATUS_BLOCKED => Ok(Action::ReplyText(format!(
+            "{} is banned. Unban them with /unban first.",
+            user_label(&user)
+        ))),
+        user::STATUS_ACTIVE => Ok(Action::ReplyText(format!(
+            "{} already has access.",
+            user_label(&user)
+        ))),
+        _ => {
+            let user = user::set_user_status(&db, user.id, user::STATUS_ACTIVE)
+                .await?
+                .unwrap_or(user);
+
+            Ok(Action::ReplyText(format!(
+                "Approved {}. They have access now.",
+                user_label(&user)
+            )))
+        }
+    }
+}
+
+async fn private_chat_lines(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    user_id: i64,
+) -> Result<String> {
+    // The id of a private chat is the id of the user.
+    let Some(chat_bot) = chat_bot::find_by_id(db, user_id).await? else {
+        return Ok("No private chat with the bot.".to_string());
+    };
+
+    let llm_service = chat_llm_service(&state.config, &chat_bot);
+    let activity = stats::get_chat_activity(db, chat_bot.id).await?;
+
+    Ok(format!(
+        "Private chat:\n- Model: {} {}\n- Persona: {}\n- Retention: {}\n- Stored messages: {}, {} tokens\n- Last message: {}",
+        llm_service,
+        current_completion_model(&chat_bot, llm_service),
+        if chat_bot.persona_id.is_some() {
+            "custom"
+        } else {
+            "none"
+        },
+        chat_bot
+            .retention_days
+            .map(|days| format!("{} days", days))
+            .unwrap_or_else(|| "default".to_string()),
+        activity.messages,
+        activity.tokens,
+        activity
+            .last_message_at
+            .map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string())
+            .unwrap_or_else(|| "never".to_string())
+    ))
+}
+
+pub async fn handle_user(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    if let Some(refusal) = admin_only(&state, message) {
+        return Ok(refusal);
+    }
+
+    let Some(user_id) = user_id_argument(message, "/user") else {
+        return Ok(Action::ReplyText(
+            "Please, specify the user id. Example: /user 123456789".to_string(),
+        ));
+    };
+
+    let Some(user) = user::get_user(&db, user_id).await? else {
+        return Ok(Action::ReplyText(format!(
+            "The user {} hasn't contacted the bot yet.",
+            user_id
+        )));
+    };
+
+    let status = if is_admin(&state.config, user.id) {
+        format!("{}, admin", user.status)
+    } else {
+        user.status.clone()
+    };
+
+    let last_seen = user
+        .last_seen_at
+        .map(|at| at.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    Ok(Action::ReplyText(format!(
+        "{}\nStatus: {}\nLast seen: {}\n\nToday:\n{}\n\n{}",
+        user_label(&user),
+        status,
+        last_seen,
+        limits::user_allowance_lines(&db, &state.config, user.id).await?,
+        private_chat_lines(&db, &state, user.id).await?
+    )))
+}
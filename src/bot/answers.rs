@@ -0,0 +1,231 @@
+use super::{
+    behavior_context_for_chat, current_chat_bot, edit_answer, generate_answer, limits, progress,
+    send_answer, RunningBotState, ThreadAnswer,
+};
+use crate::db::chat_message;
+use crate::db::chat_thread;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::InlineKeyboardButton;
+use mobot::*;
+use std::str::FromStr;
+
+const CONTINUE_INSTRUCTION: &str =
+    "Continue your previous answer from where it stopped. Don't repeat what you already wrote.";
+const SHORTER_INSTRUCTION: &str =
+    "Rewrite your previous answer to be noticeably shorter. Reply only with the new answer.";
+const LONGER_INSTRUCTION: &str =
+    "Rewrite your previous answer to be longer and more detailed. Reply only with the new answer.";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AnswerAction {
+    Regenerate,
+    Continue,
+    Shorter,
+    Longer,
+}
+
+impl AnswerAction {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            AnswerAction::Regenerate => "regenerate",
+            AnswerAction::Continue => "continue",
+            AnswerAction::Shorter => "shorter",
+            AnswerAction::Longer => "longer",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match *self {
+            AnswerAction::Regenerate => "🔄 Regenerate",
+            AnswerAction::Continue => "➡️ Continue",
+            AnswerAction::Shorter => "Shorter",
+            AnswerAction::Longer => "Longer",
+        }
+    }
+
+    fn button(&self) -> InlineKeyboardButton {
+        InlineKeyboardButton::from(self.label())
+            .with_callback_data(format!("answer:{}", self.as_str()))
+    }
+}
+
+impl FromStr for AnswerAction {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regenerate" => Ok(AnswerAction::Regenerate),
+            "continue" => Ok(AnswerAction::Continue),
+            "shorter" => Ok(AnswerAction::Shorter),
+            "longer" => Ok(AnswerAction::Longer),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn answer_keyboard() -> api::ReplyMarkup {
+    api::ReplyMarkup::inline_keyboard_markup(vec![
+        vec![
+            AnswerAction::Regenerate.button(),
+            AnswerAction::Continue.button(),
+        ],
+        vec![
+            AnswerAction::Shorter.button(),
+            AnswerAction::Longer.button(),
+        ],
+    ])
+}
+
+// Only the latest answer of the current thread can be changed, since the
+// answers after it were based on it.
+pub async fn handle_answer_callback(
+    e: Event,
+    state: State<RunningBotState>,
+    action: AnswerAction,
+) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let _turn = state.chat_queues.wait_turn(chat_id).await;
+    let telegram_message_id = e.update.message_id()?;
+    let callback_query = e.update.get_callback_query()?;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let chat_bot = current_chat_bot(&db, &state, chat_id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let current_chat_thread = chat_thread::get_or_create_chat_thread(&db, chat_id)
+        .await
+        .context("Failed to get the current chat thread")?;
+
+    let mut chat_messages = chat_message::get_chat_thread_history(&db, current_chat_thread.id)
+        .await
+        .context("Failed to get the thread")?;
+
+    let latest_answer = chat_messages
+        .last()
+        .filter(|m| {
+            m.chat_thread_id == current_chat_thread.id
+                && m.user_role == "assistant"
+                && m.telegram_message_id == Some(telegram_message_id)
+        })
+        .cloned();
+
+    let Some(latest_answer) = latest_answer else {
+        e.acknowledge_callback(Some("Only the latest answer can be changed".to_string()))
+            .await?;
+        e.remove_inline_keyboard().await?;
+
+        return Ok(Action::Done);
+    };
+
+    if let Some(reply) =
+        limits::check_limits(&db, &state, Some(callback_query.from.id), chat_id).await?
+    {
+        e.acknowledge_callback(Some(reply)).await?;
+
+        return Ok(Action::Done);
+    }
+
+    e.acknowledge_callback(None).await?;
+
+    let instruction = match action {
+        AnswerAction::Regenerate => {
+            chat_messages.pop();
+            None
+        }
+        AnswerAction::Continue => Some(CONTINUE_INSTRUCTION),
+        AnswerAction::Shorter => Some(SHORTER_INSTRUCTION),
+        AnswerAction::Longer => Some(LONGER_INSTRUCTION),
+    };
+
+    let chat = callback_query
+        .message
+        .as_ref()
+        .map(|m| m.chat.clone())
+        .ok_or_else(|| anyhow!("The callback has no message"))?;
+
+    // A stopped answer leaves the previous one as it was.
+    let answer = match progress::with_progress(
+        &e.api,
+        chat_id,
+        &progress::THINKING,
+        state.chat_queues.until_stopped(
+            chat_id,
+            generate_answer(
+                &db,
+                &state,
+                &chat_bot,
+                Some(callback_query.from.id),
+                |model| behavior_context_for_chat(&chat, Some(&callback_query.from), model),
+                &chat_messages,
+                instruction,
+            ),
+        ),
+    )
+    .await
+    {
+        Some(Ok(answer)) => answer,
+        Some(Err(e)) => return Ok(Action::ReplyText(format!("Error: {:?}", e))),
+        None => return Ok(Action::Done),
+    };
+
+    if action == AnswerAction::Continue {
+        e.remove_inline_keyboard().await?;
+
+        let chat_message_id = chat_message::insert_new_message(
+            &db,
+            &answer.content,
+            chat_id,
+            current_chat_thread.id,
+            "assistant",
+            None,
+            None,
+        )
+        .await
+        .context("Failed to insert a new chat message")?;
+
+        answer.record_usage(&db, chat_message_id).await?;
+
+        return send_answer(
+            &e.api,
+            &db,
+            &state,
+            chat_id,
+            &ThreadAnswer {
+                chat_message_id,
+                content: answer.content,
+            },
+            None,
+        )
+        .await;
+    }
+
+    answer.record_usage(&db, latest_answer.id).await?;
+
+    // Telegram refuses to edit a message without changing it.
+    if answer.content == latest_answer.content {
+        return Ok(Action::Done);
+    }
+
+    chat_message::update_message_content(&db, latest_answer.id, &answer.content).await?;
+
+    edit_answer(
+        &e.api,
+        &db,
+        &state,
+        chat_id,
+        &latest_answer,
+        &ThreadAnswer {
+            chat_message_id: latest_answer.id,
+            content: answer.content,
+        },
+    )
+    .await?;
+
+    Ok(Action::Done)
+}
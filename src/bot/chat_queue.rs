@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, OwnedMutexGuard};
+
+// Tokio's mutex is fair, so the turns are taken in the order they were
+// waited for.
+#[derive(Default)]
+struct ChatQueue {
+    turn: Arc<tokio::sync::Mutex<()>>,
+    stop_signal: Mutex<Option<Arc<Notify>>>,
+}
+
+// Every update runs in its own task, so the messages of a chat wait for their
+// turn to be answered one at a time, while the other chats go on.
+#[derive(Default)]
+pub struct ChatQueues {
+    chats: Mutex<HashMap<i64, Arc<ChatQueue>>>,
+}
+
+pub struct Turn<'a> {
+    queues: &'a ChatQueues,
+    chat_id: i64,
+    queue: Arc<ChatQueue>,
+    _guard: OwnedMutexGuard<()>,
+}
+
+impl ChatQueues {
+    fn queue(&self, chat_id: i64) -> Option<Arc<ChatQueue>> {
+        self.chats.lock().unwrap().get(&chat_id).cloned()
+    }
+
+    // Waits for the work of the chat's earlier messages to be done.
+    pub async fn wait_turn(&self, chat_id: i64) -> Turn<'_> {
+        let queue = self
+            .chats
+            .lock()
+            .unwrap()
+            .entry(chat_id)
+            .or_default()
+            .clone();
+        let guard = queue.turn.clone().lock_owned().await;
+
+        *queue.stop_signal.lock().unwrap() = Some(Arc::new(Notify::new()));
+
+        Turn {
+            queues: self,
+            chat_id,
+            queue,
+            _guard: guard,
+        }
+    }
+
+    // Stops the work of the current turn of the chat, or the next work of the
+    // turn when it's between two steps. Returns whether a turn was running.
+    pub fn stop(&self, chat_id: i64) -> bool {
+        let Some(queue) = self.queue(chat_id) else {
+            return false;
+        };
+        let stop_signal = queue.stop_signal.lock().unwrap().clone();
+
+        match stop_signal {
+            Some(stop_signal) => {
+                stop_signal.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Runs the work unless /stop is sent meanwhile, in which case the work is
+    // dropped, which cancels its requests, and None is returned. It goes inside
+    // with_progress, so the status message is still removed on a stop.
+    pub async fn until_stopped<T>(&self, chat_id: i64, work: impl Future<Output = T>) -> Option<T> {
+        let stop_signal = self
+            .queue(chat_id)
+            .and_then(|queue| queue.stop_signal.lock().unwrap().clone());
+
+        let Some(stop_signal) = stop_signal else {
+            return Some(work.await);
+        };
+
+        tokio::select! {
+            output = work => Some(output),
+            _ = stop_signal.notified() => None,
+        }
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        *self.queue.stop_signal.lock().unwrap() = None;
+
+        // The queue is only kept while other messages are waiting for it.
+        let mut chats = self.queues.chats.lock().unwrap();
+
+        if Arc::strong_count(&self.queue) == 2 {
+            chats.remove(&self.chat_id);
+        }
+    }
+}
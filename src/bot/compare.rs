@@ -0,0 +1,398 @@
+use super::{
+    active_persona, behavior_context_for_message, chat_llm_settings, current_chat_bot,
+    current_completion_model, groups, limits, progress, RunningBotState,
+};
+use crate::config::Config;
+use crate::db::chat_bot;
+use crate::db::chat_message;
+use crate::db::chat_thread;
+use crate::db::model_comparison;
+use crate::db::model_comparison::ComparisonAnswer;
+use crate::llm;
+use crate::llm::llm_thread_message;
+use crate::llm::llm_thread_message::LLMThreadMessage;
+use crate::llm::{GenerationParams, LLMServiceKind, ModelPair, TokenUsage};
+use crate::telegram_html;
+use crate::usage_limits;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::{InlineKeyboardButton, SendMessageRequest};
+use mobot::*;
+use std::time::Instant;
+
+const MAX_ANSWER_LENGTH: usize = 3500;
+
+// The configured COMPARE_MODELS, or the chat's model against the chat's
+// models of the other available providers, completed with the other models
+// of the chat's provider when there is only one provider.
+fn comparison_candidates(
+    config: &Config,
+    chat_bot: &chat_bot::ChatBot,
+    current: &ModelPair,
+) -> Vec<ModelPair> {
+    if !config.compare_models.is_empty() {
+        return config
+            .compare_models
+            .iter()
+            .filter(|m| config.is_llm_service_available(m.llm_service))
+            .cloned()
+            .collect();
+    }
+
+    let mut candidates = vec![current.clone()];
+
+    for llm_service in [LLMServiceKind::OpenAI, LLMServiceKind::Groq] {
+        if llm_service != current.llm_service && config.is_llm_service_available(llm_service) {
+            candidates.push(ModelPair {
+                llm_service,
+                completion_model: current_completion_model(chat_bot, llm_service),
+            });
+        }
+    }
+
+    for completion_model in llm::all_completion_models(current.llm_service) {
+        if candidates.len() >= 2 {
+            break;
+        }
+
+        if completion_model != current.completion_model {
+            candidates.push(ModelPair {
+                llm_service: current.llm_service,
+                completion_model: completion_model.to_string(),
+            });
+        }
+    }
+
+    candidates
+}
+
+async fn get_comparison_answer(
+    answer_index: usize,
+    candidate: ModelPair,
+    groq_api_key: Option<String>,
+    generation_params: GenerationParams,
+    thread_messages: Vec<LLMThreadMessage>,
+) -> ComparisonAnswer {
+    let started_at = Instant::now();
+
+    let completion = match llm::new_llm_service(
+        candidate.llm_service,
+        &candidate.completion_model,
+        groq_api_key,
+        generation_params,
+    ) {
+        Ok(llm_api_client) => llm_api_client.get_completion(thread_messages).await,
+        Err(e) => Err(e),
+    };
+
+    let latency_ms = started_at.elapsed().as_millis() as i64;
+
+    let (content, failed, usage) = match completion {
+        Ok(completion) => (completion.content, false, completion.usage),
+        Err(e) => (format!("Error: {:#}", e), true, None),
+    };
+
+    ComparisonAnswer {
+        answer_index: answer_index as i64,
+        llm_service: candidate.llm_service.as_str().to_string(),
+        completion_model: candidate.completion_model,
+        content,
+        failed,
+        latency_ms,
+        prompt_tokens: usage.map(|u| u.prompt_tokens),
+        completion_tokens: usage.map(|u| u.completion_tokens),
+    }
+}
+
+fn answer_label(answer: &ComparisonAnswer) -> String {
+    let llm_service = answer
+        .llm_service
+        .parse::<LLMServiceKind>()
+        .map(|s| s.to_string())
+        .unwrap_or_else(|_| answer.llm_service.clone());
+
+    format!(
+        "#{} {} {}",
+        answer.answer_index + 1,
+        llm_service,
+        answer.completion_model
+    )
+}
+
+// The answer's header and its content rendered as Telegram HTML, split in the
+// messages to send.
+fn answer_messages(answer: &ComparisonAnswer) -> Vec<String> {
+    let usage = match (answer.prompt_tokens, answer.completion_tokens) {
+        (Some(prompt_tokens), Some(completion_tokens)) => format!(
+            "{} prompt + {} completion tokens",
+            prompt_tokens, completion_tokens
+        ),
+        _ => "token usage unknown".to_string(),
+    };
+
+    let content = if answer.content.chars().count() > MAX_ANSWER_LENGTH {
+        format!(
+            "{}…",
+            answer
+                .content
+                .chars()
+                .take(MAX_ANSWER_LENGTH)
+                .collect::<String>()
+        )
+    } else {
+        answer.content.clone()
+    };
+
+    let html = format!(
+        "<b>{}</b> · {:.1}s · {}\n\n{}",
+        telegram_html::escape(&answer_label(answer)),
+        answer.latency_ms as f64 / 1000.0,
+        usage,
+        telegram_html::markdown_to_html(&content)
+    );
+
+    telegram_html::split_message(&html, telegram_html::MAX_MESSAGE_LENGTH)
+}
+
+pub async fn handle_compare(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let chat_id = message.chat.id;
+    let state = state.get().read().await;
+    let _turn = state.chat_queues.wait_turn(chat_id).await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let prompt = groups::command_argument(&message_content, "/compare");
+
+    let chat_bot = current_chat_bot(&db, &state, chat_id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let active_persona = active_persona(&db, &chat_bot).await?;
+    let llm_settings = chat_llm_settings(&state.config, &chat_bot, active_persona.as_ref());
+    let behavior_context = behavior_context_for_message(message, &llm_settings.completion_model);
+
+    let current_chat_thread = chat_thread::get_or_create_chat_thread(&db, chat_id)
+        .await
+        .context("Failed to get the current chat thread")?;
+
+    let mut chat_messages = chat_message::get_chat_thread_history(&db, current_chat_thread.id)
+        .await
+        .context("Failed to get the thread")?;
+
+    // Without a prompt the last user message of the thread is compared, with
+    // the thread up to that message as the context.
+    let prompt = if prompt.is_empty() {
+        let Some(position) = chat_messages.iter().rposition(|m| m.user_role == "user") else {
+            return Ok(Action::ReplyText(
+                "Please, specify the prompt to compare the models on. Example: /compare Explain monads in one paragraph".to_string(),
+            ));
+        };
+
+        chat_messages.truncate(position + 1);
+
+        None
+    } else {
+        Some(prompt.to_string())
+    };
+
+    let mut thread_messages =
+        llm_thread_message::build_llm_payload(&db, chat_id, &chat_messages, &behavior_context, &[])
+            .await
+            .context("Failed to get LLM payload.")?;
+
+    let prompt = match prompt {
+        Some(prompt) => {
+            thread_messages.push(LLMThreadMessage {
+                message: prompt.clone(),
+                role: "user".to_string(),
+            });
+
+            prompt
+        }
+        None => chat_messages
+            .last()
+            .map(|m| m.content.clone())
+            .unwrap_or_default(),
+    };
+
+    let current = ModelPair {
+        llm_service: llm_settings.llm_service,
+        completion_model: llm_settings.completion_model.clone(),
+    };
+    let candidates = comparison_candidates(&state.config, &chat_bot, &current);
+
+    if candidates.len() < 2 {
+        return Ok(Action::ReplyText(
+            "At least two available models are needed for a comparison. Configure them with COMPARE_MODELS.".to_string(),
+        ));
+    }
+
+    let user_id = message.from.as_ref().map(|u| u.id);
+
+    if let Some(reply) = limits::check_limits(&db, &state, user_id, chat_id).await? {
+        return Ok(Action::ReplyText(reply));
+    }
+
+    e.api
+        .send_message(&SendMessageRequest::new(
+            chat_id,
+            format!(
+                "Comparing {}…",
+                candidates
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ),
+        ))
+        .await?;
+
+    let mut handles: Vec<_> = candidates
+        .into_iter()
+        .enumerate()
+        .map(|(answer_index, candidate)| {
+            tokio::spawn(get_comparison_answer(
+                answer_index,
+                candidate,
+                state.config.groq_api_key.clone(),
+                llm_settings.generation_params,
+                thread_messages.clone(),
+            ))
+        })
+        .collect();
+
+    let answers = progress::with_progress(
+        &e.api,
+        chat_id,
+        &progress::THINKING,
+        state.chat_queues.until_stopped(chat_id, async {
+            let mut answers: Vec<ComparisonAnswer> = vec![];
+
+            for handle in handles.iter_mut() {
+                answers.push(handle.await.context("Failed to get a comparison answer")?);
+            }
+
+            anyhow::Ok(answers)
+        }),
+    )
+    .await;
+
+    // A stopped comparison isn't recorded.
+    let Some(answers) = answers else {
+        handles.iter().for_each(|handle| handle.abort());
+
+        return Ok(Action::Done);
+    };
+    let answers = answers?;
+
+    // The comparison counts as one message, with the tokens of every answer.
+    let used_tokens = answers
+        .iter()
+        .filter(|a| !a.failed)
+        .map(|a| {
+            let usage = a.prompt_tokens.zip(a.completion_tokens).map(
+                |(prompt_tokens, completion_tokens)| TokenUsage {
+                    prompt_tokens,
+                    completion_tokens,
+                },
+            );
+
+            usage_limits::used_tokens(&thread_messages, &a.content, usage)
+        })
+        .sum();
+    limits::record_tokens(&db, &state, user_id, chat_id, used_tokens).await?;
+
+    let comparison = model_comparison::insert_model_comparison(&db, chat_id, &prompt, &answers)
+        .await
+        .context("Failed to record the model comparison")?;
+
+    for answer in &answers {
+        for text in answer_messages(answer) {
+            e.api
+                .send_message(
+                    &SendMessageRequest::new(chat_id, text).with_parse_mode(api::ParseMode::HTML),
+                )
+                .await?;
+        }
+    }
+
+    let buttons: Vec<Vec<InlineKeyboardButton>> = answers
+        .iter()
+        .filter(|a| !a.failed)
+        .map(|a| {
+            vec![api::InlineKeyboardButton::from(answer_label(a))
+                .with_callback_data(format!("compare:{}:{}", comparison.id, a.answer_index))]
+        })
+        .collect();
+
+    if buttons.is_empty() {
+        return Ok(Action::ReplyText(
+            "None of the models managed to answer.".to_string(),
+        ));
+    }
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(
+                chat_id,
+                "Which answer is the best? The winner becomes the chat's model.",
+            )
+            .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(buttons)),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_compare_callback(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let (comparison_id, answer_index) = data
+        .trim_start_matches("compare:")
+        .split_once(':')
+        .and_then(|(c, a)| Some((c.parse::<i64>().ok()?, a.parse::<i64>().ok()?)))
+        .ok_or_else(|| anyhow!("Invalid comparison callback data {}", data))?;
+
+    e.acknowledge_callback(None).await?;
+    e.remove_inline_keyboard().await?;
+
+    let answer =
+        model_comparison::get_comparison_answer(&db, chat_id, comparison_id, answer_index).await?;
+
+    let Some(answer) = answer.filter(|a| !a.failed) else {
+        return Ok(Action::ReplyText(
+            "The comparison doesn't exist anymore.".to_string(),
+        ));
+    };
+
+    let llm_service = answer
+        .llm_service
+        .parse::<LLMServiceKind>()
+        .map_err(|_| anyhow!("Unknown provider {}", answer.llm_service))?;
+
+    if !state.config.is_llm_service_available(llm_service) {
+        return Ok(Action::ReplyText(format!(
+            "{} is not available anymore.",
+            llm_service
+        )));
+    }
+
+    model_comparison::set_comparison_winner(&db, chat_id, comparison_id, answer_index).await?;
+    chat_bot::set_chat_bot_model(&db, chat_id, llm_service, &answer.completion_model).await?;
+
+    Ok(Action::ReplyText(format!(
+        "Now the chat uses {} {}.",
+        llm_service, answer.completion_model
+    )))
+}
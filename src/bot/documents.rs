@@ -0,0 +1,171 @@
+use super::{current_chat_bot, import, progress, RunningBotState};
+use crate::db::document;
+use crate::knowledge;
+use crate::llm::embeddings;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::{DownloadRequest, GetFileRequest, InlineKeyboardButton, SendMessageRequest};
+use mobot::*;
+
+pub async fn handle_document(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let telegram_document = e.update.document()?;
+    let file_name = telegram_document
+        .file_name
+        .clone()
+        .unwrap_or_else(|| "document.txt".to_string());
+
+    // The JSON files are conversations to import rather than documents.
+    if file_name.to_lowercase().ends_with(".json") {
+        return import::import_document(&e.api, &state, message.chat.id, telegram_document).await;
+    }
+
+    let extension = match knowledge::file_extension(&file_name) {
+        Some(extension) => extension,
+        None => {
+            return Ok(Action::ReplyText(format!(
+                "Unsupported document type. Supported extensions: {}",
+                knowledge::SUPPORTED_EXTENSIONS.join(", ")
+            )))
+        }
+    };
+
+    if telegram_document.file_size.unwrap_or(0) > knowledge::MAX_DOCUMENT_SIZE {
+        return Ok(Action::ReplyText(format!(
+            "The document is too large. The maximum size is {} MB.",
+            knowledge::MAX_DOCUMENT_SIZE / 1024 / 1024
+        )));
+    }
+
+    current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let embeddings_service = embeddings::new_embeddings_service(&state.config.embeddings);
+
+    let document = progress::with_progress(&e.api, message.chat.id, &progress::READING, async {
+        let content = download_document(&e.api, &telegram_document.file_id).await?;
+        let text = knowledge::extract_text(&extension, content).await?;
+
+        knowledge::ingest_document(
+            &db,
+            embeddings_service.as_ref(),
+            message.chat.id,
+            &file_name,
+            &text,
+        )
+        .await
+    })
+    .await?;
+
+    Ok(Action::ReplyText(format!(
+        "Added the document {} ({} chunks). Ask questions about it, or use /docs to manage the documents.",
+        document.name, document.chunk_count
+    )))
+}
+
+pub async fn download_document(api: &API, file_id: &str) -> Result<Vec<u8>> {
+    let telegram_file = api
+        .get_file(&GetFileRequest::new(file_id.to_string()))
+        .await
+        .context("Failed to get the document file")?;
+
+    let file_path = telegram_file
+        .file_path
+        .ok_or_else(|| anyhow!("The document file path is not available"))?;
+
+    let content = api
+        .download_file(&DownloadRequest::new(file_path))
+        .await
+        .context("Failed to download the document")?;
+
+    Ok(content.to_vec())
+}
+
+pub async fn handle_docs(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let chat_id = e.update.chat_id()?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let documents = document::get_chat_documents(&db, chat_id).await?;
+
+    if documents.is_empty() {
+        return Ok(Action::ReplyText(format!(
+            "There are no documents. Send a {} file to add one.",
+            knowledge::SUPPORTED_EXTENSIONS.join(", ")
+        )));
+    }
+
+    let lines: Vec<String> = documents
+        .iter()
+        .map(|d| {
+            format!(
+                "- {} ({} chunks, added {})",
+                d.name,
+                d.chunk_count,
+                d.inserted_at.format("%Y-%m-%d")
+            )
+        })
+        .collect();
+
+    let buttons: Vec<Vec<InlineKeyboardButton>> = documents
+        .iter()
+        .map(|d| {
+            vec![
+                api::InlineKeyboardButton::from(format!("Remove {}", d.name))
+                    .with_callback_data(format!("doc_delete:{}", d.id)),
+            ]
+        })
+        .collect();
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(chat_id, format!("Documents:\n{}", lines.join("\n")))
+                .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(buttons)),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_document_delete_callback(
+    e: Event,
+    state: State<RunningBotState>,
+) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    e.acknowledge_callback(None).await?;
+    e.remove_inline_keyboard().await?;
+
+    let document_id = data
+        .trim_start_matches("doc_delete:")
+        .parse::<i64>()
+        .context(format!("Invalid document callback data {}", data))?;
+
+    match document::delete_document(&db, chat_id, document_id).await? {
+        None => Ok(Action::ReplyText(
+            "The document doesn't exist anymore.".to_string(),
+        )),
+        Some(document) => Ok(Action::ReplyText(format!(
+            "Removed the document {}",
+            document.name
+        ))),
+    }
+}
@@ -0,0 +1,182 @@
+use super::{
+    behavior_context_for_message, edit_answer, generate_answer, groups, limits, progress,
+    send_answer, RunningBotState, ThreadAnswer,
+};
+use crate::db::chat_bot;
+use crate::db::chat_message;
+use crate::db::chat_thread;
+use anyhow::{Context, Result};
+use mobot::*;
+use sqlx::{Pool, Sqlite};
+
+// An edit of a user message of the current thread replaces the message, drops
+// the turns that followed it and answers again in place of the bot's reply.
+// The edits of the other messages are ignored.
+pub async fn reanswer_edited_message(
+    api: &API,
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_bot: &chat_bot::ChatBot,
+    message: &api::Message,
+) -> Result<Action> {
+    let Some(new_content) = message.text.clone().filter(|t| !t.starts_with('/')) else {
+        return Ok(Action::Done);
+    };
+    let new_content = groups::without_mention(state, &new_content);
+
+    let current_chat_thread = chat_thread::get_or_create_chat_thread(db, chat_bot.id)
+        .await
+        .context("Failed to get the current chat thread")?;
+
+    let chat_messages = chat_message::get_chat_thread_history(db, current_chat_thread.id)
+        .await
+        .context("Failed to get the thread")?;
+
+    let Some(position) = chat_messages.iter().position(|m| {
+        m.chat_thread_id == current_chat_thread.id
+            && m.user_role == "user"
+            && m.telegram_message_id == Some(message.message_id)
+    }) else {
+        return Ok(Action::Done);
+    };
+
+    let edited_message = &chat_messages[position];
+
+    if edited_message.content == new_content {
+        return Ok(Action::Done);
+    }
+
+    chat_message::update_message_content(db, edited_message.id, &new_content).await?;
+
+    let previous_answer = chat_messages
+        .get(position + 1)
+        .filter(|m| m.user_role == "assistant" && m.telegram_message_id.is_some())
+        .cloned();
+
+    // The other members of a group may have written since, so only the latest
+    // exchange is answered again, and the other edits only update the message.
+    if groups::is_group_chat(&message.chat)
+        && (previous_answer.is_none() || position + 2 != chat_messages.len())
+    {
+        return Ok(Action::Done);
+    }
+
+    if let Some(reply) = limits::check_limits(
+        db,
+        state,
+        message.from.as_ref().map(|u| u.id),
+        message.chat.id,
+    )
+    .await?
+    {
+        return Ok(Action::ReplyText(reply));
+    }
+
+    // The forks of the later turns inherit them, so they aren't discarded or
+    // replaced under them.
+    let later_ids: Vec<i64> = chat_messages[position + 1..].iter().map(|m| m.id).collect();
+
+    if chat_thread::count_threads_forked_from(db, &later_ids).await? > 0 {
+        return Ok(Action::ReplyText(
+            "The edit is saved, but it isn't answered again, since the later messages were forked into other threads. Fork from the edited message with /fork to get a new answer.".to_string(),
+        ));
+    }
+
+    let mut history = chat_messages[..=position].to_vec();
+    history[position].content = new_content;
+    history[position].stopped_at = None;
+
+    let answer = match progress::with_progress(
+        api,
+        chat_bot.id,
+        &progress::THINKING,
+        state.chat_queues.until_stopped(
+            chat_bot.id,
+            generate_answer(
+                db,
+                state,
+                chat_bot,
+                message.from.as_ref().map(|u| u.id),
+                |model| behavior_context_for_message(message, model),
+                &history,
+                None,
+            ),
+        ),
+    )
+    .await
+    {
+        Some(Ok(answer)) => answer,
+        Some(Err(e)) => return Ok(Action::ReplyText(format!("Error: {:?}", e))),
+        // The previous answer is kept, so the message is only unanswered when
+        // it had none.
+        None => {
+            if previous_answer.is_none() {
+                chat_message::set_message_stopped(db, edited_message.id).await?;
+            }
+
+            return Ok(Action::Done);
+        }
+    };
+
+    // The later turns are only discarded once they're answered again, so a
+    // failed or stopped answer leaves the thread as it was.
+    let discarded_ids: Vec<i64> = later_ids
+        .into_iter()
+        .filter(|id| Some(*id) != previous_answer.as_ref().map(|m| m.id))
+        .collect();
+
+    chat_message::delete_messages(db, &discarded_ids)
+        .await
+        .context("Failed to discard the turns after the edited message")?;
+    chat_message::clear_message_stopped(db, edited_message.id).await?;
+
+    let Some(previous_answer) = previous_answer else {
+        let chat_message_id = chat_message::insert_new_message(
+            db,
+            &answer.content,
+            chat_bot.id,
+            current_chat_thread.id,
+            "assistant",
+            None,
+            None,
+        )
+        .await
+        .context("Failed to insert a new chat message")?;
+
+        answer.record_usage(db, chat_message_id).await?;
+
+        return send_answer(
+            api,
+            db,
+            state,
+            chat_bot.id,
+            &ThreadAnswer {
+                chat_message_id,
+                content: answer.content,
+            },
+            None,
+        )
+        .await;
+    };
+
+    chat_message::update_message_content(db, previous_answer.id, &answer.content).await?;
+    answer.record_usage(db, previous_answer.id).await?;
+
+    // Telegram refuses to edit a message without changing it.
+    if answer.content != previous_answer.content {
+        edit_answer(
+            api,
+            db,
+            state,
+            chat_bot.id,
+            &previous_answer,
+            &ThreadAnswer {
+                chat_message_id: previous_answer.id,
+                content: answer.content,
+            },
+        )
+        .await?;
+    }
+
+    Ok(Action::Done)
+}
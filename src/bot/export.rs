@@ -0,0 +1,125 @@
+use super::{chat_llm_settings, current_chat_bot, groups, telegram_client, RunningBotState};
+use crate::db::chat_bot;
+use crate::db::chat_message;
+use crate::db::chat_thread;
+use crate::db::persona;
+use crate::thread_export::{ExportFormat, ExportedMessage, ExportedThread, ThreadExport};
+use crate::thread_title;
+use anyhow::{anyhow, Context, Result};
+use chrono::SecondsFormat;
+use mobot::*;
+use sqlx::{Pool, Sqlite};
+
+pub async fn handle_export(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let format = groups::command_argument(&message_content, "/export");
+
+    let format = if format.is_empty() {
+        ExportFormat::Markdown
+    } else {
+        match format.parse::<ExportFormat>() {
+            Ok(format) => format,
+            Err(_) => {
+                return Ok(Action::ReplyText(
+                    "Please, choose md, json or html. Example: /export json".to_string(),
+                ))
+            }
+        }
+    };
+
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let current_chat_thread = chat_thread::get_or_create_chat_thread(&db, chat_bot.id)
+        .await
+        .context("Failed to get the current chat thread")?;
+
+    export_thread(&db, &state, &chat_bot, current_chat_thread.id, format).await
+}
+
+// A forked thread is exported with the history it inherited, as the model
+// sees it.
+pub async fn export_thread(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_bot: &chat_bot::ChatBot,
+    chat_thread_id: i64,
+    format: ExportFormat,
+) -> Result<Action> {
+    let Some(chat_thread) = chat_thread::get_chat_thread(db, chat_bot.id, chat_thread_id).await?
+    else {
+        return Ok(Action::ReplyText(
+            "The thread doesn't exist anymore.".to_string(),
+        ));
+    };
+
+    let chat_messages = chat_message::get_chat_thread_history(db, chat_thread.id)
+        .await
+        .context("Failed to get the thread")?;
+
+    if chat_messages.is_empty() {
+        return Ok(Action::ReplyText(
+            "The thread is empty, there is nothing to export yet.".to_string(),
+        ));
+    }
+
+    let thread_persona = match chat_thread.persona_id {
+        Some(persona_id) => persona::get_persona(db, chat_bot.id, persona_id).await?,
+        None => None,
+    };
+    let llm_settings = chat_llm_settings(&state.config, chat_bot, thread_persona.as_ref());
+
+    let title = chat_thread.title.clone().unwrap_or_else(|| {
+        thread_title::fallback_title(
+            chat_messages
+                .iter()
+                .find(|m| m.user_role == "user")
+                .map(|m| m.content.as_str()),
+        )
+    });
+
+    let export = ThreadExport::new(ExportedThread {
+        title,
+        llm_service: llm_settings.llm_service.as_str().to_string(),
+        completion_model: llm_settings.completion_model,
+        persona: thread_persona.as_ref().map(|p| p.name.clone()),
+        behavior: thread_persona
+            .map(|p| p.system_prompt)
+            .unwrap_or_else(|| chat_bot.behavior.clone()),
+        messages: chat_messages
+            .iter()
+            .map(|m| ExportedMessage {
+                role: m.user_role.clone(),
+                content: m.content.clone(),
+                inserted_at: m.inserted_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            })
+            .collect(),
+    });
+
+    telegram_client::send_document(
+        &state.http_client,
+        &state.config.telegram_token,
+        chat_bot.id,
+        &export.file_name(format),
+        format.mime_type(),
+        export.render(format)?.into_bytes(),
+        Some(&format!(
+            "{} · {} messages",
+            export.thread.title,
+            export.thread.messages.len()
+        )),
+    )
+    .await
+    .context("Failed to send the export")?;
+
+    Ok(Action::Done)
+}
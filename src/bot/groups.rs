@@ -0,0 +1,361 @@
+use super::{answer_in_current_thread, current_chat_bot, limits, send_answer, RunningBotState};
+use crate::db::chat_bot;
+use crate::db::chat_message;
+use crate::db::chat_thread;
+use anyhow::{anyhow, Context, Result};
+use mobot::*;
+use serde::{Deserialize, Serialize};
+use sqlx::{Pool, Sqlite};
+
+#[derive(Serialize)]
+struct GetChatMemberRequest {
+    chat_id: i64,
+    user_id: i64,
+}
+
+impl api::Request for GetChatMemberRequest {}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct ChatMember {
+    status: String,
+}
+
+pub fn is_group_chat(chat: &api::Chat) -> bool {
+    chat.chat_type == "group" || chat.chat_type == "supergroup"
+}
+
+// The user messages of a group are tagged with their sender's name, so the
+// model knows who said what.
+pub fn sender_name(message: &api::Message) -> Option<String> {
+    if !is_group_chat(&message.chat) {
+        return None;
+    }
+
+    message.from.as_ref().map(|user| match &user.last_name {
+        Some(last_name) => format!("{} {}", user.first_name, last_name),
+        None => user.first_name.clone(),
+    })
+}
+
+// mobot has no getChatMember, so the method is called through its client.
+pub async fn is_chat_administrator(api: &API, chat_id: i64, user_id: i64) -> Result<bool> {
+    let chat_member: ChatMember = api
+        .client
+        .post("getChatMember", &GetChatMemberRequest { chat_id, user_id })
+        .await
+        .context("Failed to get the chat member")?;
+
+    Ok(chat_member.status == "creator" || chat_member.status == "administrator")
+}
+
+// The answers in a group reply to the message they answer.
+pub fn reply_to(message: &api::Message) -> Option<i64> {
+    is_group_chat(&message.chat).then_some(message.message_id)
+}
+
+// A command of a message addressed to the bot, with the text after it.
+pub struct BotCommand<'a> {
+    pub name: &'a str,
+    pub argument: &'a str,
+}
+
+// In groups, Telegram appends the bot's username to the commands picked from
+// the menu, as in /threads@bot, so the commands are read with or without it,
+// and the commands of the other bots are left out.
+pub fn parse_command<'a>(text: &'a str, bot_username: Option<&str>) -> Option<BotCommand<'a>> {
+    let text = text.strip_prefix('/')?;
+    let (token, argument) = text.split_at(text.find(char::is_whitespace).unwrap_or(text.len()));
+    let (name, mention) = match token.split_once('@') {
+        Some((name, mention)) => (name, Some(mention)),
+        None => (token, None),
+    };
+
+    let is_for_the_bot = mention.is_none_or(|mention| {
+        bot_username.is_some_and(|bot_username| bot_username.eq_ignore_ascii_case(mention))
+    });
+
+    (!name.is_empty() && is_for_the_bot).then(|| BotCommand {
+        name,
+        argument: argument.trim(),
+    })
+}
+
+// The text after the command and the bot's username.
+pub fn command_argument<'a>(text: &'a str, command: &str) -> &'a str {
+    let argument = text.strip_prefix(command).unwrap_or(text);
+    let argument = match argument.strip_prefix('@') {
+        Some(mention) => {
+            mention.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => argument,
+    };
+
+    argument.trim()
+}
+
+// Removes the mentions of the bot from the text. Returns None when the bot
+// isn't mentioned. The usernames are ASCII, so the lowercase text keeps the
+// byte offsets of the original one.
+fn strip_mention(text: &str, bot_username: &str) -> Option<String> {
+    let lowercase_text = text.to_ascii_lowercase();
+    let mention = format!("@{}", bot_username.to_ascii_lowercase());
+    let mut stripped = String::new();
+    let mut last_end = 0;
+
+    for (start, _) in lowercase_text.match_indices(&mention) {
+        let end = start + mention.len();
+        let is_whole_username =
+            !lowercase_text[end..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+
+        if is_whole_username {
+            stripped.push_str(&text[last_end..start]);
+            last_end = end;
+        }
+    }
+
+    if last_end == 0 {
+        return None;
+    }
+
+    stripped.push_str(&text[last_end..]);
+
+    Some(stripped.trim().to_string())
+}
+
+pub fn without_mention(state: &RunningBotState, text: &str) -> String {
+    state
+        .bot_user
+        .as_ref()
+        .and_then(|bot_user| bot_user.username.as_deref())
+        .and_then(|bot_username| strip_mention(text, bot_username))
+        .unwrap_or_else(|| text.trim().to_string())
+}
+
+// A group message is addressed to the bot when it mentions the bot or replies
+// to one of its answers. Returns the prompt without the mentions.
+pub async fn addressed_prompt(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_bot: &chat_bot::ChatBot,
+    message: &api::Message,
+    text: &str,
+) -> Result<Option<String>> {
+    if text.starts_with('/') {
+        return Ok(None);
+    }
+
+    let mentioned_prompt = state
+        .bot_user
+        .as_ref()
+        .and_then(|bot_user| bot_user.username.as_deref())
+        .and_then(|bot_username| strip_mention(text, bot_username));
+
+    if mentioned_prompt.is_some() {
+        return Ok(mentioned_prompt);
+    }
+
+    let Some(replied_message_id) = message.reply_to_message else {
+        return Ok(None);
+    };
+
+    let replied_message =
+        chat_message::get_by_telegram_message_id(db, chat_bot.id, replied_message_id).await?;
+
+    Ok(replied_message
+        .filter(|m| m.user_role == "assistant")
+        .map(|_| text.to_string()))
+}
+
+// Keeps a group message that isn't addressed to the bot in the current thread
+// without answering it, when the chat keeps the group context.
+pub async fn keep_as_context(
+    db: &Pool<Sqlite>,
+    chat_bot: &chat_bot::ChatBot,
+    message: &api::Message,
+    text: &str,
+) -> Result<Action> {
+    if !chat_bot.group_context || text.starts_with('/') {
+        return Ok(Action::Done);
+    }
+
+    let current_chat_thread = chat_thread::get_or_create_chat_thread(db, chat_bot.id)
+        .await
+        .context("Failed to get the current chat thread")?;
+
+    chat_message::insert_new_message(
+        db,
+        text,
+        chat_bot.id,
+        current_chat_thread.id,
+        "user",
+        Some(message.message_id),
+        sender_name(message).as_deref(),
+    )
+    .await
+    .context("Failed to insert a new chat message")?;
+
+    Ok(Action::Done)
+}
+
+pub fn empty_prompt_reply() -> Action {
+    Action::ReplyText(
+        "Mention me with a question, reply to my answers or use /ask <question>.".to_string(),
+    )
+}
+
+pub async fn handle_ask(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let _turn = state.chat_queues.wait_turn(message.chat.id).await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let prompt = without_mention(&state, command_argument(&message_content, "/ask"));
+
+    if prompt.is_empty() {
+        return Ok(Action::ReplyText(
+            "Please, specify the question. Example: /ask what's the capital of Peru?".to_string(),
+        ));
+    }
+
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    if let Some(reply) = limits::check_limits(
+        &db,
+        &state,
+        message.from.as_ref().map(|u| u.id),
+        message.chat.id,
+    )
+    .await?
+    {
+        return Ok(Action::ReplyText(reply));
+    }
+
+    match answer_in_current_thread(&e.api, &db, &state, &chat_bot, message, &prompt).await {
+        Ok(Some(answer)) => {
+            send_answer(
+                &e.api,
+                &db,
+                &state,
+                message.chat.id,
+                &answer,
+                reply_to(message),
+            )
+            .await
+        }
+        Ok(None) => Ok(Action::Done),
+        Err(e) => Ok(Action::ReplyText(format!("Error: {:?}", e))),
+    }
+}
+
+pub async fn handle_group_context(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let argument = command_argument(&message_content, "/group_context");
+
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let group_context = match argument {
+        "on" => true,
+        "off" => false,
+        _ => {
+            return Ok(Action::ReplyText(format!(
+                "The group context is {}. With it on, the messages that aren't addressed to me are kept in the thread, so I can use them when I answer. Use /group_context on or /group_context off.",
+                if chat_bot.group_context { "on" } else { "off" }
+            )))
+        }
+    };
+
+    chat_bot::set_chat_bot_group_context(&db, chat_bot.id, group_context)
+        .await
+        .context("Failed to set the group context")?;
+
+    Ok(Action::ReplyText(if group_context {
+        "I'll keep the messages that aren't addressed to me as context.".to_string()
+    } else {
+        "I'll ignore the messages that aren't addressed to me.".to_string()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command_name<'a>(text: &'a str, bot_username: Option<&str>) -> Option<&'a str> {
+        parse_command(text, bot_username).map(|command| command.name)
+    }
+
+    #[test]
+    fn reads_a_command_with_or_without_the_bot_username() {
+        let bot_username = Some("helper_bot");
+
+        assert_eq!(command_name("/threads", bot_username), Some("threads"));
+        assert_eq!(
+            command_name("/threads@helper_bot", bot_username),
+            Some("threads")
+        );
+        assert_eq!(
+            command_name("/threads@Helper_Bot", bot_username),
+            Some("threads")
+        );
+        assert_eq!(command_name("/threads@other_bot", bot_username), None);
+        assert_eq!(command_name("/threadsx", bot_username), Some("threadsx"));
+        assert_eq!(command_name("threads", bot_username), None);
+        assert_eq!(command_name("/", bot_username), None);
+        assert_eq!(command_name("/@helper_bot", bot_username), None);
+    }
+
+    #[test]
+    fn reads_the_argument_after_the_command() {
+        let bot_username = Some("helper_bot");
+        let argument = |text| parse_command(text, bot_username).map(|command| command.argument);
+
+        assert_eq!(argument("/ask"), Some(""));
+        assert_eq!(argument("/ask what's up?"), Some("what's up?"));
+        assert_eq!(argument("/ask@helper_bot what's up?"), Some("what's up?"));
+        assert_eq!(argument("/ask@helper_bot\nwhat's up?"), Some("what's up?"));
+        assert_eq!(argument("/ask@other_bot what's up?"), None);
+    }
+
+    #[test]
+    fn reads_the_bare_commands_without_a_bot_username() {
+        assert_eq!(command_name("/threads", None), Some("threads"));
+        assert_eq!(command_name("/threads@helper_bot", None), None);
+    }
+
+    #[test]
+    fn reads_the_command_argument() {
+        assert_eq!(command_argument("/retention 30", "/retention"), "30");
+        assert_eq!(
+            command_argument("/retention@helper_bot 30", "/retention"),
+            "30"
+        );
+        assert_eq!(command_argument("/retention@helper_bot", "/retention"), "");
+        assert_eq!(command_argument("/retention", "/retention"), "");
+    }
+
+    #[test]
+    fn strips_the_mentions_of_the_bot() {
+        assert_eq!(
+            strip_mention("@Helper_Bot what's up?", "helper_bot").as_deref(),
+            Some("what's up?")
+        );
+        assert_eq!(strip_mention("@helper_bots hi", "helper_bot"), None);
+        assert_eq!(strip_mention("hi", "helper_bot"), None);
+    }
+}
@@ -0,0 +1,264 @@
+use super::documents::download_document;
+use super::{current_chat_bot, RunningBotState};
+use crate::db::chat_thread;
+use crate::thread_import;
+use crate::thread_import::ImportedConversation;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::{
+    EditMessageBase, EditMessageTextRequest, InlineKeyboardButton, SendMessageRequest,
+};
+use mobot::*;
+use std::collections::BTreeSet;
+
+const IMPORT_PAGE_SIZE: usize = 8;
+
+// The conversations of the uploaded file, kept until the user picks the ones
+// to import. A new upload replaces them.
+#[derive(Clone, Default)]
+pub struct PendingImport {
+    conversations: Vec<ImportedConversation>,
+    selected: BTreeSet<usize>,
+}
+
+fn button_label(title: &str) -> String {
+    if title.chars().count() > 40 {
+        format!("{}…", title.chars().take(40).collect::<String>())
+    } else {
+        title.to_string()
+    }
+}
+
+fn import_page(pending: &PendingImport, page: usize) -> (String, api::ReplyMarkup) {
+    let pages_count = pending
+        .conversations
+        .len()
+        .div_ceil(IMPORT_PAGE_SIZE)
+        .max(1);
+    let page = page.min(pages_count - 1);
+
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = pending
+        .conversations
+        .iter()
+        .enumerate()
+        .skip(page * IMPORT_PAGE_SIZE)
+        .take(IMPORT_PAGE_SIZE)
+        .map(|(index, conversation)| {
+            vec![api::InlineKeyboardButton::from(format!(
+                "{} {} · {}",
+                if pending.selected.contains(&index) {
+                    "✅"
+                } else {
+                    "▫️"
+                },
+                button_label(&conversation.title),
+                conversation.messages.len()
+            ))
+            .with_callback_data(format!("import:toggle:{}", index))]
+        })
+        .collect();
+
+    let mut navigation: Vec<InlineKeyboardButton> = vec![];
+
+    if page > 0 {
+        navigation.push(
+            api::InlineKeyboardButton::from("« Previous")
+                .with_callback_data(format!("import:page:{}", page - 1)),
+        );
+    }
+
+    if page < pages_count - 1 {
+        navigation.push(
+            api::InlineKeyboardButton::from("Next »")
+                .with_callback_data(format!("import:page:{}", page + 1)),
+        );
+    }
+
+    if !navigation.is_empty() {
+        buttons.push(navigation);
+    }
+
+    buttons.push(vec![
+        api::InlineKeyboardButton::from("Select all")
+            .with_callback_data(format!("import:all:{}", page)),
+        api::InlineKeyboardButton::from("Clear")
+            .with_callback_data(format!("import:none:{}", page)),
+    ]);
+    buttons.push(vec![
+        api::InlineKeyboardButton::from(format!("Import {}", pending.selected.len()))
+            .with_callback_data("import:done:0"),
+        api::InlineKeyboardButton::from("Cancel").with_callback_data("import:cancel:0"),
+    ]);
+
+    (
+        format!(
+            "Found {} conversations, page {} of {}. Pick the ones to import:",
+            pending.conversations.len(),
+            page + 1,
+            pages_count
+        ),
+        api::ReplyMarkup::inline_keyboard_markup(buttons),
+    )
+}
+
+pub async fn import_document(
+    api: &API,
+    state: &RunningBotState,
+    chat_id: i64,
+    telegram_document: &api::Document,
+) -> Result<Action> {
+    if telegram_document.file_size.unwrap_or(0) > thread_import::MAX_IMPORT_SIZE {
+        return Ok(Action::ReplyText(format!(
+            "The file is too large. The maximum size is {} MB.",
+            thread_import::MAX_IMPORT_SIZE / 1024 / 1024
+        )));
+    }
+
+    let content = download_document(api, &telegram_document.file_id).await?;
+
+    let conversations = match thread_import::parse_conversations(&content) {
+        Ok(conversations) => conversations,
+        Err(e) => return Ok(Action::ReplyText(format!("Failed to import: {}", e))),
+    };
+
+    if conversations.is_empty() {
+        return Ok(Action::ReplyText(
+            "The file has no conversations with messages to import.".to_string(),
+        ));
+    }
+
+    let pending = PendingImport {
+        selected: (0..conversations.len()).collect(),
+        conversations,
+    };
+    let (text, reply_markup) = import_page(&pending, 0);
+
+    let mut pending_imports_write_lock = state.pending_imports.write().await;
+    pending_imports_write_lock.insert(chat_id, pending);
+    drop(pending_imports_write_lock);
+
+    api.send_message(&SendMessageRequest::new(chat_id, text).with_reply_markup(reply_markup))
+        .await?;
+
+    Ok(Action::Done)
+}
+
+// The callback payloads are "import:<action>:<value>", and the selection
+// message is edited in place.
+pub async fn handle_import_callback(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let telegram_message_id = e.update.message_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let (action, value) = data
+        .trim_start_matches("import:")
+        .split_once(':')
+        .and_then(|(a, v)| Some((a, v.parse::<usize>().ok()?)))
+        .ok_or_else(|| anyhow!("Invalid import callback data {}", data))?;
+
+    let mut pending_imports_write_lock = state.pending_imports.write().await;
+
+    let Some(pending) = pending_imports_write_lock.get_mut(&chat_id) else {
+        drop(pending_imports_write_lock);
+
+        e.acknowledge_callback(Some(
+            "The import has expired, send the file again".to_string(),
+        ))
+        .await?;
+        e.remove_inline_keyboard().await?;
+
+        return Ok(Action::Done);
+    };
+
+    let page = match action {
+        "page" => value,
+        "toggle" => {
+            if !pending.selected.remove(&value) && value < pending.conversations.len() {
+                pending.selected.insert(value);
+            }
+
+            value / IMPORT_PAGE_SIZE
+        }
+        "all" => {
+            pending.selected = (0..pending.conversations.len()).collect();
+            value
+        }
+        "none" => {
+            pending.selected.clear();
+            value
+        }
+        "cancel" => {
+            pending_imports_write_lock.remove(&chat_id);
+            drop(pending_imports_write_lock);
+
+            e.acknowledge_callback(None).await?;
+            e.remove_inline_keyboard().await?;
+
+            return Ok(Action::ReplyText("Cancelled the import.".to_string()));
+        }
+        "done" => {
+            if pending.selected.is_empty() {
+                drop(pending_imports_write_lock);
+
+                e.acknowledge_callback(Some("Select at least one conversation".to_string()))
+                    .await?;
+
+                return Ok(Action::Done);
+            }
+
+            let pending = pending_imports_write_lock
+                .remove(&chat_id)
+                .unwrap_or_default();
+            drop(pending_imports_write_lock);
+
+            e.acknowledge_callback(None).await?;
+            e.remove_inline_keyboard().await?;
+
+            let _turn = state.chat_queues.wait_turn(chat_id).await;
+
+            current_chat_bot(&db, &state, chat_id)
+                .await
+                .context("Failed to get or create chat bot")?;
+
+            let mut messages_count = 0;
+
+            for index in &pending.selected {
+                let conversation = &pending.conversations[*index];
+
+                chat_thread::import_chat_thread(&db, chat_id, conversation)
+                    .await
+                    .context(format!("Failed to import '{}'", conversation.title))?;
+
+                messages_count += conversation.messages.len();
+            }
+
+            return Ok(Action::ReplyText(format!(
+                "Imported {} conversations with {} messages. Use /threads to resume them.",
+                pending.selected.len(),
+                messages_count
+            )));
+        }
+        _ => return Err(anyhow!("Unknown import callback action {}", action)),
+    };
+
+    let (text, reply_markup) = import_page(pending, page);
+    drop(pending_imports_write_lock);
+
+    e.acknowledge_callback(None).await?;
+    e.api
+        .edit_message_text(&EditMessageTextRequest {
+            base: EditMessageBase::new()
+                .with_chat_id(chat_id)
+                .with_message_id(telegram_message_id)
+                .with_reply_markup(reply_markup),
+            text,
+        })
+        .await?;
+
+    Ok(Action::Done)
+}
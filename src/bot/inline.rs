@@ -0,0 +1,236 @@
+use super::{active_persona, chat_llm_settings, current_chat_bot, limits, RunningBotState};
+use crate::llm;
+use crate::llm::behavior_template;
+use crate::llm::behavior_template::BehaviorContext;
+use crate::llm::llm_thread_message::LLMThreadMessage;
+use crate::usage_limits;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::{AnswerInlineQuery, InlineQuery};
+use mobot::*;
+use sqlx::{Pool, Sqlite};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// Telegram sends a query per keystroke, so a query is only answered when no
+// newer one arrives from the same user in the meantime.
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(800);
+// The inline queries expire quickly, so a slower answer would be lost anyway.
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(8);
+const CACHE_TTL: Duration = Duration::from_secs(600);
+const MAX_CACHED_ANSWERS: usize = 1000;
+const MAX_TITLE_LENGTH: usize = 60;
+
+struct CachedAnswer {
+    answer: String,
+    cached_at: Instant,
+}
+
+// The answers of the recent queries of each user, and the latest query each
+// user typed.
+#[derive(Default)]
+pub struct InlineQueries {
+    answers: HashMap<(i64, String), CachedAnswer>,
+    latest_queries: HashMap<i64, u64>,
+    next_query_number: u64,
+}
+
+impl InlineQueries {
+    fn cached_answer(&self, user_id: i64, query: &str) -> Option<String> {
+        self.answers
+            .get(&(user_id, query.to_string()))
+            .filter(|cached| cached.cached_at.elapsed() < CACHE_TTL)
+            .map(|cached| cached.answer.clone())
+    }
+
+    fn cache_answer(&mut self, user_id: i64, query: &str, answer: &str) {
+        self.answers
+            .retain(|_, cached| cached.cached_at.elapsed() < CACHE_TTL);
+
+        if self.answers.len() >= MAX_CACHED_ANSWERS {
+            self.answers.clear();
+        }
+
+        self.answers.insert(
+            (user_id, query.to_string()),
+            CachedAnswer {
+                answer: answer.to_string(),
+                cached_at: Instant::now(),
+            },
+        );
+    }
+
+    fn register_query(&mut self, user_id: i64) -> u64 {
+        self.next_query_number += 1;
+        self.latest_queries.insert(user_id, self.next_query_number);
+
+        self.next_query_number
+    }
+
+    fn is_latest_query(&self, user_id: i64, query_number: u64) -> bool {
+        self.latest_queries.get(&user_id) == Some(&query_number)
+    }
+
+    pub fn forget_user(&mut self, user_id: i64) {
+        self.answers.retain(|(id, _), _| *id != user_id);
+        self.latest_queries.remove(&user_id);
+    }
+}
+
+fn result_title(query: &str) -> String {
+    if query.chars().count() > MAX_TITLE_LENGTH {
+        format!(
+            "{}…",
+            query.chars().take(MAX_TITLE_LENGTH).collect::<String>()
+        )
+    } else {
+        query.to_string()
+    }
+}
+
+// A single completion of the query with the persona and the model of the
+// user's private chat with the bot. Nothing is stored in the threads.
+async fn generate_inline_answer(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    inline_query: &InlineQuery,
+    query: &str,
+) -> Result<String> {
+    // The id of a private chat is the id of the user.
+    let chat_bot = current_chat_bot(db, state, inline_query.from.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let active_persona = active_persona(db, &chat_bot).await?;
+    let llm_settings = chat_llm_settings(&state.config, &chat_bot, active_persona.as_ref());
+
+    let mut behavior_context = BehaviorContext::new(&llm_settings.completion_model);
+    behavior_context.user_first_name = Some(inline_query.from.first_name.clone());
+    behavior_context.language = inline_query.from.language_code.clone();
+
+    let behavior = active_persona
+        .map(|p| p.system_prompt)
+        .unwrap_or(chat_bot.behavior);
+
+    let llm_api_client = llm::new_llm_service(
+        llm_settings.llm_service,
+        &llm_settings.completion_model,
+        state.config.groq_api_key.clone(),
+        llm_settings.generation_params,
+    )?;
+
+    let thread_messages = vec![
+        LLMThreadMessage {
+            message: behavior_template::render(&behavior, &behavior_context),
+            role: "system".to_string(),
+        },
+        LLMThreadMessage {
+            message: query.to_string(),
+            role: "user".to_string(),
+        },
+    ];
+
+    let completion = llm_api_client
+        .get_completion(thread_messages.clone())
+        .await?;
+
+    let used_tokens =
+        usage_limits::used_tokens(&thread_messages, &completion.content, completion.usage);
+    limits::record_tokens(db, state, Some(chat_bot.id), chat_bot.id, used_tokens).await?;
+
+    Ok(completion.content)
+}
+
+async fn answer_inline_query(
+    api: &API,
+    inline_query: &InlineQuery,
+    query: &str,
+    answer: Option<&str>,
+) -> Result<Action> {
+    let request = match answer {
+        Some(answer) => AnswerInlineQuery::new(inline_query.id.clone())
+            .with_article_text(result_title(query), answer),
+        None => AnswerInlineQuery::new(inline_query.id.clone()),
+    };
+
+    api.answer_inline_query(&AnswerInlineQuery {
+        cache_time: Some(0),
+        is_personal: Some(true),
+        ..request
+    })
+    .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_inline_query(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let Update::InlineQuery(inline_query) = &e.update else {
+        return Ok(Action::Done);
+    };
+
+    let query = inline_query.query.trim();
+    let user_id = inline_query.from.id;
+
+    if query.is_empty() {
+        return Ok(Action::Done);
+    }
+
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let query_number = {
+        let mut inline_queries = state.inline_queries.write().await;
+
+        if let Some(answer) = inline_queries.cached_answer(user_id, query) {
+            drop(inline_queries);
+            return answer_inline_query(&e.api, inline_query, query, Some(&answer)).await;
+        }
+
+        inline_queries.register_query(user_id)
+    };
+
+    tokio::time::sleep(DEBOUNCE_DELAY).await;
+
+    if !state
+        .inline_queries
+        .read()
+        .await
+        .is_latest_query(user_id, query_number)
+    {
+        return Ok(Action::Done);
+    }
+
+    // The inline results can't carry a notice, so a limited user gets none.
+    if let Some(reply) = limits::check_limits(&db, &state, Some(user_id), user_id).await? {
+        println!("The inline query of {} is limited: {}", user_id, reply);
+        return answer_inline_query(&e.api, inline_query, query, None).await;
+    }
+
+    match tokio::time::timeout(
+        ANSWER_TIMEOUT,
+        generate_inline_answer(&db, &state, inline_query, query),
+    )
+    .await
+    {
+        Ok(Ok(answer)) => {
+            state
+                .inline_queries
+                .write()
+                .await
+                .cache_answer(user_id, query, &answer);
+
+            answer_inline_query(&e.api, inline_query, query, Some(&answer)).await
+        }
+        Ok(Err(err)) => {
+            println!("Failed to answer the inline query: {:?}", err);
+            answer_inline_query(&e.api, inline_query, query, None).await
+        }
+        Err(_) => {
+            println!("The inline query '{}' timed out", query);
+            answer_inline_query(&e.api, inline_query, query, None).await
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use super::access::is_admin;
+use super::{groups, RunningBotState};
+use crate::config::{Config, Limits};
+use crate::db::usage::{SUBJECT_CHAT, SUBJECT_USER};
+use crate::db::user;
+use crate::db::user::UserLimit;
+use crate::usage_limits;
+use crate::usage_limits::{LimitKind, Limited, Subject};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use mobot::*;
+use sqlx::{Pool, Sqlite};
+
+// An override of 0 lifts the limit, and no override keeps the bot-wide one.
+fn overridden_limit(default: Option<u32>, value: Option<i64>) -> Option<u32> {
+    match value {
+        None => default,
+        Some(0) => None,
+        Some(value) => u32::try_from(value).ok(),
+    }
+}
+
+fn effective_user_limits(config: &Config, user: Option<&user::User>) -> Limits {
+    let Some(user) = user else {
+        return config.user_limits;
+    };
+
+    Limits {
+        messages_per_minute: overridden_limit(
+            config.user_limits.messages_per_minute,
+            user.messages_per_minute,
+        ),
+        daily_messages: overridden_limit(config.user_limits.daily_messages, user.daily_messages),
+        daily_tokens: overridden_limit(config.user_limits.daily_tokens, user.daily_tokens),
+    }
+}
+
+// The user and the chat the message is counted against. The admins' messages
+// are counted, but not limited.
+pub(super) async fn limit_subjects(
+    db: &Pool<Sqlite>,
+    config: &Config,
+    user_id: Option<i64>,
+    chat_id: i64,
+) -> Result<Vec<Subject>> {
+    let exempt = user_id.is_some_and(|id| is_admin(config, id));
+
+    let chat = Subject {
+        kind: SUBJECT_CHAT,
+        id: chat_id,
+        limits: if exempt {
+            Limits::default()
+        } else {
+            config.chat_limits
+        },
+    };
+
+    let Some(user_id) = user_id else {
+        return Ok(vec![chat]);
+    };
+
+    let limits = if exempt {
+        Limits::default()
+    } else {
+        let user = user::get_user(db, user_id).await?;
+        effective_user_limits(config, user.as_ref())
+    };
+
+    Ok(vec![
+        Subject {
+            kind: SUBJECT_USER,
+            id: user_id,
+            limits,
+        },
+        chat,
+    ])
+}
+
+fn format_wait(now: DateTime<Utc>, retry_at: DateTime<Utc>) -> String {
+    let seconds = (retry_at - now).num_seconds().max(1);
+
+    match seconds {
+        1 => "1 second".to_string(),
+        2..=59 => format!("{} seconds", seconds),
+        60..=3599 => format!("{} min", (seconds + 59) / 60),
+        _ => format!("{} h {} min", seconds / 3600, (seconds % 3600) / 60),
+    }
+}
+
+fn limited_reply(limited: &Limited, now: DateTime<Utc>) -> String {
+    let who = if limited.subject == SUBJECT_CHAT {
+        "This chat has"
+    } else {
+        "You've"
+    };
+
+    let limit = match limited.kind {
+        LimitKind::MessagesPerMinute => "reached the limit of messages per minute",
+        LimitKind::DailyMessages => "used up today's message quota",
+        LimitKind::DailyTokens => "used up today's token quota",
+    };
+
+    format!(
+        "{} {}. Please, try again in {} (at {} UTC).",
+        who,
+        limit,
+        format_wait(now, limited.retry_at),
+        limited.retry_at.format("%H:%M:%S")
+    )
+}
+
+// Counts a message to be answered by the model. Returns the reply to send
+// instead when the user or the chat is over a limit.
+pub async fn check_limits(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    user_id: Option<i64>,
+    chat_id: i64,
+) -> Result<Option<String>> {
+    let subjects = limit_subjects(db, &state.config, user_id, chat_id).await?;
+    let now = Utc::now();
+
+    let limited = usage_limits::consume_message(db, &subjects, now)
+        .await
+        .context("Failed to check the limits")?;
+
+    Ok(limited.map(|limited| limited_reply(&limited, now)))
+}
+
+pub async fn record_tokens(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    user_id: Option<i64>,
+    chat_id: i64,
+    tokens: i64,
+) -> Result<()> {
+    let subjects = limit_subjects(db, &state.config, user_id, chat_id).await?;
+
+    usage_limits::record_tokens(db, &subjects, Utc::now(), tokens)
+        .await
+        .context("Failed to record the used tokens")
+}
+
+fn remaining(used: i64, limit: Option<u32>) -> String {
+    match limit {
+        Some(limit) => format!("{} of {} left", (i64::from(limit) - used).max(0), limit),
+        None => format!("{} used, unlimited", used),
+    }
+}
+
+async fn allowance_lines(db: &Pool<Sqlite>, subject: &Subject) -> Result<String> {
+    let allowance = usage_limits::allowance(db, subject, Utc::now()).await?;
+
+    let per_minute = match (
+        allowance.available_messages,
+        subject.limits.messages_per_minute,
+    ) {
+        (Some(available), Some(limit)) => format!("{} of {} available", available, limit),
+        _ => "unlimited".to_string(),
+    };
+
+    Ok(format!(
+        "- Messages per minute: {}\n- Messages today: {}\n- Tokens today: {}",
+        per_minute,
+        remaining(allowance.usage.messages, subject.limits.daily_messages),
+        remaining(allowance.usage.tokens, subject.limits.daily_tokens)
+    ))
+}
+
+// The user's allowance as listed by /quota, for the admins' /user.
+pub async fn user_allowance_lines(
+    db: &Pool<Sqlite>,
+    config: &Config,
+    user_id: i64,
+) -> Result<String> {
+    let subjects = limit_subjects(db, config, Some(user_id), user_id).await?;
+
+    allowance_lines(db, &subjects[0]).await
+}
+
+pub async fn handle_quota(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let user_id = message.from.as_ref().map(|u| u.id);
+    let subjects = limit_subjects(&db, &state.config, user_id, message.chat.id).await?;
+
+    let mut sections = vec![];
+
+    for subject in &subjects {
+        // In a private chat, the chat's allowance only matters when the chat
+        // limits are set.
+        if subject.kind == SUBJECT_CHAT
+            && user_id == Some(message.chat.id)
+            && subject.limits == Limits::default()
+        {
+            continue;
+        }
+
+        let title = if subject.kind == SUBJECT_USER {
+            "Your allowance"
+        } else {
+            "This chat's allowance"
+        };
+
+        sections.push(format!(
+            "{}:\n{}",
+            title,
+            allowance_lines(&db, subject).await?
+        ));
+    }
+
+    if user_id.is_some_and(|id| is_admin(&state.config, id)) {
+        sections.push("The limits don't apply to the admins.".to_string());
+    }
+
+    sections.push("The daily quotas are reset at midnight UTC.".to_string());
+
+    Ok(Action::ReplyText(sections.join("\n\n")))
+}
+
+fn limit_usage() -> String {
+    "Usage: /limit <user id> <rate|messages|tokens> <number|off|default>, e.g. /limit 123456 messages 50. The rate is the messages per minute, off lifts the limit and default restores the bot-wide one.".to_string()
+}
+
+fn describe_limits(limits: &Limits) -> String {
+    let limit = |value: Option<u32>| {
+        value
+            .map(|v| v.to_string())
+            .unwrap_or_else(|| "unlimited".to_string())
+    };
+
+    format!(
+        "{} messages per minute, {} messages and {} tokens per day",
+        limit(limits.messages_per_minute),
+        limit(limits.daily_messages),
+        limit(limits.daily_tokens)
+    )
+}
+
+// /limit <user id> shows the user's limits, and /limit <user id> <limit>
+// <value> overrides one of them.
+pub async fn handle_limit(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    if !message
+        .from
+        .as_ref()
+        .is_some_and(|u| is_admin(&state.config, u.id))
+    {
+        return Ok(Action::ReplyText(
+            "Only the admins can change the limits.".to_string(),
+        ));
+    }
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let arguments: Vec<&str> = groups::command_argument(&message_content, "/limit")
+        .split_whitespace()
+        .collect();
+
+    let Some(user_id) = arguments.first().and_then(|id| id.parse::<i64>().ok()) else {
+        return Ok(Action::ReplyText(limit_usage()));
+    };
+
+    let new_value = match arguments[1..] {
+        [] => None,
+        [limit, value] => {
+            let limit = match limit {
+                "rate" => UserLimit::MessagesPerMinute,
+                "messages" => UserLimit::DailyMessages,
+                "tokens" => UserLimit::DailyTokens,
+                _ => return Ok(Action::ReplyText(limit_usage())),
+            };
+
+            let value = match value {
+                "default" => None,
+                "off" => Some(0),
+                value => match value.parse::<u32>() {
+                    Ok(value) if value > 0 => Some(i64::from(value)),
+                    _ => return Ok(Action::ReplyText(limit_usage())),
+                },
+            };
+
+            Some((limit, value))
+        }
+        _ => return Ok(Action::ReplyText(limit_usage())),
+    };
+
+    let user = match new_value {
+        Some((limit, value)) => user::set_user_limit(&db, user_id, limit, value).await?,
+        None => user::get_user(&db, user_id).await?,
+    };
+
+    let Some(user) = user else {
+        return Ok(Action::ReplyText(format!(
+            "The user {} hasn't contacted the bot yet.",
+            user_id
+        )));
+    };
+
+    let limits = describe_limits(&effective_user_limits(&state.config, Some(&user)));
+
+    Ok(Action::ReplyText(if is_admin(&state.config, user.id) {
+        format!(
+            "The user {} is an admin, so the limits don't apply. Otherwise: {}.",
+            user.id, limits
+        )
+    } else {
+        format!("The user {} is limited to {}.", user.id, limits)
+    }))
+}
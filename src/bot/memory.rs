@@ -0,0 +1,156 @@
+use super::{current_chat_bot, groups, RunningBotState};
+use crate::db::memory;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::{InlineKeyboardButton, SendMessageRequest};
+use mobot::*;
+
+fn button_label(content: &str) -> String {
+    if content.chars().count() > 40 {
+        format!("{}…", content.chars().take(40).collect::<String>())
+    } else {
+        content.to_string()
+    }
+}
+
+pub async fn handle_remember(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let fact = groups::command_argument(&message_content, "/remember");
+
+    if fact.is_empty() {
+        return Ok(Action::ReplyText(
+            "Please, specify what to remember. Example: /remember I'm vegetarian".to_string(),
+        ));
+    }
+
+    if fact.chars().count() > crate::memory::MAX_MEMORY_LENGTH {
+        return Ok(Action::ReplyText(format!(
+            "The memory is too long. The maximum length is {} characters.",
+            crate::memory::MAX_MEMORY_LENGTH
+        )));
+    }
+
+    current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    match memory::insert_memory(&db, message.chat.id, fact, "explicit").await? {
+        Some(_) => Ok(Action::ReplyText(format!("I'll remember: '{}'", fact))),
+        None => Ok(Action::ReplyText(format!("I already remember: '{}'", fact))),
+    }
+}
+
+pub async fn handle_memories(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let chat_id = e.update.chat_id()?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let memories = memory::get_chat_memories(&db, chat_id).await?;
+
+    if memories.is_empty() {
+        return Ok(Action::ReplyText(
+            "I don't remember anything yet. Use /remember <fact> to add a memory.".to_string(),
+        ));
+    }
+
+    let lines: Vec<String> = memories
+        .iter()
+        .map(|m| match m.source.as_str() {
+            "auto" => format!("- {} (learned)", m.content),
+            _ => format!("- {}", m.content),
+        })
+        .collect();
+
+    Ok(Action::ReplyText(format!(
+        "What I remember:\n{}\n\nUse /forget to remove memories.",
+        lines.join("\n")
+    )))
+}
+
+pub async fn handle_forget(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let chat_id = e.update.chat_id()?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let memories = memory::get_chat_memories(&db, chat_id).await?;
+
+    if memories.is_empty() {
+        return Ok(Action::ReplyText(
+            "I don't remember anything yet.".to_string(),
+        ));
+    }
+
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = memories
+        .iter()
+        .map(|m| {
+            vec![api::InlineKeyboardButton::from(button_label(&m.content))
+                .with_callback_data(format!("memory_delete:{}", m.id))]
+        })
+        .collect();
+
+    buttons.push(vec![api::InlineKeyboardButton::from("Forget everything")
+        .with_callback_data("memory_delete:all")]);
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(chat_id, "Choose the memory to forget:")
+                .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(buttons)),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_memory_delete_callback(
+    e: Event,
+    state: State<RunningBotState>,
+) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    e.acknowledge_callback(None).await?;
+    e.remove_inline_keyboard().await?;
+
+    let memory_id = data.trim_start_matches("memory_delete:");
+
+    if memory_id == "all" {
+        let deleted_count = memory::delete_chat_memories(&db, chat_id).await?;
+
+        return Ok(Action::ReplyText(format!(
+            "Forgot {} memories.",
+            deleted_count
+        )));
+    }
+
+    let memory_id = memory_id
+        .parse::<i64>()
+        .context(format!("Invalid memory callback data {}", data))?;
+
+    match memory::delete_memory(&db, chat_id, memory_id).await? {
+        None => Ok(Action::ReplyText(
+            "The memory doesn't exist anymore.".to_string(),
+        )),
+        Some(memory) => Ok(Action::ReplyText(format!("Forgot: '{}'", memory.content))),
+    }
+}
@@ -0,0 +1,454 @@
+use super::{current_chat_bot, groups, RunningBotState, UserChatState};
+use crate::db::chat_bot;
+use crate::db::persona;
+use crate::db::persona::NewPersona;
+use crate::llm;
+use crate::llm::behavior_template;
+use crate::llm::LLMServiceKind;
+use anyhow::{anyhow, Context, Result};
+use mobot::*;
+use mobot::{api::InlineKeyboardButton, api::SendMessageRequest};
+use sqlx::{Pool, Sqlite};
+
+fn parse_new_persona(
+    args: &str,
+    default_llm_service: LLMServiceKind,
+) -> Result<NewPersona, String> {
+    let mut tokens = args.split_whitespace();
+
+    let name = tokens
+        .next()
+        .ok_or("Please, specify the persona name. Example: /persona_add translator provider=groq model=llama3-8b-8192 temperature=0.2 max_tokens=512")?;
+
+    let mut new_persona = NewPersona {
+        name: name.to_string(),
+        ..Default::default()
+    };
+
+    for token in tokens {
+        let (key, value) = token
+            .split_once('=')
+            .ok_or(format!("Invalid option '{}'. Use key=value.", token))?;
+
+        match key {
+            "provider" => {
+                let llm_service = value
+                    .parse::<LLMServiceKind>()
+                    .map_err(|_| format!("Unknown provider '{}'", value))?;
+
+                new_persona.llm_service = Some(llm_service.as_str().to_string());
+            }
+            "model" => new_persona.completion_model = Some(value.to_string()),
+            "temperature" => {
+                let temperature = value
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|t| (0.0..=2.0).contains(t))
+                    .ok_or(format!("Invalid temperature '{}'. Use 0..2.", value))?;
+
+                new_persona.temperature = Some(temperature);
+            }
+            "max_tokens" => {
+                let max_tokens = value
+                    .parse::<u16>()
+                    .ok()
+                    .filter(|t| *t > 0)
+                    .ok_or(format!("Invalid max_tokens '{}'", value))?;
+
+                new_persona.max_tokens = Some(max_tokens.into());
+            }
+            _ => return Err(format!("Unknown option '{}'", key)),
+        }
+    }
+
+    if let Some(completion_model) = &new_persona.completion_model {
+        let llm_service = new_persona
+            .llm_service
+            .as_ref()
+            .and_then(|s| s.parse::<LLMServiceKind>().ok())
+            .unwrap_or(default_llm_service);
+
+        if !llm::is_valid_completion_model(llm_service, completion_model) {
+            return Err(format!(
+                "Unknown {} completion model '{}'",
+                llm_service, completion_model
+            ));
+        }
+    }
+
+    Ok(new_persona)
+}
+
+fn persona_description(persona: &persona::Persona) -> String {
+    let mut options: Vec<String> = vec![];
+
+    if let Some(llm_service) = &persona.llm_service {
+        options.push(format!("provider={}", llm_service));
+    }
+    if let Some(completion_model) = &persona.completion_model {
+        options.push(format!("model={}", completion_model));
+    }
+    if let Some(temperature) = persona.temperature {
+        options.push(format!("temperature={}", temperature));
+    }
+    if let Some(max_tokens) = persona.max_tokens {
+        options.push(format!("max_tokens={}", max_tokens));
+    }
+
+    if options.is_empty() {
+        persona.name.clone()
+    } else {
+        format!("{} ({})", persona.name, options.join(", "))
+    }
+}
+
+pub async fn handle_persona_add(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let message_content = message.text.clone().unwrap_or_default();
+    let args = groups::command_argument(&message_content, "/persona_add").to_string();
+
+    match parse_new_persona(&args, state.config.llm_service) {
+        Err(error) => Ok(Action::ReplyText(error)),
+        Ok(new_persona) => {
+            let name = new_persona.name.clone();
+            let mut user_chat_state_write_lock = state.user_chat_state.write().await;
+            user_chat_state_write_lock.insert(
+                message.chat.id,
+                UserChatState::WaitingPersonaPrompt(new_persona),
+            );
+
+            Ok(Action::ReplyText(format!(
+                "Please enter the system prompt for the persona '{}' in the next message.",
+                name
+            )))
+        }
+    }
+}
+
+pub async fn save_persona_prompt(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_id: i64,
+    new_persona: NewPersona,
+    system_prompt: &str,
+) -> Result<Action> {
+    let unknown_variables = behavior_template::unknown_variables(system_prompt);
+
+    if !unknown_variables.is_empty() {
+        return Ok(Action::ReplyText(format!(
+            "Unknown template variables: {}. Available variables: {}. Please enter the system prompt again.",
+            unknown_variables
+                .iter()
+                .map(|v| format!("{{{}}}", v))
+                .collect::<Vec<String>>()
+                .join(", "),
+            super::available_template_variables()
+        )));
+    }
+
+    let persona = persona::insert_persona(
+        db,
+        chat_id,
+        &NewPersona {
+            system_prompt: system_prompt.to_string(),
+            ..new_persona
+        },
+    )
+    .await?;
+
+    chat_bot::set_chat_bot_persona(db, chat_id, Some(persona.id)).await?;
+
+    let mut user_chat_state_write_lock = state.user_chat_state.write().await;
+    user_chat_state_write_lock.insert(chat_id, UserChatState::Default);
+
+    Ok(Action::ReplyText(format!(
+        "Saved and activated the persona {}. Use /persona to switch between personas.",
+        persona_description(&persona)
+    )))
+}
+
+pub async fn handle_persona_list(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+    let personas = persona::get_chat_personas(&db, message.chat.id).await?;
+
+    if personas.is_empty() {
+        return Ok(Action::ReplyText(
+            "There are no saved personas. Use /persona_add <name> to create one.".to_string(),
+        ));
+    }
+
+    let lines: Vec<String> = personas
+        .iter()
+        .map(|p| {
+            let marker = if chat_bot.persona_id == Some(p.id) {
+                "* "
+            } else {
+                "- "
+            };
+
+            format!("{}{}", marker, persona_description(p))
+        })
+        .collect();
+
+    Ok(Action::ReplyText(format!(
+        "Saved personas:\n{}",
+        lines.join("\n")
+    )))
+}
+
+fn persona_buttons(
+    personas: &[persona::Persona],
+    callback_prefix: &str,
+) -> Vec<Vec<InlineKeyboardButton>> {
+    let buttons: Vec<InlineKeyboardButton> = personas
+        .iter()
+        .map(|p| {
+            api::InlineKeyboardButton::from(p.name.as_str())
+                .with_callback_data(format!("{}{}", callback_prefix, p.id))
+        })
+        .collect();
+
+    buttons.chunks(2).map(|chunk| chunk.to_vec()).collect()
+}
+
+pub async fn handle_persona_switch(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let chat_id = e.update.chat_id()?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let personas = persona::get_chat_personas(&db, chat_id).await?;
+
+    let mut buttons = persona_buttons(&personas, "persona:");
+    buttons.push(vec![
+        api::InlineKeyboardButton::from("Default behavior").with_callback_data("persona:none")
+    ]);
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(chat_id, "Choose the persona:")
+                .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(buttons)),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_persona_delete(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let chat_id = e.update.chat_id()?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let personas = persona::get_chat_personas(&db, chat_id).await?;
+
+    if personas.is_empty() {
+        return Ok(Action::ReplyText(
+            "There are no saved personas.".to_string(),
+        ));
+    }
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(chat_id, "Choose the persona to delete:").with_reply_markup(
+                api::ReplyMarkup::inline_keyboard_markup(persona_buttons(
+                    &personas,
+                    "persona_delete:",
+                )),
+            ),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_persona_callback(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    current_chat_bot(&db, &state, chat_id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    e.acknowledge_callback(None).await?;
+    e.remove_inline_keyboard().await?;
+
+    let persona_id = data.trim_start_matches("persona:");
+
+    if persona_id == "none" {
+        chat_bot::set_chat_bot_persona(&db, chat_id, None).await?;
+
+        return Ok(Action::ReplyText(
+            "Switched to the default behavior. Use /get_behavior to see it.".to_string(),
+        ));
+    }
+
+    let persona_id = persona_id
+        .parse::<i64>()
+        .context(format!("Invalid persona callback data {}", data))?;
+
+    match persona::get_persona(&db, chat_id, persona_id).await? {
+        None => Ok(Action::ReplyText(
+            "The persona doesn't exist anymore.".to_string(),
+        )),
+        Some(persona) => {
+            chat_bot::set_chat_bot_persona(&db, chat_id, Some(persona.id)).await?;
+
+            Ok(Action::ReplyText(format!(
+                "Switched to the persona {}",
+                persona_description(&persona)
+            )))
+        }
+    }
+}
+
+pub async fn handle_persona_delete_callback(
+    e: Event,
+    state: State<RunningBotState>,
+) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    e.acknowledge_callback(None).await?;
+    e.remove_inline_keyboard().await?;
+
+    let persona_id = data
+        .trim_start_matches("persona_delete:")
+        .parse::<i64>()
+        .context(format!("Invalid persona callback data {}", data))?;
+
+    match persona::delete_persona(&db, chat_id, persona_id).await? {
+        None => Ok(Action::ReplyText(
+            "The persona doesn't exist anymore.".to_string(),
+        )),
+        Some(persona) => Ok(Action::ReplyText(format!(
+            "Deleted the persona {}",
+            persona.name
+        ))),
+    }
+}
+
+pub async fn handle_catalog(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let chat_id = e.update.chat_id()?;
+    let state = state.get().read().await;
+    let catalog = &state.config.persona_catalog;
+
+    if catalog.personas.is_empty() {
+        return Ok(Action::ReplyText(
+            "The persona catalog is empty.".to_string(),
+        ));
+    }
+
+    let lines: Vec<String> = catalog
+        .personas
+        .iter()
+        .map(|p| match &p.description {
+            Some(description) => format!("- {}: {}", p.name, description),
+            None => format!("- {}", p.name),
+        })
+        .collect();
+
+    let buttons: Vec<InlineKeyboardButton> = catalog
+        .personas
+        .iter()
+        .map(|p| {
+            api::InlineKeyboardButton::from(p.name.as_str())
+                .with_callback_data(format!("catalog:{}", p.name))
+        })
+        .collect();
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(
+                chat_id,
+                format!("Choose a persona to adopt:\n{}", lines.join("\n")),
+            )
+            .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(
+                buttons.chunks(2).map(|chunk| chunk.to_vec()).collect(),
+            )),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_catalog_callback(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    e.acknowledge_callback(None).await?;
+    e.remove_inline_keyboard().await?;
+
+    let name = data.trim_start_matches("catalog:");
+
+    let catalog_persona = match state.config.persona_catalog.get(name) {
+        Some(catalog_persona) => catalog_persona,
+        None => {
+            return Ok(Action::ReplyText(format!(
+                "The persona '{}' is not in the catalog anymore.",
+                name
+            )))
+        }
+    };
+
+    current_chat_bot(&db, &state, chat_id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let new_persona = NewPersona {
+        name: catalog_persona.name.clone(),
+        system_prompt: catalog_persona.system_prompt.clone(),
+        llm_service: catalog_persona.llm_service.clone(),
+        completion_model: catalog_persona.completion_model.clone(),
+        temperature: catalog_persona.temperature,
+        max_tokens: catalog_persona.max_tokens.map(i64::from),
+        examples: if catalog_persona.examples.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&catalog_persona.examples)?)
+        },
+    };
+
+    let persona = persona::insert_persona(&db, chat_id, &new_persona).await?;
+    chat_bot::set_chat_bot_persona(&db, chat_id, Some(persona.id)).await?;
+
+    Ok(Action::ReplyText(format!(
+        "Adopted and activated the persona {}. Use /persona to switch between personas.",
+        persona_description(&persona)
+    )))
+}
@@ -0,0 +1,169 @@
+use super::{current_chat_bot, groups, RunningBotState};
+use crate::db::chat_bot;
+use crate::db::chat_data;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::SendMessageRequest;
+use mobot::*;
+
+fn retention_description(
+    chat_bot: &chat_bot::ChatBot,
+    default_retention_days: Option<u32>,
+) -> String {
+    match chat_bot.retention_days {
+        Some(0) => "The messages of this chat are kept forever.".to_string(),
+        Some(days) => format!("The messages of this chat are deleted after {} days.", days),
+        None => match default_retention_days {
+            Some(days) => format!(
+                "The messages of this chat are deleted after {} days, the bot's default.",
+                days
+            ),
+            None => "The messages of this chat are kept forever, the bot's default.".to_string(),
+        },
+    }
+}
+
+pub async fn handle_retention(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let argument = groups::command_argument(&message_content, "/retention");
+
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let retention_days = match argument {
+        "" => {
+            return Ok(Action::ReplyText(format!(
+                "{}\n\nUse /retention <days>, /retention off to keep them forever, or /retention default.",
+                retention_description(&chat_bot, state.config.retention_days)
+            )))
+        }
+        "off" => Some(0),
+        "default" => None,
+        days => match days.parse::<u32>() {
+            Ok(days) if days > 0 => Some(i64::from(days)),
+            _ => {
+                return Ok(Action::ReplyText(
+                    "Please, specify a positive number of days, off or default. Example: /retention 30"
+                        .to_string(),
+                ))
+            }
+        },
+    };
+
+    let chat_bot = chat_bot::set_chat_bot_retention_days(&db, chat_bot.id, retention_days)
+        .await
+        .context("Failed to set the retention")?;
+
+    Ok(Action::ReplyText(retention_description(
+        &chat_bot,
+        state.config.retention_days,
+    )))
+}
+
+// In a group, only its administrators can delete the group's data.
+async fn may_forget_chat(e: &Event, chat: &api::Chat) -> Result<bool> {
+    if !groups::is_group_chat(chat) {
+        return Ok(true);
+    }
+
+    let user_id = e.update.from_user()?.id;
+
+    groups::is_chat_administrator(&e.api, chat.id, user_id).await
+}
+
+pub async fn handle_forget_me(e: Event, _state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let chat_id = message.chat.id;
+
+    if !may_forget_chat(&e, &message.chat).await? {
+        return Ok(Action::ReplyText(
+            "Only the administrators of the group can delete its data.".to_string(),
+        ));
+    }
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(
+                chat_id,
+                "This deletes everything the bot stores for this chat: the settings, threads, messages, personas, documents, memories, and model comparisons. Your usage, limits and access are kept. It can't be undone.",
+            )
+            .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(vec![vec![
+                api::InlineKeyboardButton::from("Delete everything")
+                    .with_callback_data("forget_me:confirm"),
+                api::InlineKeyboardButton::from("Cancel").with_callback_data("forget_me:cancel"),
+            ]])),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+pub async fn handle_forget_me_callback(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let Update::CallbackQuery(query) = &e.update else {
+        return Ok(Action::Done);
+    };
+
+    // Anyone in a group can press the buttons.
+    if let Some(message) = &query.message {
+        if !may_forget_chat(&e, &message.chat).await? {
+            e.acknowledge_callback(Some(
+                "Only the administrators of the group can delete its data.".to_string(),
+            ))
+            .await?;
+
+            return Ok(Action::Done);
+        }
+    }
+
+    e.acknowledge_callback(None).await?;
+    e.remove_inline_keyboard().await?;
+
+    if data != "forget_me:confirm" {
+        return Ok(Action::ReplyText("Nothing was deleted.".to_string()));
+    }
+
+    // The id of a private chat is the id of the user.
+    let user_id = (query.from.id == chat_id).then_some(chat_id);
+
+    let deleted = chat_data::delete_chat_data(&db, chat_id)
+        .await
+        .context("Failed to delete the chat data")?;
+
+    // The pending inputs, imports and inline answers would otherwise outlive
+    // the chat's data.
+    state.user_chat_state.write().await.remove(&chat_id);
+    state.pending_imports.write().await.remove(&chat_id);
+
+    if let Some(user_id) = user_id {
+        state.inline_queries.write().await.forget_user(user_id);
+    }
+
+    Ok(Action::ReplyText(format!(
+        "Deleted everything stored for this chat:\n- {} chat settings\n- {} threads\n- {} messages\n- {} personas\n- {} documents ({} chunks)\n- {} memories\n- {} model comparisons",
+        deleted.chat_bots,
+        deleted.chat_threads,
+        deleted.chat_messages,
+        deleted.personas,
+        deleted.documents,
+        deleted.document_chunks,
+        deleted.memories,
+        deleted.model_comparisons
+    )))
+}
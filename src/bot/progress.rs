@@ -0,0 +1,91 @@
+use mobot::api::{ChatAction, DeleteMessageRequest, SendChatActionRequest, SendMessageRequest};
+use mobot::*;
+use std::future::Future;
+use std::time::Duration;
+
+// Telegram shows a chat action for 5 seconds, or until a message is sent.
+const ACTION_REFRESH: Duration = Duration::from_secs(4);
+const STATUS_DELAY: Duration = Duration::from_secs(10);
+
+pub struct Progress {
+    action: ChatAction,
+    status: &'static str,
+}
+
+pub const THINKING: Progress = Progress {
+    action: ChatAction::Typing,
+    status: "Still thinking…",
+};
+
+pub const UPLOADING: Progress = Progress {
+    action: ChatAction::UploadDocument,
+    status: "Still uploading…",
+};
+
+pub const READING: Progress = Progress {
+    action: ChatAction::Typing,
+    status: "Still reading the document…",
+};
+
+// Repeats the chat action until dropped, and posts the status message once
+// the delay is over.
+async fn indicate(
+    api: &API,
+    chat_id: i64,
+    progress: &Progress,
+    status_message_id: &mut Option<i64>,
+) {
+    let mut elapsed = Duration::ZERO;
+
+    loop {
+        if let Err(e) = api
+            .send_chat_action(&SendChatActionRequest::new(
+                chat_id,
+                progress.action.clone(),
+            ))
+            .await
+        {
+            println!("Failed to send the chat action: {:?}", e);
+        }
+
+        if elapsed >= STATUS_DELAY && status_message_id.is_none() {
+            match api
+                .send_message(&SendMessageRequest::new(chat_id, progress.status))
+                .await
+            {
+                Ok(message) => *status_message_id = Some(message.message_id),
+                Err(e) => println!("Failed to send the status message: {:?}", e),
+            }
+        }
+
+        tokio::time::sleep(ACTION_REFRESH).await;
+        elapsed += ACTION_REFRESH;
+    }
+}
+
+// Runs the work while the chat shows the progress, and removes the status
+// message posted for the long ones.
+pub async fn with_progress<T>(
+    api: &API,
+    chat_id: i64,
+    progress: &Progress,
+    work: impl Future<Output = T>,
+) -> T {
+    let mut status_message_id = None;
+
+    let output = tokio::select! {
+        output = work => output,
+        _ = indicate(api, chat_id, progress, &mut status_message_id) => unreachable!(),
+    };
+
+    if let Some(message_id) = status_message_id {
+        if let Err(e) = api
+            .delete_message(&DeleteMessageRequest::new(chat_id, message_id))
+            .await
+        {
+            println!("Failed to delete the status message: {:?}", e);
+        }
+    }
+
+    output
+}
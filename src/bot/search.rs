@@ -0,0 +1,179 @@
+use super::{current_chat_bot, groups, RunningBotState};
+use crate::db::chat_message;
+use crate::db::chat_message::{ChatMessageSearchResult, SEARCH_MATCH_END, SEARCH_MATCH_START};
+use crate::db::chat_thread;
+use crate::encryption;
+use crate::telegram_html;
+use crate::thread_title;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::{InlineKeyboardButton, ParseMode, SendMessageRequest};
+use mobot::*;
+
+const MAX_SEARCH_RESULTS: i64 = 10;
+
+fn result_title(result: &ChatMessageSearchResult) -> String {
+    result
+        .thread_title
+        .clone()
+        .unwrap_or_else(|| thread_title::fallback_title(result.thread_first_message.as_deref()))
+}
+
+fn button_label(title: &str) -> String {
+    if title.chars().count() > 40 {
+        format!("{}…", title.chars().take(40).collect::<String>())
+    } else {
+        title.to_string()
+    }
+}
+
+// The snippet is escaped first, and then its match markers become bold tags.
+fn highlighted_snippet(snippet: &str) -> String {
+    telegram_html::escape(&snippet.replace('\n', " "))
+        .replace(SEARCH_MATCH_START, "<b>")
+        .replace(SEARCH_MATCH_END, "</b>")
+}
+
+pub async fn handle_search(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    // The encrypted messages can't be indexed.
+    if encryption::is_enabled() {
+        return Ok(Action::ReplyText(
+            "The search isn't available, since the messages are stored encrypted.".to_string(),
+        ));
+    }
+
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let chat_id = message.chat.id;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let query = groups::command_argument(&message_content, "/search");
+
+    if query.is_empty() {
+        return Ok(Action::ReplyText(
+            "Please, specify what to search for. Example: /search sourdough recipe".to_string(),
+        ));
+    }
+
+    let results =
+        chat_message::search_chat_messages(&db, chat_id, query, MAX_SEARCH_RESULTS).await?;
+
+    if results.is_empty() {
+        return Ok(Action::ReplyText(format!("Nothing found for '{}'.", query)));
+    }
+
+    let lines: Vec<String> = results
+        .iter()
+        .enumerate()
+        .map(|(index, result)| {
+            format!(
+                "{}. <i>{}</i>, {}\n{}: {}",
+                index + 1,
+                telegram_html::escape(&result_title(result)),
+                result.inserted_at.format("%Y-%m-%d %H:%M"),
+                if result.user_role == "user" {
+                    "You"
+                } else {
+                    "Bot"
+                },
+                highlighted_snippet(&result.snippet)
+            )
+        })
+        .collect();
+
+    let buttons: Vec<Vec<InlineKeyboardButton>> = results
+        .iter()
+        .enumerate()
+        .map(|(index, result)| {
+            vec![api::InlineKeyboardButton::from(format!(
+                "{}. Resume {}",
+                index + 1,
+                button_label(&result_title(result))
+            ))
+            .with_callback_data(format!("search:{}", result.id))]
+        })
+        .collect();
+
+    e.api
+        .send_message(
+            &SendMessageRequest::new(
+                chat_id,
+                format!(
+                    "Found {} messages for '{}':\n\n{}",
+                    results.len(),
+                    telegram_html::escape(query),
+                    lines.join("\n\n")
+                ),
+            )
+            .with_parse_mode(ParseMode::HTML)
+            .with_reply_markup(api::ReplyMarkup::inline_keyboard_markup(buttons)),
+        )
+        .await?;
+
+    Ok(Action::Done)
+}
+
+// The thread is resumed as it is when the found message is its latest one,
+// otherwise the conversation continues from the message in a fork, so the
+// turns after it are kept.
+pub async fn handle_search_callback(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_id = data
+        .trim_start_matches("search:")
+        .parse::<i64>()
+        .context(format!("Invalid search callback data {}", data))?;
+
+    e.acknowledge_callback(None).await?;
+
+    let Some(found_message) = chat_message::get_chat_message(&db, chat_id, message_id).await?
+    else {
+        return Ok(Action::ReplyText(
+            "The message doesn't exist anymore.".to_string(),
+        ));
+    };
+
+    let chat_bot = current_chat_bot(&db, &state, chat_id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let thread_messages =
+        chat_message::get_chat_thread_messages(&db, found_message.chat_thread_id).await?;
+    let is_latest_message = thread_messages.last().map(|m| m.id) == Some(found_message.id);
+
+    let chat_thread = if is_latest_message {
+        chat_thread::resume_chat_thread(&db, chat_id, found_message.chat_thread_id)
+            .await
+            .context("Failed to resume the thread")?
+            .ok_or_else(|| anyhow!("The thread of the message doesn't exist"))?
+    } else {
+        chat_thread::fork_chat_thread(&db, chat_id, found_message.id, chat_bot.persona_id)
+            .await
+            .context("Failed to fork the thread")?
+    };
+
+    let history = chat_message::get_chat_thread_history(&db, chat_thread.id).await?;
+
+    Ok(Action::ReplyText(if is_latest_message {
+        format!(
+            "Resumed the thread at that message with {} messages of history.",
+            history.len()
+        )
+    } else {
+        format!(
+            "Continued from that message in a new thread with {} messages of history. The thread it was found in is kept as it was.",
+            history.len()
+        )
+    }))
+}
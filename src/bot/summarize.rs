@@ -0,0 +1,100 @@
+use super::{
+    active_persona, answer_in_current_thread, chat_llm_settings, current_chat_bot, groups, limits,
+    send_answer, RunningBotState,
+};
+use crate::db::chat_bot;
+use crate::llm;
+use crate::web_page;
+use anyhow::{anyhow, Context, Result};
+use mobot::*;
+use sqlx::{Pool, Sqlite};
+
+pub async fn handle_summarize_url(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let _turn = state.chat_queues.wait_turn(message.chat.id).await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let message_content = message.text.clone().unwrap_or_default();
+    let url = groups::command_argument(&message_content, "/summarize_url");
+
+    if !web_page::is_single_url(url) {
+        return Ok(Action::ReplyText(
+            "Please, specify the page to summarize. Example: /summarize_url https://example.com"
+                .to_string(),
+        ));
+    }
+
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    if let Some(reply) = limits::check_limits(
+        &db,
+        &state,
+        message.from.as_ref().map(|u| u.id),
+        message.chat.id,
+    )
+    .await?
+    {
+        return Ok(Action::ReplyText(reply));
+    }
+
+    summarize_url(&e.api, &db, &state, &chat_bot, message, url).await
+}
+
+// The extracted text is stored in the thread as the user message, so the
+// follow-up questions about the page are answered with the page at hand.
+pub async fn summarize_url(
+    api: &API,
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_bot: &chat_bot::ChatBot,
+    message: &api::Message,
+    url: &str,
+) -> Result<Action> {
+    let page = match web_page::fetch(&state.http_client, url).await {
+        Ok(page) => page,
+        Err(e) => {
+            return Ok(Action::ReplyText(format!(
+                "Failed to read the page: {:#}",
+                e
+            )))
+        }
+    };
+
+    let active_persona = active_persona(db, chat_bot).await?;
+    let llm_settings = chat_llm_settings(&state.config, chat_bot, active_persona.as_ref());
+    let context_window =
+        llm::context_window(llm_settings.llm_service, &llm_settings.completion_model);
+
+    let user_content = format!(
+        "Summarize the web page {}{}:\n\n{}",
+        page.url,
+        page.title
+            .as_ref()
+            .map(|title| format!(" ({})", title))
+            .unwrap_or_default(),
+        web_page::truncate_to_context(&page.text, context_window)
+    );
+
+    match answer_in_current_thread(api, db, state, chat_bot, message, &user_content).await {
+        Ok(Some(answer)) => {
+            send_answer(
+                api,
+                db,
+                state,
+                message.chat.id,
+                &answer,
+                groups::reply_to(message),
+            )
+            .await
+        }
+        Ok(None) => Ok(Action::Done),
+        Err(e) => Ok(Action::ReplyText(format!("Error: {:?}", e))),
+    }
+}
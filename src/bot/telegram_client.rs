@@ -0,0 +1,108 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use mobot::client::Post;
+use serde_json::Value;
+
+fn api_url(token: &str) -> String {
+    format!("https://api.telegram.org/bot{}", token)
+}
+
+// mobot types `Message.reply_to_message` as the id of the replied message,
+// while Telegram sends the whole replied message, which fails the parsing of
+// every update with a reply. The responses are fixed up before mobot parses
+// them.
+pub struct TelegramPost {
+    base_url: String,
+    client: reqwest::Client,
+}
+
+impl TelegramPost {
+    pub fn new(token: &str) -> Self {
+        Self {
+            base_url: api_url(token),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl Post for TelegramPost {
+    async fn post(&self, method: String, req: String) -> Result<String> {
+        let body = self
+            .client
+            .post(format!("{}/{}", self.base_url, method))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(req)
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let mut response: Value = serde_json::from_str(&body)?;
+        replace_replied_messages_with_ids(&mut response);
+
+        Ok(response.to_string())
+    }
+}
+
+fn replace_replied_messages_with_ids(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            if let Some(replied_message) = fields.get_mut("reply_to_message") {
+                if let Some(message_id) = replied_message.get("message_id").cloned() {
+                    *replied_message = message_id;
+                }
+            }
+
+            fields
+                .values_mut()
+                .for_each(replace_replied_messages_with_ids);
+        }
+        Value::Array(items) => items.iter_mut().for_each(replace_replied_messages_with_ids),
+        _ => {}
+    }
+}
+
+// mobot has no sendDocument, so the files are uploaded with a multipart request
+// of our own.
+pub async fn send_document(
+    http_client: &reqwest::Client,
+    token: &str,
+    chat_id: i64,
+    file_name: &str,
+    mime_type: &str,
+    content: Vec<u8>,
+    caption: Option<&str>,
+) -> Result<()> {
+    let document = reqwest::multipart::Part::bytes(content)
+        .file_name(file_name.to_string())
+        .mime_str(mime_type)?;
+
+    let mut form = reqwest::multipart::Form::new()
+        .text("chat_id", chat_id.to_string())
+        .part("document", document);
+
+    if let Some(caption) = caption {
+        form = form.text("caption", caption.to_string());
+    }
+
+    let response: Value = http_client
+        .post(format!("{}/sendDocument", api_url(token)))
+        .multipart(form)
+        .send()
+        .await?
+        .json()
+        .await?;
+
+    if response.get("ok").and_then(Value::as_bool) != Some(true) {
+        return Err(anyhow!(
+            "Failed to send the document: {}",
+            response
+                .get("description")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown error")
+        ));
+    }
+
+    Ok(())
+}
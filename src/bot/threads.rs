@@ -0,0 +1,404 @@
+use super::{current_chat_bot, export, RunningBotState, UserChatState};
+use crate::db::chat_message;
+use crate::db::chat_thread;
+use crate::db::chat_thread::ChatThreadSummary;
+use crate::thread_export::ExportFormat;
+use crate::thread_title;
+use anyhow::{anyhow, Context, Result};
+use mobot::api::{
+    EditMessageBase, EditMessageTextRequest, InlineKeyboardButton, SendMessageRequest,
+};
+use mobot::*;
+use sqlx::{Pool, Sqlite};
+
+const THREADS_PAGE_SIZE: i64 = 5;
+
+// The new thread shares the history up to the replied message, and the thread
+// it was forked from is kept as it is.
+pub async fn handle_fork(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let message = e.update.get_new().context("Failed to get new update")?;
+    let state = state.get().read().await;
+    let _turn = state.chat_queues.wait_turn(message.chat.id).await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let Some(replied_message_id) = message.reply_to_message else {
+        return Ok(Action::ReplyText(
+            "Reply with /fork to the message you want to continue the conversation from."
+                .to_string(),
+        ));
+    };
+
+    let chat_bot = current_chat_bot(&db, &state, message.chat.id)
+        .await
+        .context("Failed to get or create chat bot")?;
+
+    let fork_point =
+        chat_message::get_by_telegram_message_id(&db, chat_bot.id, replied_message_id).await?;
+
+    let Some(fork_point) = fork_point else {
+        return Ok(Action::ReplyText(
+            "This message isn't part of any thread, so the conversation can't be forked from it."
+                .to_string(),
+        ));
+    };
+
+    let chat_thread =
+        chat_thread::fork_chat_thread(&db, chat_bot.id, fork_point.id, chat_bot.persona_id)
+            .await
+            .context("Failed to fork the thread")?;
+
+    let history = chat_message::get_chat_thread_history(&db, chat_thread.id).await?;
+
+    Ok(Action::ReplyText(format!(
+        "Started the thread number {} from that message with {} messages of history. The previous thread is kept as it was.",
+        chat_thread.id,
+        history.len()
+    )))
+}
+
+fn thread_title(summary: &ChatThreadSummary) -> String {
+    summary
+        .title
+        .clone()
+        .unwrap_or_else(|| thread_title::fallback_title(summary.first_message.as_deref()))
+}
+
+fn thread_description(summary: &ChatThreadSummary) -> String {
+    let last_message_at = summary
+        .last_message_at
+        .as_ref()
+        .map(|date| format!(", last on {}", date.chars().take(16).collect::<String>()))
+        .unwrap_or_default();
+
+    format!(
+        "{} messages{}{}",
+        summary.message_count,
+        last_message_at,
+        if summary.is_current { " (current)" } else { "" }
+    )
+}
+
+async fn threads_page(
+    db: &Pool<Sqlite>,
+    chat_id: i64,
+    page: i64,
+) -> Result<(String, api::ReplyMarkup)> {
+    let threads_count = chat_thread::count_chat_threads(db, chat_id).await?;
+    let pages_count = ((threads_count + THREADS_PAGE_SIZE - 1) / THREADS_PAGE_SIZE).max(1);
+    let page = page.clamp(0, pages_count - 1);
+
+    let summaries = chat_thread::get_chat_thread_summaries(
+        db,
+        chat_id,
+        THREADS_PAGE_SIZE,
+        page * THREADS_PAGE_SIZE,
+    )
+    .await?;
+
+    if summaries.is_empty() {
+        return Ok((
+            "There are no threads yet. Send a message to start one!".to_string(),
+            api::ReplyMarkup::inline_keyboard_markup(vec![]),
+        ));
+    }
+
+    let lines: Vec<String> = summaries
+        .iter()
+        .enumerate()
+        .map(|(index, summary)| {
+            format!(
+                "{}. {}\n{}",
+                page * THREADS_PAGE_SIZE + index as i64 + 1,
+                thread_title(summary),
+                thread_description(summary)
+            )
+        })
+        .collect();
+
+    let mut buttons: Vec<Vec<InlineKeyboardButton>> = summaries
+        .iter()
+        .enumerate()
+        .map(|(index, summary)| {
+            vec![api::InlineKeyboardButton::from(format!(
+                "{}. {}",
+                page * THREADS_PAGE_SIZE + index as i64 + 1,
+                thread_title(summary)
+            ))
+            .with_callback_data(format!("threads:open:{}", summary.id))]
+        })
+        .collect();
+
+    let mut navigation: Vec<InlineKeyboardButton> = vec![];
+
+    if page > 0 {
+        navigation.push(
+            api::InlineKeyboardButton::from("« Newer")
+                .with_callback_data(format!("threads:page:{}", page - 1)),
+        );
+    }
+
+    if page < pages_count - 1 {
+        navigation.push(
+            api::InlineKeyboardButton::from("Older »")
+                .with_callback_data(format!("threads:page:{}", page + 1)),
+        );
+    }
+
+    if !navigation.is_empty() {
+        buttons.push(navigation);
+    }
+
+    Ok((
+        format!(
+            "Threads, page {} of {}:\n\n{}",
+            page + 1,
+            pages_count,
+            lines.join("\n\n")
+        ),
+        api::ReplyMarkup::inline_keyboard_markup(buttons),
+    ))
+}
+
+fn thread_keyboard(summary: &ChatThreadSummary) -> api::ReplyMarkup {
+    let mut actions: Vec<InlineKeyboardButton> = vec![];
+
+    if !summary.is_current {
+        actions.push(
+            api::InlineKeyboardButton::from("Resume")
+                .with_callback_data(format!("threads:resume:{}", summary.id)),
+        );
+    }
+
+    actions.push(
+        api::InlineKeyboardButton::from("Rename")
+            .with_callback_data(format!("threads:rename:{}", summary.id)),
+    );
+    actions.push(
+        api::InlineKeyboardButton::from("Delete")
+            .with_callback_data(format!("threads:delete:{}", summary.id)),
+    );
+
+    let exports: Vec<InlineKeyboardButton> = [
+        ExportFormat::Markdown,
+        ExportFormat::Json,
+        ExportFormat::Html,
+    ]
+    .iter()
+    .map(|format| {
+        api::InlineKeyboardButton::from(format!("Export .{}", format.extension()))
+            .with_callback_data(format!(
+                "threads:export_{}:{}",
+                format.extension(),
+                summary.id
+            ))
+    })
+    .collect();
+
+    api::ReplyMarkup::inline_keyboard_markup(vec![
+        actions,
+        exports,
+        vec![api::InlineKeyboardButton::from("« Back").with_callback_data("threads:page:0")],
+    ])
+}
+
+pub async fn handle_threads(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let chat_id = e.update.chat_id()?;
+    let state = state.get().read().await;
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let (text, reply_markup) = threads_page(&db, chat_id, 0).await?;
+
+    e.api
+        .send_message(&SendMessageRequest::new(chat_id, text).with_reply_markup(reply_markup))
+        .await?;
+
+    Ok(Action::Done)
+}
+
+// The callback payloads are "threads:<action>:<value>", and the list message
+// is edited in place while browsing.
+pub async fn handle_threads_callback(e: Event, state: State<RunningBotState>) -> Result<Action> {
+    let state = state.get().read().await;
+    let chat_id = e.update.chat_id()?;
+    let telegram_message_id = e.update.message_id()?;
+    let data = e.update.data().unwrap_or_default().to_string();
+    let db = state
+        .db_pool
+        .as_ref()
+        .cloned()
+        .ok_or_else(|| anyhow!("Database pool not available"))?;
+
+    let (action, value) = data
+        .trim_start_matches("threads:")
+        .split_once(':')
+        .and_then(|(a, v)| Some((a, v.parse::<i64>().ok()?)))
+        .ok_or_else(|| anyhow!("Invalid threads callback data {}", data))?;
+
+    let edit_message = |text: String, reply_markup: api::ReplyMarkup| EditMessageTextRequest {
+        base: EditMessageBase::new()
+            .with_chat_id(chat_id)
+            .with_message_id(telegram_message_id)
+            .with_reply_markup(reply_markup),
+        text,
+    };
+
+    if action == "page" {
+        e.acknowledge_callback(None).await?;
+
+        let (text, reply_markup) = threads_page(&db, chat_id, value).await?;
+        e.api
+            .edit_message_text(&edit_message(text, reply_markup))
+            .await?;
+
+        return Ok(Action::Done);
+    }
+
+    let Some(summary) = chat_thread::get_chat_thread_summary(&db, chat_id, value).await? else {
+        e.acknowledge_callback(Some("The thread doesn't exist anymore".to_string()))
+            .await?;
+
+        let (text, reply_markup) = threads_page(&db, chat_id, 0).await?;
+        e.api
+            .edit_message_text(&edit_message(text, reply_markup))
+            .await?;
+
+        return Ok(Action::Done);
+    };
+
+    e.acknowledge_callback(None).await?;
+
+    match action {
+        "open" => {
+            e.api
+                .edit_message_text(&edit_message(
+                    format!(
+                        "{}\n{}",
+                        thread_title(&summary),
+                        thread_description(&summary)
+                    ),
+                    thread_keyboard(&summary),
+                ))
+                .await?;
+
+            Ok(Action::Done)
+        }
+        "resume" => {
+            e.remove_inline_keyboard().await?;
+
+            // The chat's answer in progress finishes in the thread it began in.
+            let _turn = state.chat_queues.wait_turn(chat_id).await;
+
+            chat_thread::resume_chat_thread(&db, chat_id, summary.id)
+                .await
+                .context("Failed to resume the thread")?;
+
+            let history = chat_message::get_chat_thread_history(&db, summary.id).await?;
+
+            Ok(Action::ReplyText(format!(
+                "Resumed '{}' with {} messages of history.",
+                thread_title(&summary),
+                history.len()
+            )))
+        }
+        "rename" => {
+            e.remove_inline_keyboard().await?;
+
+            let mut user_chat_state_write_lock = state.user_chat_state.write().await;
+            user_chat_state_write_lock
+                .insert(chat_id, UserChatState::WaitingThreadTitle(summary.id));
+
+            Ok(Action::ReplyText(format!(
+                "Send the new title for '{}'.",
+                thread_title(&summary)
+            )))
+        }
+        "delete" => {
+            e.api
+                .edit_message_text(&edit_message(
+                    format!(
+                        "Delete '{}' and its {} messages? This can't be undone.",
+                        thread_title(&summary),
+                        summary.message_count
+                    ),
+                    api::ReplyMarkup::inline_keyboard_markup(vec![vec![
+                        api::InlineKeyboardButton::from("Delete")
+                            .with_callback_data(format!("threads:confirm_delete:{}", summary.id)),
+                        api::InlineKeyboardButton::from("Cancel")
+                            .with_callback_data(format!("threads:open:{}", summary.id)),
+                    ]]),
+                ))
+                .await?;
+
+            Ok(Action::Done)
+        }
+        "confirm_delete" => {
+            e.remove_inline_keyboard().await?;
+
+            if chat_thread::count_forked_threads(&db, summary.id).await? > 0 {
+                return Ok(Action::ReplyText(format!(
+                    "Other threads were forked from '{}', so it can't be deleted without breaking their history. Delete them first.",
+                    thread_title(&summary)
+                )));
+            }
+
+            chat_thread::delete_chat_thread(&db, chat_id, summary.id)
+                .await
+                .context("Failed to delete the thread")?;
+
+            Ok(Action::ReplyText(format!(
+                "Deleted '{}'.",
+                thread_title(&summary)
+            )))
+        }
+        "export_md" | "export_json" | "export_html" => {
+            let format = action
+                .trim_start_matches("export_")
+                .parse::<ExportFormat>()?;
+            let chat_bot = current_chat_bot(&db, &state, chat_id)
+                .await
+                .context("Failed to get or create chat bot")?;
+
+            export::export_thread(&db, &state, &chat_bot, summary.id, format).await
+        }
+        _ => Err(anyhow!("Unknown threads callback action {}", action)),
+    }
+}
+
+pub async fn save_thread_title(
+    db: &Pool<Sqlite>,
+    state: &RunningBotState,
+    chat_id: i64,
+    thread_id: i64,
+    title: &str,
+) -> Result<Action> {
+    let title = title.trim();
+
+    if title.is_empty() || title.chars().count() > thread_title::MAX_TITLE_LENGTH {
+        return Ok(Action::ReplyText(format!(
+            "The title should have from 1 to {} characters. Please, send it again.",
+            thread_title::MAX_TITLE_LENGTH
+        )));
+    }
+
+    let mut user_chat_state_write_lock = state.user_chat_state.write().await;
+    user_chat_state_write_lock.insert(chat_id, UserChatState::Default);
+    drop(user_chat_state_write_lock);
+
+    match chat_thread::set_chat_thread_title(db, chat_id, thread_id, title).await? {
+        Some(_) => Ok(Action::ReplyText(format!(
+            "Renamed the thread to '{}'.",
+            title
+        ))),
+        None => Ok(Action::ReplyText(
+            "The thread doesn't exist anymore.".to_string(),
+        )),
+    }
+}
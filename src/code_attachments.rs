@@ -0,0 +1,298 @@
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use std::ops::Range;
+
+// The fenced blocks from this many lines or characters are sent as files.
+const MIN_ATTACHMENT_LINES: usize = 40;
+const MIN_ATTACHMENT_LENGTH: usize = 2000;
+
+pub struct Attachment {
+    pub file_name: String,
+    pub mime_type: &'static str,
+    pub content: String,
+}
+
+// The answer's text with the large blocks replaced by a reference to their
+// file, and the files to send along.
+pub struct AnswerParts {
+    pub text: String,
+    pub attachments: Vec<Attachment>,
+}
+
+struct FencedBlock {
+    range: Range<usize>,
+    language: String,
+    content: String,
+}
+
+fn fenced_blocks(markdown: &str) -> Vec<FencedBlock> {
+    let mut blocks = vec![];
+    let mut current: Option<FencedBlock> = None;
+
+    for (event, range) in Parser::new(markdown).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(info))) => {
+                current = Some(FencedBlock {
+                    range,
+                    language: info
+                        .split_whitespace()
+                        .next()
+                        .unwrap_or_default()
+                        .to_lowercase(),
+                    content: String::new(),
+                });
+            }
+            Event::Text(text) => {
+                if let Some(block) = current.as_mut() {
+                    block.content.push_str(&text);
+                }
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                blocks.extend(current.take());
+            }
+            _ => {}
+        }
+    }
+
+    blocks
+}
+
+fn is_json(content: &str) -> bool {
+    let content = content.trim_start();
+
+    (content.starts_with('{') || content.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(content).is_ok()
+}
+
+// Every line has the same number of commas, and there is at least one.
+fn is_csv(content: &str) -> bool {
+    let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+    let Some(header) = lines.next() else {
+        return false;
+    };
+    let columns = header.matches(',').count();
+
+    columns > 0 && lines.all(|l| l.matches(',').count() == columns)
+}
+
+fn extension(language: &str) -> Option<&'static str> {
+    let extension = match language {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "javascript" | "js" | "jsx" => "js",
+        "typescript" | "ts" | "tsx" => "ts",
+        "json" => "json",
+        "csv" => "csv",
+        "html" => "html",
+        "xml" => "xml",
+        "css" => "css",
+        "sql" => "sql",
+        "bash" | "sh" | "shell" | "zsh" => "sh",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "go" | "golang" => "go",
+        "java" => "java",
+        "kotlin" | "kt" => "kt",
+        "swift" => "swift",
+        "c" => "c",
+        "cpp" | "c++" | "cc" => "cpp",
+        "csharp" | "c#" | "cs" => "cs",
+        "ruby" | "rb" => "rb",
+        "php" => "php",
+        "markdown" | "md" => "md",
+        _ => return None,
+    };
+
+    Some(extension)
+}
+
+// The untagged blocks holding JSON or CSV get their extension too.
+fn file_extension(block: &FencedBlock) -> &'static str {
+    match extension(&block.language) {
+        Some(extension) => extension,
+        None if is_json(&block.content) => "json",
+        None if is_csv(&block.content) => "csv",
+        None => "txt",
+    }
+}
+
+fn mime_type(extension: &str) -> &'static str {
+    match extension {
+        "json" => "application/json",
+        "csv" => "text/csv",
+        "html" => "text/html",
+        "xml" => "application/xml",
+        "md" => "text/markdown",
+        _ => "text/plain",
+    }
+}
+
+fn is_large(content: &str) -> bool {
+    content.lines().count() >= MIN_ATTACHMENT_LINES
+        || content.chars().count() >= MIN_ATTACHMENT_LENGTH
+}
+
+pub fn extract_attachments(markdown: &str) -> AnswerParts {
+    let mut text = String::new();
+    let mut attachments: Vec<Attachment> = vec![];
+    let mut copied_until = 0;
+
+    for block in fenced_blocks(markdown)
+        .into_iter()
+        .filter(|b| is_large(&b.content))
+    {
+        let extension = file_extension(&block);
+        let stem = match extension {
+            "json" | "csv" => "data",
+            _ => "code",
+        };
+        let same_kind = attachments
+            .iter()
+            .filter(|a| {
+                a.file_name.starts_with(stem) && a.file_name.ends_with(&format!(".{}", extension))
+            })
+            .count();
+        let file_name = if same_kind == 0 {
+            format!("{}.{}", stem, extension)
+        } else {
+            format!("{}-{}.{}", stem, same_kind + 1, extension)
+        };
+
+        let lines = block.content.lines().count();
+
+        text.push_str(&markdown[copied_until..block.range.start]);
+        text.push_str(&format!(
+            "📎 `{}` ({} {}) is attached.\n",
+            file_name,
+            lines,
+            if lines == 1 { "line" } else { "lines" }
+        ));
+        copied_until = block.range.end;
+
+        attachments.push(Attachment {
+            file_name,
+            mime_type: mime_type(extension),
+            content: block.content,
+        });
+    }
+
+    text.push_str(&markdown[copied_until..]);
+
+    AnswerParts { text, attachments }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fenced(language: &str, content: &str) -> String {
+        format!("```{}\n{}\n```\n", language, content)
+    }
+
+    fn lines(count: usize) -> String {
+        (1..=count)
+            .map(|n| format!("line {}", n))
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn file_names(parts: &AnswerParts) -> Vec<&str> {
+        parts
+            .attachments
+            .iter()
+            .map(|a| a.file_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_small_blocks_in_the_text() {
+        let markdown = format!(
+            "Here:\n\n{}{}",
+            fenced("rust", &lines(MIN_ATTACHMENT_LINES - 1)),
+            // The block's content ends with a newline.
+            fenced("", &"a".repeat(MIN_ATTACHMENT_LENGTH - 2))
+        );
+        let parts = extract_attachments(&markdown);
+
+        assert!(parts.attachments.is_empty());
+        assert_eq!(parts.text, markdown);
+    }
+
+    #[test]
+    fn attaches_the_blocks_from_the_line_or_length_threshold() {
+        let parts = extract_attachments(&format!(
+            "{}{}",
+            fenced("rust", &lines(MIN_ATTACHMENT_LINES)),
+            fenced("rust", &"a".repeat(MIN_ATTACHMENT_LENGTH - 1))
+        ));
+
+        assert_eq!(file_names(&parts), vec!["code.rs", "code-2.rs"]);
+        assert_eq!(
+            parts.attachments[0].content,
+            format!("{}\n", lines(MIN_ATTACHMENT_LINES))
+        );
+    }
+
+    #[test]
+    fn replaces_the_attached_block_with_a_reference() {
+        let parts = extract_attachments(&format!(
+            "Before\n\n{}\nAfter",
+            fenced("python", &lines(MIN_ATTACHMENT_LINES))
+        ));
+
+        assert!(parts
+            .text
+            .starts_with("Before\n\n📎 `code.py` (40 lines) is attached.\n"));
+        assert!(parts.text.ends_with("\nAfter"));
+        assert!(!parts.text.contains("line 1"));
+        assert_eq!(parts.attachments[0].mime_type, "text/plain");
+    }
+
+    #[test]
+    fn names_the_file_from_the_language_tag() {
+        let block = lines(MIN_ATTACHMENT_LINES);
+        let parts = extract_attachments(&format!(
+            "{}{}{}{}",
+            fenced("TypeScript", &block),
+            fenced("sh {.numberLines}", &block),
+            fenced("brainfuck", &block),
+            fenced("html", &block)
+        ));
+
+        assert_eq!(
+            file_names(&parts),
+            vec!["code.ts", "code.sh", "code.txt", "code.html"]
+        );
+        assert_eq!(parts.attachments[3].mime_type, "text/html");
+    }
+
+    #[test]
+    fn names_the_untagged_data_from_its_content() {
+        let json = format!(
+            "[{}]",
+            (0..MIN_ATTACHMENT_LINES)
+                .map(|n| format!("{{\"n\": {}}}", n))
+                .collect::<Vec<String>>()
+                .join(",\n")
+        );
+        let csv = format!(
+            "name,age\n{}",
+            (0..MIN_ATTACHMENT_LINES)
+                .map(|n| format!("user {},{}", n, n))
+                .collect::<Vec<String>>()
+                .join("\n")
+        );
+        let parts = extract_attachments(&format!(
+            "{}{}{}",
+            fenced("", &json),
+            fenced("", &csv),
+            fenced("", &json)
+        ));
+
+        assert_eq!(
+            file_names(&parts),
+            vec!["data.json", "data.csv", "data-2.json"]
+        );
+        assert_eq!(parts.attachments[0].mime_type, "application/json");
+        assert_eq!(parts.attachments[1].mime_type, "text/csv");
+    }
+}
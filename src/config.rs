@@ -1,13 +1,61 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use std::env;
+use std::str::FromStr;
 
+use crate::llm::embeddings::EmbeddingsConfig;
 use crate::llm::LLMServiceKind;
+use crate::llm::ModelPair;
+use crate::persona_catalog;
+use crate::persona_catalog::PersonaCatalog;
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub llm_service: LLMServiceKind,
     pub telegram_token: String,
     pub groq_api_key: Option<String>,
+    pub openai_api_key: Option<String>,
+    pub persona_catalog: PersonaCatalog,
+    pub embeddings: EmbeddingsConfig,
+    pub auto_memories: bool,
+    pub compare_models: Vec<ModelPair>,
+    pub retention_days: Option<u32>,
+    pub user_limits: Limits,
+    pub chat_limits: Limits,
+    pub access_mode: AccessMode,
+    pub allowed_ids: Vec<i64>,
+    pub admins: Vec<i64>,
+}
+
+// The limits of the messages answered by the model. None is unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    pub messages_per_minute: Option<u32>,
+    pub daily_messages: Option<u32>,
+    pub daily_tokens: Option<u32>,
+}
+
+// Who may use the bot besides the admins and the ALLOWED_IDS users and chats.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessMode {
+    // Everyone that isn't blocked.
+    Open,
+    // Only the allowed users and chats, and the users approved by an admin.
+    Allowlist,
+    // Also the users that redeemed an invite code.
+    Invite,
+}
+
+impl FromStr for AccessMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "open" => Ok(AccessMode::Open),
+            "allowlist" => Ok(AccessMode::Allowlist),
+            "invite" => Ok(AccessMode::Invite),
+            _ => Err(()),
+        }
+    }
 }
 
 fn assert_env_var(env_var_name: &str) -> String {
@@ -26,6 +74,28 @@ impl Default for Config {
             llm_service: LLMServiceKind::Mock,
             telegram_token: assert_env_var("TELEGRAM_TOKEN"),
             groq_api_key: env::var("GROQ_API_KEY").ok().clone(),
+            openai_api_key: env::var("OPENAI_API_KEY").ok().clone(),
+            persona_catalog: PersonaCatalog::default(),
+            embeddings: embeddings_config(),
+            // The extraction sends the thread to the model, so it's opt-in.
+            auto_memories: env::var("AUTO_MEMORIES").is_ok_and(|v| v == "true"),
+            compare_models: vec![],
+            retention_days: None,
+            user_limits: Limits::default(),
+            chat_limits: Limits::default(),
+            access_mode: AccessMode::Open,
+            allowed_ids: vec![],
+            admins: vec![],
+        }
+    }
+}
+
+impl Config {
+    pub fn is_llm_service_available(&self, llm_service: LLMServiceKind) -> bool {
+        match llm_service {
+            LLMServiceKind::OpenAI => self.openai_api_key.is_some(),
+            LLMServiceKind::Groq => self.groq_api_key.is_some(),
+            LLMServiceKind::Mock => true,
         }
     }
 }
@@ -33,13 +103,144 @@ impl Default for Config {
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(next_line_help = true)]
-struct Cli {
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
     #[arg(value_enum)]
     llm_service: Option<LLMServiceKind>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Re-encrypts the stored messages and behaviors with the first key of
+    /// ENCRYPTION_KEYS, so the previous keys can be removed afterwards.
+    RotateKeys,
+}
+
+// Embeddings use the OpenAI-compatible API when a key is available and fall
+// back to the local hashed embeddings otherwise.
+fn embeddings_config() -> EmbeddingsConfig {
+    if env::var("EMBEDDINGS_PROVIDER").ok().as_deref() == Some("local") {
+        return EmbeddingsConfig::Local;
+    }
+
+    let api_key = env::var("EMBEDDINGS_API_KEY").or_else(|_| env::var("OPENAI_API_KEY"));
+
+    match api_key {
+        Ok(api_key) => EmbeddingsConfig::OpenAICompatible {
+            api_url: env::var("EMBEDDINGS_API_URL")
+                .unwrap_or_else(|_| "https://api.openai.com/v1".to_string()),
+            api_key,
+            model: env::var("EMBEDDINGS_MODEL")
+                .unwrap_or_else(|_| "text-embedding-3-small".to_string()),
+        },
+        Err(_) => EmbeddingsConfig::Local,
+    }
+}
+
+fn load_persona_catalog() -> PersonaCatalog {
+    let catalog_path = env::var("PERSONA_CATALOG").ok();
+
+    let path = match catalog_path {
+        Some(path) => path,
+        None if std::path::Path::new(persona_catalog::DEFAULT_CATALOG_PATH).exists() => {
+            persona_catalog::DEFAULT_CATALOG_PATH.to_string()
+        }
+        None => return PersonaCatalog::default(),
+    };
+
+    persona_catalog::load(&path).unwrap_or_else(|e| {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(1);
+    })
+}
+
+fn load_compare_models() -> Vec<ModelPair> {
+    let Ok(compare_models) = env::var("COMPARE_MODELS") else {
+        return vec![];
+    };
+
+    compare_models
+        .split(',')
+        .filter(|m| !m.trim().is_empty())
+        .map(|m| {
+            m.parse::<ModelPair>().unwrap_or_else(|e| {
+                eprintln!("Error: invalid COMPARE_MODELS entry. {:?}", e);
+                std::process::exit(1);
+            })
+        })
+        .collect()
+}
+
+// The messages are kept forever unless RETENTION_DAYS is set to a positive
+// number of days.
+fn load_retention_days() -> Option<u32> {
+    let retention_days = env::var("RETENTION_DAYS").ok()?;
+
+    let retention_days = retention_days.trim().parse::<u32>().unwrap_or_else(|e| {
+        eprintln!("Error: invalid RETENTION_DAYS. {:?}", e);
+        std::process::exit(1);
+    });
+
+    Some(retention_days).filter(|days| *days > 0)
+}
+
+// A positive number, or 0 and unset for unlimited.
+fn load_limit(env_var_name: &str) -> Option<u32> {
+    let limit = env::var(env_var_name).ok()?;
+
+    let limit = limit.trim().parse::<u32>().unwrap_or_else(|e| {
+        eprintln!("Error: invalid {}. {:?}", env_var_name, e);
+        std::process::exit(1);
+    });
+
+    Some(limit).filter(|limit| *limit > 0)
 }
 
-pub fn create_config() -> Config {
-    let cli = Cli::parse();
+// The user limits are RATE_LIMIT_PER_MINUTE, DAILY_MESSAGE_QUOTA and
+// DAILY_TOKEN_QUOTA, and the chat limits have the same names prefixed with
+// CHAT_.
+fn load_limits(prefix: &str) -> Limits {
+    Limits {
+        messages_per_minute: load_limit(&format!("{}RATE_LIMIT_PER_MINUTE", prefix)),
+        daily_messages: load_limit(&format!("{}DAILY_MESSAGE_QUOTA", prefix)),
+        daily_tokens: load_limit(&format!("{}DAILY_TOKEN_QUOTA", prefix)),
+    }
+}
+
+fn load_access_mode() -> AccessMode {
+    let Ok(access_mode) = env::var("ACCESS_MODE") else {
+        return AccessMode::Open;
+    };
+
+    access_mode.parse::<AccessMode>().unwrap_or_else(|_| {
+        eprintln!(
+            "Error: invalid ACCESS_MODE {:?}. Use open, allowlist or invite.",
+            access_mode
+        );
+        std::process::exit(1);
+    })
+}
+
+// A comma-separated list of Telegram user or chat ids.
+fn load_ids(env_var_name: &str) -> Vec<i64> {
+    let Ok(ids) = env::var(env_var_name) else {
+        return vec![];
+    };
+
+    ids.split(',')
+        .filter(|id| !id.trim().is_empty())
+        .map(|id| {
+            id.trim().parse::<i64>().unwrap_or_else(|e| {
+                eprintln!("Error: invalid {} entry {:?}. {:?}", env_var_name, id, e);
+                std::process::exit(1);
+            })
+        })
+        .collect()
+}
+
+pub fn create_config(cli: &Cli) -> Config {
     let mut cfg = Config::default();
 
     let openai_key = env::var("OPENAI_API_KEY");
@@ -60,5 +261,14 @@ pub fn create_config() -> Config {
         },
     }
 
+    cfg.persona_catalog = load_persona_catalog();
+    cfg.compare_models = load_compare_models();
+    cfg.retention_days = load_retention_days();
+    cfg.user_limits = load_limits("");
+    cfg.chat_limits = load_limits("CHAT_");
+    cfg.access_mode = load_access_mode();
+    cfg.allowed_ids = load_ids("ALLOWED_IDS");
+    cfg.admins = load_ids("ADMINS");
+
     cfg
 }
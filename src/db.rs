@@ -1,22 +1,29 @@
-use sqlx::{FromRow, Pool, Sqlite, SqlitePool};
+use sqlx::{Pool, Sqlite, SqlitePool};
 
 pub mod chat_bot;
+pub mod chat_data;
 pub mod chat_message;
 pub mod chat_thread;
+pub mod document;
+pub mod invite_code;
+pub mod memory;
 pub mod migration;
-
-#[derive(Clone, FromRow, Debug)]
-pub struct ChatThread {
-    pub id: i64,
-    pub is_current: bool,
-    pub chat_id: i64,
-}
+pub mod model_comparison;
+pub mod persona;
+pub mod reencryption;
+pub mod stats;
+pub mod usage;
+pub mod user;
 
 pub async fn start(url: &String) -> Pool<Sqlite> {
     migration::create_db_if_doesnt_exists(url).await;
 
-    let db_conn = SqlitePool::connect(url).await.unwrap();
-    migration::run_all_migrations(&db_conn).await;
+    let migration_conn = SqlitePool::connect(url).await.unwrap();
+    migration::run_all_migrations(&migration_conn).await;
+
+    // The connections used by the migrations may hold statements prepared
+    // against the old schema, so the bot gets a fresh pool.
+    migration_conn.close().await;
 
-    db_conn
+    SqlitePool::connect(url).await.unwrap()
 }
@@ -1,5 +1,8 @@
+use crate::db::user::STATUS_BLOCKED;
+use crate::encryption;
 use crate::llm::{
     groq::GroqCompletionModel, mock::MockCompletionModel, openai::OpenAICompletionModel,
+    LLMServiceKind,
 };
 use anyhow::{Context, Result};
 use sqlx::{FromRow, Pool, Sqlite};
@@ -11,19 +14,48 @@ pub struct ChatBot {
     pub mock_model: String,
     pub openai_model: String,
     pub groq_model: String,
+    pub persona_id: Option<i64>,
+    pub llm_service: Option<String>,
+    pub retention_days: Option<i64>,
+    pub group_context: bool,
 }
 
-async fn get_by_id(db_conn: &Pool<Sqlite>, id: i64) -> Result<ChatBot> {
+// The behavior is stored encrypted when the encryption is enabled, and the
+// functions below encrypt and decrypt it for their callers.
+fn decrypted(mut chat_bot: ChatBot) -> Result<ChatBot> {
+    chat_bot.behavior = encryption::decrypt(
+        encryption::CHAT_BOT_BEHAVIOR,
+        chat_bot.id,
+        &chat_bot.behavior,
+    )?;
+
+    Ok(chat_bot)
+}
+
+pub async fn get_by_id(db_conn: &Pool<Sqlite>, id: i64) -> Result<ChatBot> {
     let chat_bot = sqlx::query_as::<_, ChatBot>("SELECT * FROM chat_bots WHERE id = ?1 LIMIT 1")
         .bind(id)
         .fetch_one(db_conn)
         .await?;
 
-    Ok(chat_bot)
+    decrypted(chat_bot)
 }
 
-pub async fn get_or_create_chat_bot(db_conn: &Pool<Sqlite>, id: i64) -> Result<ChatBot> {
-    let behavior: String = "You're a helpful assistant.".into();
+pub async fn find_by_id(db_conn: &Pool<Sqlite>, id: i64) -> Result<Option<ChatBot>> {
+    let chat_bot: Option<ChatBot> = sqlx::query_as("SELECT * FROM chat_bots WHERE id = ?1")
+        .bind(id)
+        .fetch_optional(db_conn)
+        .await
+        .context("Failed to find the chat bot")?;
+
+    chat_bot.map(decrypted).transpose()
+}
+
+pub async fn get_or_create_chat_bot(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    default_behavior: &str,
+) -> Result<ChatBot> {
     let mock_completion_model = MockCompletionModel::default_string();
     let openai_completion_model = OpenAICompletionModel::default_string();
     let groq_completion_model = GroqCompletionModel::default_string();
@@ -36,7 +68,11 @@ pub async fn get_or_create_chat_bot(db_conn: &Pool<Sqlite>, id: i64) -> Result<C
           DO NOTHING"#,
     )
     .bind(id)
-    .bind(behavior)
+    .bind(encryption::encrypt(
+        encryption::CHAT_BOT_BEHAVIOR,
+        id,
+        default_behavior,
+    )?)
     .bind(mock_completion_model)
     .bind(openai_completion_model)
     .bind(groq_completion_model)
@@ -51,10 +87,65 @@ pub async fn get_or_create_chat_bot(db_conn: &Pool<Sqlite>, id: i64) -> Result<C
 pub async fn set_chat_bot_behavior(
     db_conn: &Pool<Sqlite>,
     id: i64,
-    behavior: &String,
+    behavior: &str,
 ) -> Result<ChatBot> {
     sqlx::query("UPDATE chat_bots SET behavior = ?1 WHERE id = ?2")
-        .bind(behavior)
+        .bind(encryption::encrypt(
+            encryption::CHAT_BOT_BEHAVIOR,
+            id,
+            behavior,
+        )?)
+        .bind(id)
+        .execute(db_conn)
+        .await?;
+
+    let chat_bot = get_by_id(db_conn, id).await?;
+
+    Ok(chat_bot)
+}
+
+// None falls back to the bot-wide retention, and 0 keeps the messages forever.
+pub async fn set_chat_bot_retention_days(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    retention_days: Option<i64>,
+) -> Result<ChatBot> {
+    sqlx::query("UPDATE chat_bots SET retention_days = ?1 WHERE id = ?2")
+        .bind(retention_days)
+        .bind(id)
+        .execute(db_conn)
+        .await?;
+
+    let chat_bot = get_by_id(db_conn, id).await?;
+
+    Ok(chat_bot)
+}
+
+// Whether the group messages that aren't addressed to the bot are kept in the
+// thread as context.
+pub async fn set_chat_bot_group_context(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    group_context: bool,
+) -> Result<ChatBot> {
+    sqlx::query("UPDATE chat_bots SET group_context = ?1 WHERE id = ?2")
+        .bind(group_context)
+        .bind(id)
+        .execute(db_conn)
+        .await?;
+
+    let chat_bot = get_by_id(db_conn, id).await?;
+
+    Ok(chat_bot)
+}
+
+pub async fn set_chat_bot_persona(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    persona_id: Option<i64>,
+) -> Result<ChatBot> {
+    sqlx::query("UPDATE chat_bots SET persona_id = ?1 WHERE id = ?2")
+        .bind(persona_id)
         .bind(id)
         .execute(db_conn)
         .await?;
@@ -83,7 +174,7 @@ pub async fn set_chat_bot_mock_model(
         completion_model_string
     ))?;
 
-    Ok(chat_bot)
+    decrypted(chat_bot)
 }
 
 pub async fn set_chat_bot_openai_model(
@@ -131,3 +222,48 @@ pub async fn set_chat_bot_groq_model(
 
     Ok(chat_bot)
 }
+
+// Makes the model the chat's model and its provider the chat's provider.
+pub async fn set_chat_bot_model(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    llm_service: LLMServiceKind,
+    completion_model: &str,
+) -> Result<ChatBot> {
+    let model_column = match llm_service {
+        LLMServiceKind::OpenAI => "openai_model",
+        LLMServiceKind::Groq => "groq_model",
+        LLMServiceKind::Mock => "mock_model",
+    };
+
+    let chat_bot = sqlx::query_as::<_, ChatBot>(&format!(
+        "UPDATE chat_bots SET llm_service = ?1, {} = ?2 WHERE id = ?3 RETURNING *;",
+        model_column
+    ))
+    .bind(llm_service.as_str())
+    .bind(completion_model)
+    .bind(id)
+    .fetch_one(db_conn)
+    .await
+    .context(format!(
+        "Couldn't update the chat bot's model with {} {}",
+        llm_service, completion_model
+    ))?;
+
+    decrypted(chat_bot)
+}
+
+// The chats with the blocked users are left out.
+pub async fn get_chat_bot_ids(db_conn: &Pool<Sqlite>) -> Result<Vec<i64>> {
+    let ids: Vec<(i64,)> = sqlx::query_as(
+        r#"SELECT id FROM chat_bots
+        WHERE id NOT IN (SELECT id FROM users WHERE status = ?1)
+        ORDER BY id"#,
+    )
+    .bind(STATUS_BLOCKED)
+    .fetch_all(db_conn)
+    .await
+    .context("Failed to get the chat bot ids")?;
+
+    Ok(ids.into_iter().map(|(id,)| id).collect())
+}
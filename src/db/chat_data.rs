@@ -0,0 +1,267 @@
+use sqlx::{Pool, Sqlite};
+
+#[derive(Clone, Debug)]
+pub struct DeletedChatData {
+    pub chat_bots: u64,
+    pub chat_threads: u64,
+    pub chat_messages: u64,
+    pub personas: u64,
+    pub documents: u64,
+    pub document_chunks: u64,
+    pub memories: u64,
+    pub model_comparisons: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct ExpiredChatData {
+    pub chat_threads: u64,
+    pub chat_messages: u64,
+    pub model_comparisons: u64,
+}
+
+impl ExpiredChatData {
+    pub fn is_empty(&self) -> bool {
+        self.chat_threads == 0 && self.chat_messages == 0 && self.model_comparisons == 0
+    }
+}
+
+// Removes the conversation content stored for the chat. The users, their usage
+// and their limits are kept, so deleting the data doesn't reset the quotas or
+// the access set by the admins. The full-text index follows the messages
+// through its triggers.
+pub async fn delete_chat_data(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+) -> anyhow::Result<DeletedChatData> {
+    let mut tx = db_conn.begin().await?;
+
+    let chat_messages = sqlx::query("DELETE FROM chat_messages WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    let chat_threads = sqlx::query("DELETE FROM chat_threads WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    let personas = sqlx::query("DELETE FROM personas WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    let document_chunks = sqlx::query("DELETE FROM document_chunks WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    let documents = sqlx::query("DELETE FROM documents WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    let memories = sqlx::query("DELETE FROM memories WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    sqlx::query(
+        r#"DELETE FROM model_comparison_answers WHERE comparison_id IN (
+            SELECT id FROM model_comparisons WHERE chat_id = ?1
+        )"#,
+    )
+    .bind(chat_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let model_comparisons = sqlx::query("DELETE FROM model_comparisons WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    let chat_bots = sqlx::query("DELETE FROM chat_bots WHERE id = ?1")
+        .bind(chat_id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(DeletedChatData {
+        chat_bots,
+        chat_threads,
+        chat_messages,
+        personas,
+        documents,
+        document_chunks,
+        memories,
+        model_comparisons,
+    })
+}
+
+// Deletes the messages and the model comparisons older than the chat's
+// retention, or the default retention when the chat has none, and then the
+// threads left without messages. The current threads are kept. The imported
+// messages keep their original timestamps, so their age counts from the
+// import.
+pub async fn delete_expired_chat_data(
+    db_conn: &Pool<Sqlite>,
+    default_retention_days: Option<u32>,
+) -> anyhow::Result<ExpiredChatData> {
+    let default_retention_days = i64::from(default_retention_days.unwrap_or(0));
+    let mut tx = db_conn.begin().await?;
+
+    let chat_messages = sqlx::query(
+        r#"
+        WITH expired AS (
+            SELECT chat_messages.id FROM chat_messages
+            LEFT JOIN chat_bots ON chat_bots.id = chat_messages.chat_id
+            LEFT JOIN chat_threads ON chat_threads.id = chat_messages.chat_thread_id
+            WHERE COALESCE(chat_bots.retention_days, ?1) > 0
+              AND MAX(
+                chat_messages.inserted_at,
+                COALESCE(chat_threads.imported_at, chat_messages.inserted_at)
+              ) < STRFTIME(
+                '%Y-%m-%d %H:%M:%f', 'NOW', '-' || COALESCE(chat_bots.retention_days, ?1) || ' days'
+              )
+        )
+        DELETE FROM chat_messages WHERE id IN (SELECT id FROM expired)
+        "#,
+    )
+    .bind(default_retention_days)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    // The history a message or a fork inherits is older than the message, so
+    // it expires first, and the references to it are cleared instead of left
+    // dangling.
+    sqlx::query(
+        r#"UPDATE chat_messages SET parent_message_id = NULL
+        WHERE parent_message_id IS NOT NULL
+          AND parent_message_id NOT IN (SELECT id FROM chat_messages)"#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"UPDATE chat_threads SET parent_message_id = NULL
+        WHERE parent_message_id IS NOT NULL
+          AND parent_message_id NOT IN (SELECT id FROM chat_messages)"#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let model_comparisons = sqlx::query(
+        r#"
+        WITH expired AS (
+            SELECT model_comparisons.id FROM model_comparisons
+            LEFT JOIN chat_bots ON chat_bots.id = model_comparisons.chat_id
+            WHERE COALESCE(chat_bots.retention_days, ?1) > 0
+              AND model_comparisons.inserted_at < STRFTIME(
+                '%Y-%m-%d %H:%M:%f', 'NOW', '-' || COALESCE(chat_bots.retention_days, ?1) || ' days'
+              )
+        )
+        DELETE FROM model_comparisons WHERE id IN (SELECT id FROM expired)
+        "#,
+    )
+    .bind(default_retention_days)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    sqlx::query(
+        r#"DELETE FROM model_comparison_answers WHERE comparison_id NOT IN (
+            SELECT id FROM model_comparisons
+        )"#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let chat_threads = sqlx::query(
+        r#"
+        DELETE FROM chat_threads
+        WHERE NOT COALESCE(chat_threads.is_current, false)
+          AND NOT EXISTS (
+            SELECT 1 FROM chat_messages WHERE chat_messages.chat_thread_id = chat_threads.id
+          )
+        "#,
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    tx.commit().await?;
+
+    Ok(ExpiredChatData {
+        chat_threads,
+        chat_messages,
+        model_comparisons,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::db::usage;
+    use crate::db::user;
+
+    #[tokio::test]
+    async fn keeps_the_usage_limits_and_access() {
+        let path = std::env::temp_dir().join(format!("chat-data-usage-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_conn = db::start(&format!("sqlite:{}", path.display())).await;
+        let day = "2024-05-01";
+        let bucket = usage::RateLimitBucket {
+            tokens: 0.5,
+            updated_at: 1714557600.0,
+        };
+
+        user::get_or_create_user(&db_conn, 42, "Ada", None)
+            .await
+            .unwrap();
+        user::set_user_status(&db_conn, 42, user::STATUS_ACTIVE)
+            .await
+            .unwrap();
+        user::set_user_limit(&db_conn, 42, user::UserLimit::DailyMessages, Some(5))
+            .await
+            .unwrap();
+
+        for subject in [usage::SUBJECT_USER, usage::SUBJECT_CHAT] {
+            usage::add_daily_usage(&db_conn, subject, 42, day, 3, 1200)
+                .await
+                .unwrap();
+            usage::set_rate_limit_bucket(&db_conn, subject, 42, &bucket)
+                .await
+                .unwrap();
+        }
+
+        delete_chat_data(&db_conn, 42).await.unwrap();
+
+        for subject in [usage::SUBJECT_USER, usage::SUBJECT_CHAT] {
+            let daily_usage = usage::get_daily_usage(&db_conn, subject, 42, day)
+                .await
+                .unwrap();
+            let rate_limit_bucket = usage::get_rate_limit_bucket(&db_conn, subject, 42)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!((daily_usage.messages, daily_usage.tokens), (3, 1200));
+            assert_eq!(rate_limit_bucket.tokens, 0.5);
+        }
+
+        let user = user::get_user(&db_conn, 42).await.unwrap().unwrap();
+
+        assert_eq!(user.status, user::STATUS_ACTIVE);
+        assert_eq!(user.daily_messages, Some(5));
+    }
+}
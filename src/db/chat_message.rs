@@ -1,3 +1,4 @@
+use crate::encryption;
 use sqlx::{FromRow, Pool, Sqlite};
 extern crate rand;
 use anyhow::Context;
@@ -7,38 +8,91 @@ use rand::Rng;
 pub struct ChatMessage {
     pub id: i64,
     pub content: String,
-    pub chat_id: i64,
     pub chat_thread_id: i64,
     pub user_role: String,
     pub inserted_at: chrono::DateTime<chrono::Utc>,
+    pub telegram_message_id: Option<i64>,
+    pub earlier_telegram_message_ids: Option<String>,
+    pub sender_name: Option<String>,
+    pub stopped_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
+impl ChatMessage {
+    // The ids of the Telegram messages the answer was sent in, in order. The
+    // answers sent before their earlier parts were kept only have the last one.
+    pub fn telegram_message_ids(&self) -> Vec<i64> {
+        let mut ids: Vec<i64> = self
+            .earlier_telegram_message_ids
+            .as_deref()
+            .and_then(|ids| serde_json::from_str(ids).ok())
+            .unwrap_or_default();
+
+        ids.extend(self.telegram_message_id);
+        ids
+    }
+}
+
+// A message matching a search, with an excerpt of its content where the
+// matched terms are wrapped in SEARCH_MATCH_START and SEARCH_MATCH_END.
 #[derive(Clone, FromRow, Debug)]
-pub struct NewChatMessage {
+pub struct ChatMessageSearchResult {
     pub id: i64,
-    pub content: String,
-    pub chat_id: i64,
-    pub chat_thread_id: i64,
     pub user_role: String,
+    pub inserted_at: chrono::DateTime<chrono::Utc>,
+    pub snippet: String,
+    pub thread_id: i64,
+    pub thread_title: Option<String>,
+    pub thread_first_message_id: Option<i64>,
+    pub thread_first_message: Option<String>,
+}
+
+pub const SEARCH_MATCH_START: char = '\u{2}';
+pub const SEARCH_MATCH_END: char = '\u{3}';
+
+// The content is stored encrypted when the encryption is enabled, and the
+// functions below encrypt and decrypt it for their callers.
+fn decrypted(mut chat_message: ChatMessage) -> anyhow::Result<ChatMessage> {
+    chat_message.content = encryption::decrypt(
+        encryption::MESSAGE_CONTENT,
+        chat_message.id,
+        &chat_message.content,
+    )?;
+
+    Ok(chat_message)
 }
 
 pub async fn insert_new_message(
     db_conn: &Pool<Sqlite>,
-    content: &String,
+    content: &str,
     chat_id: i64,
     chat_thread_id: i64,
     user_role: &str,
+    telegram_message_id: Option<i64>,
+    sender_name: Option<&str>,
 ) -> anyhow::Result<i64> {
     let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
 
+    // The new message continues the thread's branch: its parent is the latest
+    // message of the thread, or the message the thread was forked from.
     let _chat_message = sqlx::query(
-        "INSERT INTO chat_messages (id, content, chat_id, chat_thread_id, user_role) VALUES(?1, ?2, ?3, ?4, ?5)"
+        r#"INSERT INTO chat_messages
+          (id, content, chat_id, chat_thread_id, user_role, telegram_message_id, parent_message_id, sender_name)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, COALESCE(
+            (SELECT id FROM chat_messages WHERE chat_thread_id = ?4 ORDER BY inserted_at DESC LIMIT 1),
+            (SELECT parent_message_id FROM chat_threads WHERE id = ?4)
+        ), ?7)"#,
     )
     .bind(new_id)
-    .bind(content)
+    .bind(encryption::encrypt(
+        encryption::MESSAGE_CONTENT,
+        new_id,
+        content,
+    )?)
     .bind(chat_id)
     .bind(chat_thread_id)
     .bind(user_role)
+    .bind(telegram_message_id)
+    .bind(sender_name)
     .execute(db_conn)
     .await
     .context("Failed to create a chat message")?;
@@ -61,5 +115,368 @@ pub async fn get_chat_thread_messages(
         chat_thread_id
     ))?;
 
-    Ok(chat_messages)
+    chat_messages.into_iter().map(decrypted).collect()
+}
+
+// The messages of the thread's branch: the thread's own messages preceded by
+// the history of the thread it was forked from, oldest first.
+pub async fn get_chat_thread_history(
+    db_conn: &Pool<Sqlite>,
+    chat_thread_id: i64,
+) -> anyhow::Result<Vec<ChatMessage>> {
+    let chat_messages: Vec<ChatMessage> = sqlx::query_as(
+        r#"WITH RECURSIVE ancestry(id, depth) AS (
+            SELECT COALESCE(
+                (SELECT id FROM chat_messages WHERE chat_thread_id = ?1 ORDER BY inserted_at DESC LIMIT 1),
+                (SELECT parent_message_id FROM chat_threads WHERE id = ?1)
+            ), 0
+            UNION ALL
+            SELECT chat_messages.parent_message_id, ancestry.depth + 1
+            FROM chat_messages
+            INNER JOIN ancestry ON chat_messages.id = ancestry.id
+            WHERE chat_messages.parent_message_id IS NOT NULL
+        )
+        SELECT chat_messages.* FROM chat_messages
+        INNER JOIN ancestry ON chat_messages.id = ancestry.id
+        ORDER BY ancestry.depth DESC"#,
+    )
+    .bind(chat_thread_id)
+    .fetch_all(db_conn)
+    .await
+    .context(format!(
+        "Failed to get the history of the chat thread {}",
+        chat_thread_id
+    ))?;
+
+    chat_messages.into_iter().map(decrypted).collect()
+}
+
+pub async fn get_by_telegram_message_id(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    telegram_message_id: i64,
+) -> anyhow::Result<Option<ChatMessage>> {
+    let chat_message: Option<ChatMessage> = sqlx::query_as(
+        "SELECT * FROM chat_messages WHERE chat_id = ?1 AND telegram_message_id = ?2 LIMIT 1",
+    )
+    .bind(chat_id)
+    .bind(telegram_message_id)
+    .fetch_optional(db_conn)
+    .await
+    .context(format!(
+        "Failed to get the chat message for the Telegram message {}",
+        telegram_message_id
+    ))?;
+
+    chat_message.map(decrypted).transpose()
+}
+
+// The answer is found by the last of its messages, which has the buttons, and
+// the earlier ones are kept to edit the whole answer.
+pub async fn set_telegram_message_ids(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    telegram_message_ids: &[i64],
+) -> anyhow::Result<()> {
+    let Some((last_message_id, earlier_message_ids)) = telegram_message_ids.split_last() else {
+        return Ok(());
+    };
+
+    let earlier_message_ids = match earlier_message_ids {
+        [] => None,
+        ids => Some(serde_json::to_string(ids)?),
+    };
+
+    sqlx::query(
+        "UPDATE chat_messages SET telegram_message_id = ?1, earlier_telegram_message_ids = ?2 WHERE id = ?3",
+    )
+    .bind(last_message_id)
+    .bind(earlier_message_ids)
+    .bind(id)
+    .execute(db_conn)
+    .await
+    .context(format!(
+        "Failed to set the Telegram message ids of the chat message {}",
+        id
+    ))?;
+
+    Ok(())
+}
+
+pub async fn update_message_content(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    content: &str,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE chat_messages SET content = ?1 WHERE id = ?2")
+        .bind(encryption::encrypt(
+            encryption::MESSAGE_CONTENT,
+            id,
+            content,
+        )?)
+        .bind(id)
+        .execute(db_conn)
+        .await
+        .context(format!("Failed to update the chat message {}", id))?;
+
+    Ok(())
+}
+
+// The tokens of an answer generated again are added to the ones it used
+// before.
+pub async fn add_message_usage(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    llm_service: &str,
+    tokens: i64,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE chat_messages SET llm_service = ?1, tokens = COALESCE(tokens, 0) + ?2 WHERE id = ?3",
+    )
+    .bind(llm_service)
+    .bind(tokens)
+    .bind(id)
+    .execute(db_conn)
+    .await
+    .context(format!("Failed to record the usage of the chat message {}", id))?;
+
+    Ok(())
+}
+
+// Marks the user message whose answer was stopped with /stop. It's left out of
+// the messages sent to the model, which would otherwise get two user turns in
+// a row.
+pub async fn set_message_stopped(db_conn: &Pool<Sqlite>, id: i64) -> anyhow::Result<()> {
+    sqlx::query(
+        "UPDATE chat_messages SET stopped_at = STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW') WHERE id = ?1",
+    )
+    .bind(id)
+    .execute(db_conn)
+    .await
+    .context(format!("Failed to mark the chat message {} as stopped", id))?;
+
+    Ok(())
+}
+
+// An edited message is answered after all.
+pub async fn clear_message_stopped(db_conn: &Pool<Sqlite>, id: i64) -> anyhow::Result<()> {
+    sqlx::query("UPDATE chat_messages SET stopped_at = NULL WHERE id = ?1")
+        .bind(id)
+        .execute(db_conn)
+        .await
+        .context(format!(
+            "Failed to clear the stop of the chat message {}",
+            id
+        ))?;
+
+    Ok(())
+}
+
+pub async fn delete_messages(db_conn: &Pool<Sqlite>, ids: &[i64]) -> anyhow::Result<()> {
+    let mut tx = db_conn.begin().await?;
+
+    for id in ids {
+        sqlx::query("DELETE FROM chat_messages WHERE id = ?1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .context(format!("Failed to delete the chat message {}", id))?;
+    }
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+pub async fn get_chat_message(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<ChatMessage>> {
+    let row: Option<ChatMessage> =
+        sqlx::query_as("SELECT * FROM chat_messages WHERE id = ?1 AND chat_id = ?2")
+            .bind(id)
+            .bind(chat_id)
+            .fetch_optional(db_conn)
+            .await?;
+
+    row.map(decrypted).transpose()
+}
+
+// Every word of the query is quoted, so the FTS5 syntax characters typed by
+// the user are searched as text, and all the words have to match.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub async fn search_chat_messages(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    query: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<ChatMessageSearchResult>> {
+    let fts_query = fts_query(query);
+
+    if fts_query.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let rows: Vec<ChatMessageSearchResult> = sqlx::query_as(
+        r#"
+        SELECT
+            chat_messages.id,
+            chat_messages.user_role,
+            chat_messages.inserted_at,
+            snippet(chat_messages_fts, 0, ?3, ?4, '…', 16) AS snippet,
+            chat_threads.id AS thread_id,
+            chat_threads.title AS thread_title,
+            first_message.id AS thread_first_message_id,
+            first_message.content AS thread_first_message
+        FROM chat_messages_fts
+        JOIN chat_messages ON chat_messages.id = chat_messages_fts.rowid
+        JOIN chat_threads ON chat_threads.id = chat_messages.chat_thread_id
+        LEFT JOIN chat_messages first_message ON first_message.id = (
+            SELECT id FROM chat_messages
+            WHERE chat_thread_id = chat_threads.id AND user_role = 'user'
+            ORDER BY inserted_at
+            LIMIT 1
+        )
+        WHERE chat_messages_fts MATCH ?1 AND chat_messages.chat_id = ?2
+        ORDER BY chat_messages_fts.rank
+        LIMIT ?5
+        "#,
+    )
+    .bind(fts_query)
+    .bind(chat_id)
+    .bind(SEARCH_MATCH_START.to_string())
+    .bind(SEARCH_MATCH_END.to_string())
+    .bind(limit)
+    .fetch_all(db_conn)
+    .await
+    .context("Failed to search the messages")?;
+
+    rows.into_iter()
+        .map(|mut row| {
+            row.thread_title = encryption::decrypt_optional(
+                encryption::THREAD_TITLE,
+                row.thread_id,
+                row.thread_title,
+            )?;
+
+            if let Some(first_message_id) = row.thread_first_message_id {
+                row.thread_first_message = encryption::decrypt_optional(
+                    encryption::MESSAGE_CONTENT,
+                    first_message_id,
+                    row.thread_first_message,
+                )?;
+            }
+
+            Ok(row)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::db::chat_thread;
+
+    #[tokio::test]
+    async fn indexes_only_the_messages_that_arent_encrypted() {
+        let path =
+            std::env::temp_dir().join(format!("chat-message-search-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_conn = db::start(&format!("sqlite:{}", path.display())).await;
+        let keyring =
+            encryption::Keyring::parse("key:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=").unwrap();
+        let thread = chat_thread::get_or_create_chat_thread(&db_conn, 42)
+            .await
+            .unwrap();
+
+        let mut plaintext_ids = vec![];
+        for content in ["ENC: deploy notes", "Enc: deploy plan"] {
+            plaintext_ids.push(
+                insert_new_message(&db_conn, content, 42, thread.id, "user", None, None)
+                    .await
+                    .unwrap(),
+            );
+        }
+
+        // The encrypted content is stored as it would be with the encryption
+        // enabled.
+        let encrypted_id = insert_new_message(
+            &db_conn,
+            "deploy secrets",
+            42,
+            thread.id,
+            "user",
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+        let encrypted = encryption::encrypt_with(
+            Some(&keyring),
+            encryption::MESSAGE_CONTENT,
+            encrypted_id,
+            "deploy secrets",
+        )
+        .unwrap();
+        sqlx::query("UPDATE chat_messages SET content = ?1 WHERE id = ?2")
+            .bind(&encrypted)
+            .bind(encrypted_id)
+            .execute(&db_conn)
+            .await
+            .unwrap();
+
+        let mut found_ids: Vec<i64> = search_chat_messages(&db_conn, 42, "deploy", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|result| result.id)
+            .collect();
+        found_ids.sort();
+        plaintext_ids.sort();
+
+        assert_eq!(found_ids, plaintext_ids);
+        assert!(search_chat_messages(&db_conn, 42, "secrets", 10)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_the_telegram_messages_of_every_part() {
+        let path =
+            std::env::temp_dir().join(format!("chat-message-parts-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_conn = db::start(&format!("sqlite:{}", path.display())).await;
+        let thread = chat_thread::get_or_create_chat_thread(&db_conn, 42)
+            .await
+            .unwrap();
+        let id = insert_new_message(&db_conn, "Hello", 42, thread.id, "assistant", None, None)
+            .await
+            .unwrap();
+
+        set_telegram_message_ids(&db_conn, id, &[10, 11, 12])
+            .await
+            .unwrap();
+        let answer = get_by_telegram_message_id(&db_conn, 42, 12)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(answer.telegram_message_ids(), vec![10, 11, 12]);
+
+        set_telegram_message_ids(&db_conn, id, &[10]).await.unwrap();
+        let answer = get_by_telegram_message_id(&db_conn, 42, 10)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(answer.telegram_message_ids(), vec![10]);
+    }
 }
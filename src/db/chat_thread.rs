@@ -1,12 +1,34 @@
+use crate::encryption;
+use crate::thread_import::ImportedConversation;
 use sqlx::{FromRow, Pool, Sqlite};
 extern crate rand;
 use rand::Rng;
 
 #[derive(Clone, FromRow, Debug)]
 pub struct ChatThread {
+    pub id: i64,
+    pub persona_id: Option<i64>,
+    pub title: Option<String>,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct ChatThreadSummary {
     pub id: i64,
     pub is_current: bool,
-    pub chat_id: i64,
+    pub title: Option<String>,
+    pub first_message_id: Option<i64>,
+    pub first_message: Option<String>,
+    pub message_count: i64,
+    pub last_message_at: Option<String>,
+}
+
+// The title is stored encrypted when the encryption is enabled, and the
+// functions below encrypt and decrypt it for their callers.
+fn decrypted(mut chat_thread: ChatThread) -> anyhow::Result<ChatThread> {
+    chat_thread.title =
+        encryption::decrypt_optional(encryption::THREAD_TITLE, chat_thread.id, chat_thread.title)?;
+
+    Ok(chat_thread)
 }
 
 pub async fn close_chat_thread(
@@ -20,7 +42,7 @@ pub async fn close_chat_thread(
     .fetch_optional(db_conn)
     .await?;
 
-    Ok(row)
+    row.map(decrypted).transpose()
 }
 
 pub async fn get_or_create_chat_thread(
@@ -37,7 +59,7 @@ pub async fn get_or_create_chat_thread(
     .await?;
 
     match chat_thread_optional {
-        Some(chat_thread) => Ok(chat_thread),
+        Some(chat_thread) => decrypted(chat_thread),
         None => {
             let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
 
@@ -54,3 +76,342 @@ pub async fn get_or_create_chat_thread(
         }
     }
 }
+
+pub async fn set_chat_thread_persona(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    persona_id: Option<i64>,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE chat_threads SET persona_id = ?1 WHERE id = ?2")
+        .bind(persona_id)
+        .bind(id)
+        .execute(db_conn)
+        .await?;
+
+    Ok(())
+}
+
+// Replaces the current thread with a new one that continues the history from
+// the given message.
+pub async fn fork_chat_thread(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    parent_message_id: i64,
+    persona_id: Option<i64>,
+) -> anyhow::Result<ChatThread> {
+    let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+    let mut tx = db_conn.begin().await?;
+
+    sqlx::query(
+        "UPDATE chat_threads SET is_current = false WHERE chat_id = ?1 AND is_current = true",
+    )
+    .bind(chat_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let chat_thread = sqlx::query_as::<_, ChatThread>(
+        r#"INSERT INTO chat_threads (id, chat_id, is_current, persona_id, parent_message_id)
+        VALUES(?1, ?2, ?3, ?4, ?5) RETURNING *"#,
+    )
+    .bind(new_id)
+    .bind(chat_id)
+    .bind(true)
+    .bind(persona_id)
+    .bind(parent_message_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    decrypted(chat_thread)
+}
+
+pub async fn get_chat_thread(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<ChatThread>> {
+    let row: Option<ChatThread> =
+        sqlx::query_as("SELECT * FROM chat_threads WHERE id = ?1 AND chat_id = ?2")
+            .bind(id)
+            .bind(chat_id)
+            .fetch_optional(db_conn)
+            .await?;
+
+    row.map(decrypted).transpose()
+}
+
+pub async fn count_chat_threads(db_conn: &Pool<Sqlite>, chat_id: i64) -> anyhow::Result<i64> {
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM chat_threads WHERE chat_id = ?1")
+        .bind(chat_id)
+        .fetch_one(db_conn)
+        .await?;
+
+    Ok(count)
+}
+
+const CHAT_THREAD_SUMMARY_SELECT: &str = r#"
+    SELECT
+        chat_threads.id,
+        COALESCE(chat_threads.is_current, false) AS is_current,
+        chat_threads.title,
+        first_message.id AS first_message_id,
+        first_message.content AS first_message,
+        COUNT(chat_messages.id) AS message_count,
+        MAX(chat_messages.inserted_at) AS last_message_at
+    FROM chat_threads
+    LEFT JOIN chat_messages ON chat_messages.chat_thread_id = chat_threads.id
+    LEFT JOIN chat_messages first_message ON first_message.id = (
+        SELECT id FROM chat_messages
+        WHERE chat_thread_id = chat_threads.id AND user_role = 'user'
+        ORDER BY inserted_at
+        LIMIT 1
+    )
+"#;
+
+fn decrypted_summary(mut summary: ChatThreadSummary) -> anyhow::Result<ChatThreadSummary> {
+    summary.title =
+        encryption::decrypt_optional(encryption::THREAD_TITLE, summary.id, summary.title)?;
+
+    if let Some(first_message_id) = summary.first_message_id {
+        summary.first_message = encryption::decrypt_optional(
+            encryption::MESSAGE_CONTENT,
+            first_message_id,
+            summary.first_message,
+        )?;
+    }
+
+    Ok(summary)
+}
+
+// The current thread first, then the most recently active ones. The messages
+// inherited from a forked thread aren't counted.
+pub async fn get_chat_thread_summaries(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<ChatThreadSummary>> {
+    let rows: Vec<ChatThreadSummary> = sqlx::query_as(&format!(
+        r#"{}
+        WHERE chat_threads.chat_id = ?1
+        GROUP BY chat_threads.id
+        ORDER BY is_current DESC, last_message_at DESC
+        LIMIT ?2 OFFSET ?3"#,
+        CHAT_THREAD_SUMMARY_SELECT
+    ))
+    .bind(chat_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(db_conn)
+    .await?;
+
+    rows.into_iter().map(decrypted_summary).collect()
+}
+
+pub async fn get_chat_thread_summary(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<ChatThreadSummary>> {
+    let row: Option<ChatThreadSummary> = sqlx::query_as(&format!(
+        r#"{}
+        WHERE chat_threads.chat_id = ?1 AND chat_threads.id = ?2
+        GROUP BY chat_threads.id"#,
+        CHAT_THREAD_SUMMARY_SELECT
+    ))
+    .bind(chat_id)
+    .bind(id)
+    .fetch_optional(db_conn)
+    .await?;
+
+    row.map(decrypted_summary).transpose()
+}
+
+pub async fn set_chat_thread_title(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+    title: &str,
+) -> anyhow::Result<Option<ChatThread>> {
+    let row: Option<ChatThread> = sqlx::query_as(
+        "UPDATE chat_threads SET title = ?1 WHERE id = ?2 AND chat_id = ?3 RETURNING *",
+    )
+    .bind(encryption::encrypt(encryption::THREAD_TITLE, id, title)?)
+    .bind(id)
+    .bind(chat_id)
+    .fetch_optional(db_conn)
+    .await?;
+
+    row.map(decrypted).transpose()
+}
+
+// The current thread is unset first, since a chat can only have one current
+// thread.
+pub async fn resume_chat_thread(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<ChatThread>> {
+    let mut tx = db_conn.begin().await?;
+
+    sqlx::query(
+        "UPDATE chat_threads SET is_current = false WHERE chat_id = ?1 AND is_current = true AND id != ?2",
+    )
+    .bind(chat_id)
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    let row: Option<ChatThread> = sqlx::query_as(
+        "UPDATE chat_threads SET is_current = true WHERE id = ?1 AND chat_id = ?2 RETURNING *",
+    )
+    .bind(id)
+    .bind(chat_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if row.is_some() {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+    }
+
+    row.map(decrypted).transpose()
+}
+
+// The threads forked from a message of the thread, which would lose a part of
+// their history if the thread was deleted.
+pub async fn count_forked_threads(db_conn: &Pool<Sqlite>, id: i64) -> anyhow::Result<i64> {
+    let count: i64 = sqlx::query_scalar(
+        r#"
+        SELECT COUNT(*) FROM chat_threads
+        WHERE chat_threads.id != ?1
+          AND chat_threads.parent_message_id IN (
+            SELECT chat_messages.id FROM chat_messages WHERE chat_messages.chat_thread_id = ?1
+          )
+        "#,
+    )
+    .bind(id)
+    .fetch_one(db_conn)
+    .await?;
+
+    Ok(count)
+}
+
+// The threads forked from any of the messages.
+pub async fn count_threads_forked_from(
+    db_conn: &Pool<Sqlite>,
+    message_ids: &[i64],
+) -> anyhow::Result<i64> {
+    let mut count = 0;
+
+    for message_id in message_ids {
+        let forked: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM chat_threads WHERE parent_message_id = ?1")
+                .bind(message_id)
+                .fetch_one(db_conn)
+                .await?;
+
+        count += forked;
+    }
+
+    Ok(count)
+}
+
+pub async fn delete_chat_thread(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<ChatThread>> {
+    let mut tx = db_conn.begin().await?;
+
+    let row: Option<ChatThread> =
+        sqlx::query_as("DELETE FROM chat_threads WHERE id = ?1 AND chat_id = ?2 RETURNING *")
+            .bind(id)
+            .bind(chat_id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    if row.is_some() {
+        sqlx::query("DELETE FROM chat_messages WHERE chat_thread_id = ?1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    row.map(decrypted).transpose()
+}
+
+// Keeps the title the user may have given the thread in the meantime.
+pub async fn set_missing_chat_thread_title(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    title: &str,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE chat_threads SET title = ?1 WHERE id = ?2 AND title IS NULL")
+        .bind(encryption::encrypt(encryption::THREAD_TITLE, id, title)?)
+        .bind(id)
+        .execute(db_conn)
+        .await?;
+
+    Ok(())
+}
+
+// Adds a closed thread with the conversation's messages, keeping their
+// original timestamps and order. The import time is kept for the retention.
+pub async fn import_chat_thread(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    conversation: &ImportedConversation,
+) -> anyhow::Result<ChatThread> {
+    let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+    let mut tx = db_conn.begin().await?;
+
+    let chat_thread = sqlx::query_as::<_, ChatThread>(
+        r#"INSERT INTO chat_threads (id, chat_id, is_current, title, imported_at)
+        VALUES(?1, ?2, ?3, ?4, STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW')) RETURNING *"#,
+    )
+    .bind(new_id)
+    .bind(chat_id)
+    .bind(false)
+    .bind(encryption::encrypt(
+        encryption::THREAD_TITLE,
+        new_id,
+        &conversation.title,
+    )?)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let mut parent_message_id: Option<i64> = None;
+
+    for message in &conversation.messages {
+        let message_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+
+        sqlx::query(
+            r#"INSERT INTO chat_messages (id, content, chat_id, chat_thread_id, user_role, inserted_at, parent_message_id)
+            VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7)"#,
+        )
+        .bind(message_id)
+        .bind(encryption::encrypt(
+            encryption::MESSAGE_CONTENT,
+            message_id,
+            &message.content,
+        )?)
+        .bind(chat_id)
+        .bind(chat_thread.id)
+        .bind(&message.role)
+        .bind(message.inserted_at.format("%Y-%m-%d %H:%M:%S%.3f").to_string())
+        .bind(parent_message_id)
+        .execute(&mut *tx)
+        .await?;
+
+        parent_message_id = Some(message_id);
+    }
+
+    tx.commit().await?;
+
+    decrypted(chat_thread)
+}
@@ -0,0 +1,129 @@
+use anyhow::Context;
+use sqlx::{FromRow, Pool, Sqlite};
+extern crate rand;
+use rand::Rng;
+
+#[derive(Clone, FromRow, Debug)]
+pub struct Document {
+    pub id: i64,
+    pub name: String,
+    pub chunk_count: i64,
+    pub inserted_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct DocumentChunk {
+    pub document_name: String,
+    pub chunk_index: i64,
+    pub content: String,
+    pub embedding: Vec<u8>,
+}
+
+pub async fn insert_document(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    name: &str,
+    embedding_model: &str,
+    chunks: &[(String, Vec<u8>)],
+) -> anyhow::Result<Document> {
+    let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+    let mut tx = db_conn.begin().await?;
+
+    let document = sqlx::query_as::<_, Document>(
+        r#"INSERT INTO documents (id, chat_id, name, embedding_model, chunk_count)
+        VALUES(?1, ?2, ?3, ?4, ?5) RETURNING *"#,
+    )
+    .bind(new_id)
+    .bind(chat_id)
+    .bind(name)
+    .bind(embedding_model)
+    .bind(chunks.len() as i64)
+    .fetch_one(&mut *tx)
+    .await
+    .context(format!("Failed to create the document {}", name))?;
+
+    for (chunk_index, (content, embedding)) in chunks.iter().enumerate() {
+        let chunk_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+
+        sqlx::query(
+            r#"INSERT INTO document_chunks (id, document_id, chat_id, chunk_index, content, embedding)
+            VALUES(?1, ?2, ?3, ?4, ?5, ?6)"#,
+        )
+        .bind(chunk_id)
+        .bind(document.id)
+        .bind(chat_id)
+        .bind(chunk_index as i64)
+        .bind(content)
+        .bind(embedding)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to create a document chunk")?;
+    }
+
+    tx.commit().await?;
+
+    Ok(document)
+}
+
+pub async fn get_chat_documents(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+) -> anyhow::Result<Vec<Document>> {
+    let documents: Vec<Document> =
+        sqlx::query_as("SELECT * FROM documents WHERE chat_id = ?1 ORDER BY inserted_at ASC")
+            .bind(chat_id)
+            .fetch_all(db_conn)
+            .await
+            .context(format!("Failed to get the documents for chat {}", chat_id))?;
+
+    Ok(documents)
+}
+
+pub async fn get_chat_document_chunks(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    embedding_model: &str,
+) -> anyhow::Result<Vec<DocumentChunk>> {
+    let chunks: Vec<DocumentChunk> = sqlx::query_as(
+        r#"SELECT documents.name AS document_name, document_chunks.chunk_index,
+            document_chunks.content, document_chunks.embedding
+        FROM document_chunks
+        INNER JOIN documents ON documents.id = document_chunks.document_id
+        WHERE document_chunks.chat_id = ?1 AND documents.embedding_model = ?2"#,
+    )
+    .bind(chat_id)
+    .bind(embedding_model)
+    .fetch_all(db_conn)
+    .await
+    .context(format!(
+        "Failed to get the document chunks for chat {}",
+        chat_id
+    ))?;
+
+    Ok(chunks)
+}
+
+pub async fn delete_document(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<Document>> {
+    let mut tx = db_conn.begin().await?;
+
+    let document: Option<Document> =
+        sqlx::query_as("DELETE FROM documents WHERE chat_id = ?1 AND id = ?2 RETURNING *")
+            .bind(chat_id)
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    sqlx::query("DELETE FROM document_chunks WHERE chat_id = ?1 AND document_id = ?2")
+        .bind(chat_id)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(document)
+}
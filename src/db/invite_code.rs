@@ -0,0 +1,73 @@
+use crate::db::user::{User, STATUS_ACTIVE, STATUS_BLOCKED};
+use anyhow::Context;
+use sqlx::{FromRow, Pool, Sqlite};
+extern crate rand;
+use rand::Rng;
+
+#[derive(Clone, FromRow, Debug)]
+pub struct InviteCode {
+    pub id: i64,
+    pub code: String,
+}
+
+pub async fn create_invite_code(
+    db_conn: &Pool<Sqlite>,
+    code: &str,
+    created_by: i64,
+    max_uses: i64,
+) -> anyhow::Result<InviteCode> {
+    let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+
+    let invite_code: InviteCode = sqlx::query_as(
+        "INSERT INTO invite_codes (id, code, created_by, max_uses) VALUES(?1, ?2, ?3, ?4) RETURNING *",
+    )
+    .bind(new_id)
+    .bind(code)
+    .bind(created_by)
+    .bind(max_uses)
+    .fetch_one(db_conn)
+    .await
+    .context("Failed to create the invite code")?;
+
+    Ok(invite_code)
+}
+
+// Uses the code up once and activates the user. Returns None when the code
+// doesn't exist, is used up, or the user is blocked.
+pub async fn redeem_invite_code(
+    db_conn: &Pool<Sqlite>,
+    code: &str,
+    user_id: i64,
+) -> anyhow::Result<Option<User>> {
+    let mut tx = db_conn.begin().await?;
+
+    let invite_code: Option<InviteCode> = sqlx::query_as(
+        "UPDATE invite_codes SET uses = uses + 1 WHERE code = ?1 AND uses < max_uses RETURNING *",
+    )
+    .bind(code)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(invite_code) = invite_code else {
+        tx.rollback().await?;
+        return Ok(None);
+    };
+
+    let user: Option<User> = sqlx::query_as(
+        "UPDATE users SET status = ?1, invite_code_id = ?2 WHERE id = ?3 AND status != ?4 RETURNING *",
+    )
+    .bind(STATUS_ACTIVE)
+    .bind(invite_code.id)
+    .bind(user_id)
+    .bind(STATUS_BLOCKED)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if user.is_some() {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+    }
+
+    Ok(user)
+}
@@ -0,0 +1,98 @@
+use crate::encryption;
+use anyhow::Context;
+use sqlx::{FromRow, Pool, Sqlite};
+extern crate rand;
+use rand::Rng;
+
+#[derive(Clone, FromRow, Debug)]
+pub struct Memory {
+    pub id: i64,
+    pub content: String,
+    pub source: String,
+}
+
+// The content is stored encrypted when the encryption is enabled, and the
+// functions below encrypt and decrypt it for their callers.
+fn decrypted(mut memory: Memory) -> anyhow::Result<Memory> {
+    memory.content = encryption::decrypt(encryption::MEMORY_CONTENT, memory.id, &memory.content)?;
+
+    Ok(memory)
+}
+
+// Returns None when the chat already has the memory. The encrypted contents
+// differ even when equal, so they're compared once decrypted.
+pub async fn insert_memory(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    content: &str,
+    source: &str,
+) -> anyhow::Result<Option<Memory>> {
+    if get_chat_memories(db_conn, chat_id)
+        .await?
+        .iter()
+        .any(|memory| memory.content == content)
+    {
+        return Ok(None);
+    }
+
+    let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+
+    let memory: Option<Memory> = sqlx::query_as(
+        r#"INSERT INTO memories (id, chat_id, content, source)
+        VALUES(?1, ?2, ?3, ?4)
+          ON CONFLICT (chat_id, content)
+          DO NOTHING
+        RETURNING *"#,
+    )
+    .bind(new_id)
+    .bind(chat_id)
+    .bind(encryption::encrypt(
+        encryption::MEMORY_CONTENT,
+        new_id,
+        content,
+    )?)
+    .bind(source)
+    .fetch_optional(db_conn)
+    .await
+    .context("Failed to create a memory")?;
+
+    memory.map(decrypted).transpose()
+}
+
+pub async fn get_chat_memories(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+) -> anyhow::Result<Vec<Memory>> {
+    let memories: Vec<Memory> =
+        sqlx::query_as("SELECT * FROM memories WHERE chat_id = ?1 ORDER BY inserted_at ASC")
+            .bind(chat_id)
+            .fetch_all(db_conn)
+            .await
+            .context(format!("Failed to get the memories for chat {}", chat_id))?;
+
+    memories.into_iter().map(decrypted).collect()
+}
+
+pub async fn delete_memory(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<Memory>> {
+    let memory: Option<Memory> =
+        sqlx::query_as("DELETE FROM memories WHERE chat_id = ?1 AND id = ?2 RETURNING *")
+            .bind(chat_id)
+            .bind(id)
+            .fetch_optional(db_conn)
+            .await?;
+
+    memory.map(decrypted).transpose()
+}
+
+pub async fn delete_chat_memories(db_conn: &Pool<Sqlite>, chat_id: i64) -> anyhow::Result<u64> {
+    let result = sqlx::query("DELETE FROM memories WHERE chat_id = ?1")
+        .bind(chat_id)
+        .execute(db_conn)
+        .await?;
+
+    Ok(result.rows_affected())
+}
@@ -1,4 +1,4 @@
-use sqlx::{migrate::MigrateDatabase, Pool, Sqlite};
+use sqlx::{migrate::MigrateDatabase, Pool, Row, Sqlite};
 
 pub async fn create_db_if_doesnt_exists(url: &String) {
     let db_exists = Sqlite::database_exists(url).await.unwrap_or(false);
@@ -48,9 +48,283 @@ pub async fn run_all_migrations(db_conn: &Pool<Sqlite>) {
 
         CREATE INDEX IF NOT EXISTS idx_chat_messages_inserted_at
               ON chat_messages (inserted_at);
+
+        CREATE TABLE IF NOT EXISTS personas (
+            id INTEGER PRIMARY KEY NOT NULL,
+            chat_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            system_prompt TEXT NOT NULL,
+            llm_service TEXT,
+            completion_model TEXT,
+            temperature REAL,
+            max_tokens INTEGER,
+            inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_personas_chat_id_name
+              ON personas (chat_id, name);
+
+        CREATE TABLE IF NOT EXISTS documents (
+            id INTEGER PRIMARY KEY NOT NULL,
+            chat_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            embedding_model TEXT NOT NULL,
+            chunk_count INTEGER NOT NULL,
+            inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_documents_chat_id
+              ON documents (chat_id);
+
+        CREATE TABLE IF NOT EXISTS document_chunks (
+            id INTEGER PRIMARY KEY NOT NULL,
+            document_id INTEGER NOT NULL,
+            chat_id INTEGER NOT NULL,
+            chunk_index INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            embedding BLOB NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_document_chunks_chat_id
+              ON document_chunks (chat_id);
+
+        CREATE TABLE IF NOT EXISTS memories (
+            id INTEGER PRIMARY KEY NOT NULL,
+            chat_id INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            source TEXT NOT NULL,
+            inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_memories_chat_id_content
+              ON memories (chat_id, content);
+
+        CREATE TABLE IF NOT EXISTS model_comparisons (
+            id INTEGER PRIMARY KEY NOT NULL,
+            chat_id INTEGER NOT NULL,
+            prompt TEXT NOT NULL,
+            winner_index INTEGER,
+            inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_model_comparisons_chat_id
+              ON model_comparisons (chat_id);
+
+        CREATE TABLE IF NOT EXISTS model_comparison_answers (
+            id INTEGER PRIMARY KEY NOT NULL,
+            comparison_id INTEGER NOT NULL,
+            answer_index INTEGER NOT NULL,
+            llm_service TEXT NOT NULL,
+            completion_model TEXT NOT NULL,
+            content TEXT NOT NULL,
+            failed BOOLEAN NOT NULL,
+            latency_ms INTEGER NOT NULL,
+            prompt_tokens INTEGER,
+            completion_tokens INTEGER
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_model_comparison_answers_index
+              ON model_comparison_answers (comparison_id, answer_index);
+
+        CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY NOT NULL,
+            first_name TEXT NOT NULL,
+            username TEXT,
+            status TEXT NOT NULL,
+            invite_code_id INTEGER,
+            inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+        );
+
+        CREATE TABLE IF NOT EXISTS invite_codes (
+            id INTEGER PRIMARY KEY NOT NULL,
+            code TEXT NOT NULL,
+            created_by INTEGER NOT NULL,
+            max_uses INTEGER NOT NULL,
+            uses INTEGER NOT NULL DEFAULT 0,
+            inserted_at DATETIME DEFAULT(STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+        );
+
+        CREATE UNIQUE INDEX IF NOT EXISTS idx_invite_codes_code
+              ON invite_codes (code);
+
+        CREATE TABLE IF NOT EXISTS daily_usage (
+            subject TEXT NOT NULL,
+            subject_id INTEGER NOT NULL,
+            day TEXT NOT NULL,
+            messages INTEGER NOT NULL DEFAULT 0,
+            tokens INTEGER NOT NULL DEFAULT 0,
+            PRIMARY KEY (subject, subject_id, day)
+        );
+
+        CREATE TABLE IF NOT EXISTS rate_limit_buckets (
+            subject TEXT NOT NULL,
+            subject_id INTEGER NOT NULL,
+            tokens REAL NOT NULL,
+            updated_at REAL NOT NULL,
+            PRIMARY KEY (subject, subject_id)
+        );
       ",
     )
     .execute(db_conn)
     .await
     .unwrap();
+
+    add_column_if_missing(db_conn, "chat_bots", "persona_id", "INTEGER").await;
+    add_column_if_missing(db_conn, "chat_threads", "persona_id", "INTEGER").await;
+    add_column_if_missing(db_conn, "personas", "examples", "TEXT").await;
+    add_column_if_missing(db_conn, "chat_bots", "llm_service", "TEXT").await;
+    add_column_if_missing(db_conn, "chat_messages", "telegram_message_id", "INTEGER").await;
+    add_column_if_missing(db_conn, "chat_threads", "parent_message_id", "INTEGER").await;
+    add_column_if_missing(db_conn, "chat_threads", "title", "TEXT").await;
+    add_column_if_missing(db_conn, "chat_bots", "retention_days", "INTEGER").await;
+    add_column_if_missing(
+        db_conn,
+        "chat_bots",
+        "group_context",
+        "BOOLEAN NOT NULL DEFAULT false",
+    )
+    .await;
+    add_column_if_missing(db_conn, "chat_messages", "sender_name", "TEXT").await;
+    add_column_if_missing(
+        db_conn,
+        "chat_messages",
+        "earlier_telegram_message_ids",
+        "TEXT",
+    )
+    .await;
+    add_column_if_missing(db_conn, "users", "messages_per_minute", "INTEGER").await;
+    add_column_if_missing(db_conn, "users", "daily_messages", "INTEGER").await;
+    add_column_if_missing(db_conn, "users", "daily_tokens", "INTEGER").await;
+    add_column_if_missing(db_conn, "users", "last_seen_at", "DATETIME").await;
+    add_column_if_missing(db_conn, "chat_messages", "llm_service", "TEXT").await;
+    add_column_if_missing(db_conn, "chat_messages", "tokens", "INTEGER").await;
+    add_column_if_missing(db_conn, "chat_messages", "stopped_at", "DATETIME").await;
+    add_column_if_missing(db_conn, "chat_threads", "imported_at", "DATETIME").await;
+
+    // The existing threads are linear, so every message's parent is the
+    // message before it in the same thread.
+    if add_column_if_missing(db_conn, "chat_messages", "parent_message_id", "INTEGER").await {
+        sqlx::query(
+            "
+            UPDATE chat_messages SET parent_message_id = (
+                SELECT previous.id FROM chat_messages AS previous
+                WHERE previous.chat_thread_id = chat_messages.chat_thread_id
+                  AND previous.inserted_at < chat_messages.inserted_at
+                ORDER BY previous.inserted_at DESC
+                LIMIT 1
+            )
+          ",
+        )
+        .execute(db_conn)
+        .await
+        .unwrap();
+    }
+
+    // The full-text index of the messages is an external content table kept in
+    // sync by the triggers, and it's filled with the existing messages when
+    // it's created. The encrypted contents aren't indexed, and the triggers
+    // are replaced on every start so the existing databases get their latest
+    // version. The prefix is compared exactly, since LIKE ignores the case.
+    let fts_exists = table_exists(db_conn, "chat_messages_fts").await;
+    let skipped_case_variants: bool = sqlx::query_scalar(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'trigger' AND name = 'chat_messages_fts_insert' AND sql LIKE '%NOT LIKE%'",
+    )
+    .fetch_one(db_conn)
+    .await
+    .unwrap();
+
+    sqlx::query(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS chat_messages_fts USING fts5(
+            content,
+            content='chat_messages',
+            content_rowid='id',
+            tokenize='unicode61 remove_diacritics 2'
+        );
+
+        DROP TRIGGER IF EXISTS chat_messages_fts_insert;
+        DROP TRIGGER IF EXISTS chat_messages_fts_delete;
+        DROP TRIGGER IF EXISTS chat_messages_fts_update;
+
+        CREATE TRIGGER chat_messages_fts_insert AFTER INSERT ON chat_messages BEGIN
+            INSERT INTO chat_messages_fts (rowid, content)
+                SELECT new.id, new.content WHERE substr(new.content, 1, 4) <> 'enc:';
+        END;
+
+        CREATE TRIGGER chat_messages_fts_delete AFTER DELETE ON chat_messages BEGIN
+            INSERT INTO chat_messages_fts (chat_messages_fts, rowid, content)
+                SELECT 'delete', old.id, old.content WHERE substr(old.content, 1, 4) <> 'enc:';
+        END;
+
+        CREATE TRIGGER chat_messages_fts_update AFTER UPDATE OF content ON chat_messages BEGIN
+            INSERT INTO chat_messages_fts (chat_messages_fts, rowid, content)
+                SELECT 'delete', old.id, old.content WHERE substr(old.content, 1, 4) <> 'enc:';
+            INSERT INTO chat_messages_fts (rowid, content)
+                SELECT new.id, new.content WHERE substr(new.content, 1, 4) <> 'enc:';
+        END;
+      "#,
+    )
+    .execute(db_conn)
+    .await
+    .unwrap();
+
+    if !fts_exists {
+        sqlx::query(
+            "INSERT INTO chat_messages_fts (rowid, content) SELECT id, content FROM chat_messages WHERE substr(content, 1, 4) <> 'enc:'",
+        )
+        .execute(db_conn)
+        .await
+        .unwrap();
+    }
+
+    // The previous triggers left out the messages starting with "ENC:" or any
+    // other case of the prefix.
+    if fts_exists && skipped_case_variants {
+        sqlx::query(
+            "INSERT INTO chat_messages_fts (rowid, content) SELECT id, content FROM chat_messages WHERE content LIKE 'enc:%' AND substr(content, 1, 4) <> 'enc:'",
+        )
+        .execute(db_conn)
+        .await
+        .unwrap();
+    }
+}
+
+// SQLite has no `ADD COLUMN IF NOT EXISTS`, so columns added to the existing
+// tables are checked against the table info first. Returns whether the column
+// was added.
+async fn add_column_if_missing(
+    db_conn: &Pool<Sqlite>,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> bool {
+    let columns = sqlx::query(&format!("PRAGMA table_info({})", table))
+        .fetch_all(db_conn)
+        .await
+        .unwrap();
+
+    let column_exists = columns.iter().any(|c| c.get::<String, _>("name") == column);
+
+    if !column_exists {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(db_conn)
+        .await
+        .unwrap();
+    }
+
+    !column_exists
+}
+
+async fn table_exists(db_conn: &Pool<Sqlite>, table: &str) -> bool {
+    let count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?1")
+            .bind(table)
+            .fetch_one(db_conn)
+            .await
+            .unwrap();
+
+    count > 0
 }
@@ -0,0 +1,184 @@
+use crate::encryption;
+use anyhow::Context;
+use sqlx::{FromRow, Pool, Sqlite};
+extern crate rand;
+use rand::Rng;
+
+#[derive(Clone, FromRow, Debug)]
+pub struct ModelComparison {
+    pub id: i64,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct ComparisonAnswer {
+    pub answer_index: i64,
+    pub llm_service: String,
+    pub completion_model: String,
+    pub content: String,
+    pub failed: bool,
+    pub latency_ms: i64,
+    pub prompt_tokens: Option<i64>,
+    pub completion_tokens: Option<i64>,
+}
+
+// An answer with the id of its row, which its content is bound to.
+#[derive(FromRow)]
+struct StoredComparisonAnswer {
+    id: i64,
+    #[sqlx(flatten)]
+    answer: ComparisonAnswer,
+}
+
+pub async fn insert_model_comparison(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    prompt: &str,
+    answers: &[ComparisonAnswer],
+) -> anyhow::Result<ModelComparison> {
+    let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+    let mut tx = db_conn.begin().await?;
+
+    let comparison = sqlx::query_as::<_, ModelComparison>(
+        r#"INSERT INTO model_comparisons (id, chat_id, prompt)
+        VALUES(?1, ?2, ?3) RETURNING *"#,
+    )
+    .bind(new_id)
+    .bind(chat_id)
+    .bind(encryption::encrypt(
+        encryption::COMPARISON_PROMPT,
+        new_id,
+        prompt,
+    )?)
+    .fetch_one(&mut *tx)
+    .await
+    .context("Failed to create a model comparison")?;
+
+    for answer in answers {
+        let answer_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+
+        sqlx::query(
+            r#"INSERT INTO model_comparison_answers
+              (id, comparison_id, answer_index, llm_service, completion_model, content, failed,
+               latency_ms, prompt_tokens, completion_tokens)
+            VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"#,
+        )
+        .bind(answer_id)
+        .bind(comparison.id)
+        .bind(answer.answer_index)
+        .bind(&answer.llm_service)
+        .bind(&answer.completion_model)
+        .bind(encryption::encrypt(
+            encryption::COMPARISON_ANSWER_CONTENT,
+            answer_id,
+            &answer.content,
+        )?)
+        .bind(answer.failed)
+        .bind(answer.latency_ms)
+        .bind(answer.prompt_tokens)
+        .bind(answer.completion_tokens)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to create a model comparison answer")?;
+    }
+
+    tx.commit().await?;
+
+    Ok(comparison)
+}
+
+pub async fn get_comparison_answer(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    comparison_id: i64,
+    answer_index: i64,
+) -> anyhow::Result<Option<ComparisonAnswer>> {
+    let answer: Option<StoredComparisonAnswer> = sqlx::query_as(
+        r#"SELECT model_comparison_answers.* FROM model_comparison_answers
+        INNER JOIN model_comparisons
+          ON model_comparisons.id = model_comparison_answers.comparison_id
+        WHERE model_comparisons.chat_id = ?1 AND model_comparisons.id = ?2
+          AND model_comparison_answers.answer_index = ?3"#,
+    )
+    .bind(chat_id)
+    .bind(comparison_id)
+    .bind(answer_index)
+    .fetch_optional(db_conn)
+    .await
+    .context(format!(
+        "Failed to get the answer {} of the comparison {}",
+        answer_index, comparison_id
+    ))?;
+
+    answer
+        .map(|StoredComparisonAnswer { id, mut answer }| {
+            answer.content =
+                encryption::decrypt(encryption::COMPARISON_ANSWER_CONTENT, id, &answer.content)?;
+
+            Ok(answer)
+        })
+        .transpose()
+}
+
+pub async fn set_comparison_winner(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    comparison_id: i64,
+    winner_index: i64,
+) -> anyhow::Result<Option<ModelComparison>> {
+    let comparison: Option<ModelComparison> = sqlx::query_as(
+        r#"UPDATE model_comparisons SET winner_index = ?1
+        WHERE chat_id = ?2 AND id = ?3 RETURNING *"#,
+    )
+    .bind(winner_index)
+    .bind(chat_id)
+    .bind(comparison_id)
+    .fetch_optional(db_conn)
+    .await
+    .context(format!(
+        "Failed to set the winner of the comparison {}",
+        comparison_id
+    ))?;
+
+    Ok(comparison)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    #[tokio::test]
+    async fn reads_back_the_answers_of_a_comparison() {
+        let path = std::env::temp_dir().join(format!("model-comparison-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_conn = db::start(&format!("sqlite:{}", path.display())).await;
+        let answer = |answer_index: i64, content: &str| ComparisonAnswer {
+            answer_index,
+            llm_service: "groq".to_string(),
+            completion_model: "llama3-70b-8192".to_string(),
+            content: content.to_string(),
+            failed: false,
+            latency_ms: 1200,
+            prompt_tokens: Some(10),
+            completion_tokens: Some(20),
+        };
+
+        let comparison =
+            insert_model_comparison(&db_conn, 7, "Hello", &[answer(0, "Hi!"), answer(1, "Hey!")])
+                .await
+                .unwrap();
+
+        for (answer_index, content) in [(0, "Hi!"), (1, "Hey!")] {
+            let answer = get_comparison_answer(&db_conn, 7, comparison.id, answer_index)
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(answer.content, content);
+        }
+        assert!(get_comparison_answer(&db_conn, 8, comparison.id, 0)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use anyhow::Context;
+use sqlx::{FromRow, Pool, Sqlite};
+extern crate rand;
+use rand::Rng;
+
+#[derive(Clone, FromRow, Debug)]
+pub struct Persona {
+    pub id: i64,
+    pub name: String,
+    pub system_prompt: String,
+    pub llm_service: Option<String>,
+    pub completion_model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<i64>,
+    pub examples: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NewPersona {
+    pub name: String,
+    pub system_prompt: String,
+    pub llm_service: Option<String>,
+    pub completion_model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<i64>,
+    pub examples: Option<String>,
+}
+
+pub async fn insert_persona(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    new_persona: &NewPersona,
+) -> anyhow::Result<Persona> {
+    let new_id: i64 = rand::thread_rng().gen_range(1..i64::MAX);
+
+    let persona = sqlx::query_as::<_, Persona>(
+        r#"INSERT INTO personas
+          (id, chat_id, name, system_prompt, llm_service, completion_model, temperature, max_tokens, examples)
+        VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+          ON CONFLICT (chat_id, name)
+          DO UPDATE SET
+            system_prompt = excluded.system_prompt,
+            llm_service = excluded.llm_service,
+            completion_model = excluded.completion_model,
+            temperature = excluded.temperature,
+            max_tokens = excluded.max_tokens,
+            examples = excluded.examples
+        RETURNING *"#,
+    )
+    .bind(new_id)
+    .bind(chat_id)
+    .bind(&new_persona.name)
+    .bind(&new_persona.system_prompt)
+    .bind(&new_persona.llm_service)
+    .bind(&new_persona.completion_model)
+    .bind(new_persona.temperature)
+    .bind(new_persona.max_tokens)
+    .bind(&new_persona.examples)
+    .fetch_one(db_conn)
+    .await
+    .context(format!("Failed to save the persona {}", new_persona.name))?;
+
+    Ok(persona)
+}
+
+pub async fn get_chat_personas(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+) -> anyhow::Result<Vec<Persona>> {
+    let personas: Vec<Persona> =
+        sqlx::query_as("SELECT * FROM personas WHERE chat_id = ?1 ORDER BY name ASC")
+            .bind(chat_id)
+            .fetch_all(db_conn)
+            .await
+            .context(format!("Failed to get the personas for chat {}", chat_id))?;
+
+    Ok(personas)
+}
+
+pub async fn get_persona(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<Persona>> {
+    let persona: Option<Persona> =
+        sqlx::query_as("SELECT * FROM personas WHERE chat_id = ?1 AND id = ?2 LIMIT 1")
+            .bind(chat_id)
+            .bind(id)
+            .fetch_optional(db_conn)
+            .await?;
+
+    Ok(persona)
+}
+
+pub async fn delete_persona(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+    id: i64,
+) -> anyhow::Result<Option<Persona>> {
+    let persona: Option<Persona> =
+        sqlx::query_as("DELETE FROM personas WHERE chat_id = ?1 AND id = ?2 RETURNING *")
+            .bind(chat_id)
+            .bind(id)
+            .fetch_optional(db_conn)
+            .await?;
+
+    sqlx::query("UPDATE chat_bots SET persona_id = NULL WHERE id = ?1 AND persona_id = ?2")
+        .bind(chat_id)
+        .bind(id)
+        .execute(db_conn)
+        .await?;
+
+    Ok(persona)
+}
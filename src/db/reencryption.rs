@@ -0,0 +1,82 @@
+use crate::encryption;
+use anyhow::Context;
+use sqlx::{Pool, Sqlite};
+
+const REENCRYPTION_BATCH_SIZE: i64 = 500;
+
+// The encrypted columns, named "<table>.<column>". Their values are bound to
+// the id of their row.
+const ENCRYPTED_COLUMNS: [&str; 6] = [
+    encryption::MESSAGE_CONTENT,
+    encryption::CHAT_BOT_BEHAVIOR,
+    encryption::THREAD_TITLE,
+    encryption::MEMORY_CONTENT,
+    encryption::COMPARISON_PROMPT,
+    encryption::COMPARISON_ANSWER_CONTENT,
+];
+
+// Rewrites the values of the column that aren't encrypted with the active
+// key, in batches of one transaction each. Returns the number of rewritten
+// values.
+async fn reencrypt_column(db_conn: &Pool<Sqlite>, encrypted_column: &str) -> anyhow::Result<u64> {
+    let (table, column) = encrypted_column
+        .split_once('.')
+        .context("The encrypted column should be named <table>.<column>")?;
+    let mut last_id = i64::MIN;
+    let mut reencrypted = 0;
+
+    loop {
+        let rows: Vec<(i64, String)> = sqlx::query_as(&format!(
+            "SELECT id, {} FROM {} WHERE id > ?1 AND {} IS NOT NULL ORDER BY id LIMIT ?2",
+            column, table, column
+        ))
+        .bind(last_id)
+        .bind(REENCRYPTION_BATCH_SIZE)
+        .fetch_all(db_conn)
+        .await?;
+
+        let Some((batch_last_id, _)) = rows.last() else {
+            return Ok(reencrypted);
+        };
+        last_id = *batch_last_id;
+
+        let mut tx = db_conn.begin().await?;
+
+        for (id, value) in rows
+            .iter()
+            .filter(|(_, value)| !encryption::is_encrypted_with_active_key(value))
+        {
+            let value = encryption::decrypt(encrypted_column, *id, value).context(format!(
+                "Failed to decrypt {} of the row {}",
+                encrypted_column, id
+            ))?;
+
+            sqlx::query(&format!(
+                "UPDATE {} SET {} = ?1 WHERE id = ?2",
+                table, column
+            ))
+            .bind(encryption::encrypt(encrypted_column, *id, &value)?)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+
+            reencrypted += 1;
+        }
+
+        tx.commit().await?;
+    }
+}
+
+// Returns the number of rewritten values of each encrypted column.
+pub async fn reencrypt_all(db_conn: &Pool<Sqlite>) -> anyhow::Result<Vec<(&'static str, u64)>> {
+    let mut counts = vec![];
+
+    for encrypted_column in ENCRYPTED_COLUMNS {
+        counts.push((
+            encrypted_column,
+            reencrypt_column(db_conn, encrypted_column).await?,
+        ));
+    }
+
+    Ok(counts)
+}
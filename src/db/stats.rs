@@ -0,0 +1,118 @@
+use anyhow::Context;
+use sqlx::{FromRow, Pool, Sqlite};
+
+#[derive(Clone, FromRow, Debug)]
+pub struct ChatCounts {
+    pub known: i64,
+    pub active_today: i64,
+    pub active_this_week: i64,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct DailyMessages {
+    pub day: String,
+    pub user_messages: i64,
+    pub answers: i64,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct DailyTokens {
+    pub day: String,
+    pub llm_service: String,
+    pub tokens: i64,
+}
+
+#[derive(Clone, FromRow, Debug)]
+pub struct ChatActivity {
+    pub messages: i64,
+    pub tokens: i64,
+    pub last_message_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+// A chat is active on a day when it stored a message that day.
+pub async fn get_chat_counts(db_conn: &Pool<Sqlite>) -> anyhow::Result<ChatCounts> {
+    let counts: ChatCounts = sqlx::query_as(
+        r#"SELECT
+            (SELECT COUNT(*) FROM chat_bots) AS known,
+            (SELECT COUNT(DISTINCT chat_id) FROM chat_messages
+              WHERE inserted_at >= DATE('NOW')) AS active_today,
+            (SELECT COUNT(DISTINCT chat_id) FROM chat_messages
+              WHERE inserted_at >= DATE('NOW', '-6 days')) AS active_this_week"#,
+    )
+    .fetch_one(db_conn)
+    .await
+    .context("Failed to count the chats")?;
+
+    Ok(counts)
+}
+
+// The messages of the last days, today included, in UTC days.
+pub async fn get_daily_messages(
+    db_conn: &Pool<Sqlite>,
+    days: u32,
+) -> anyhow::Result<Vec<DailyMessages>> {
+    let daily_messages: Vec<DailyMessages> = sqlx::query_as(
+        r#"SELECT DATE(inserted_at) AS day,
+            SUM(user_role = 'user') AS user_messages,
+            SUM(user_role = 'assistant') AS answers
+        FROM chat_messages
+        WHERE inserted_at >= DATE('NOW', '-' || ?1 || ' days')
+        GROUP BY day
+        ORDER BY day DESC"#,
+    )
+    .bind(days.saturating_sub(1))
+    .fetch_all(db_conn)
+    .await
+    .context("Failed to count the daily messages")?;
+
+    Ok(daily_messages)
+}
+
+// The tokens of the answers and of the model comparisons of the last days, per
+// provider.
+pub async fn get_daily_tokens(
+    db_conn: &Pool<Sqlite>,
+    days: u32,
+) -> anyhow::Result<Vec<DailyTokens>> {
+    let daily_tokens: Vec<DailyTokens> = sqlx::query_as(
+        r#"SELECT day, llm_service, SUM(tokens) AS tokens FROM (
+            SELECT DATE(inserted_at) AS day, llm_service, tokens
+            FROM chat_messages
+            WHERE tokens IS NOT NULL AND inserted_at >= DATE('NOW', '-' || ?1 || ' days')
+            UNION ALL
+            SELECT DATE(model_comparisons.inserted_at) AS day,
+                model_comparison_answers.llm_service,
+                COALESCE(prompt_tokens, 0) + COALESCE(completion_tokens, 0) AS tokens
+            FROM model_comparison_answers
+            JOIN model_comparisons ON model_comparisons.id = model_comparison_answers.comparison_id
+            WHERE model_comparisons.inserted_at >= DATE('NOW', '-' || ?1 || ' days')
+        )
+        GROUP BY day, llm_service
+        ORDER BY day DESC, llm_service"#,
+    )
+    .bind(days.saturating_sub(1))
+    .fetch_all(db_conn)
+    .await
+    .context("Failed to count the daily tokens")?;
+
+    Ok(daily_tokens)
+}
+
+pub async fn get_chat_activity(
+    db_conn: &Pool<Sqlite>,
+    chat_id: i64,
+) -> anyhow::Result<ChatActivity> {
+    let activity: ChatActivity = sqlx::query_as(
+        r#"SELECT COUNT(*) AS messages,
+            COALESCE(SUM(tokens), 0) AS tokens,
+            MAX(inserted_at) AS last_message_at
+        FROM chat_messages
+        WHERE chat_id = ?1"#,
+    )
+    .bind(chat_id)
+    .fetch_one(db_conn)
+    .await
+    .context("Failed to get the chat activity")?;
+
+    Ok(activity)
+}
@@ -0,0 +1,105 @@
+use anyhow::Context;
+use sqlx::{Executor, FromRow, Sqlite};
+
+// The limits are kept per user and per chat, and the subject tells them apart
+// in a private chat, where both have the same id.
+pub const SUBJECT_USER: &str = "user";
+pub const SUBJECT_CHAT: &str = "chat";
+
+#[derive(Clone, FromRow, Debug, Default)]
+pub struct DailyUsage {
+    pub messages: i64,
+    pub tokens: i64,
+}
+
+// The tokens left in the bucket at updated_at, a Unix timestamp in seconds.
+#[derive(Clone, FromRow, Debug)]
+pub struct RateLimitBucket {
+    pub tokens: f64,
+    pub updated_at: f64,
+}
+
+pub async fn get_daily_usage<'c>(
+    db_conn: impl Executor<'c, Database = Sqlite>,
+    subject: &str,
+    subject_id: i64,
+    day: &str,
+) -> anyhow::Result<DailyUsage> {
+    let usage: Option<DailyUsage> = sqlx::query_as(
+        "SELECT messages, tokens FROM daily_usage WHERE subject = ?1 AND subject_id = ?2 AND day = ?3",
+    )
+    .bind(subject)
+    .bind(subject_id)
+    .bind(day)
+    .fetch_optional(db_conn)
+    .await
+    .context("Failed to get the daily usage")?;
+
+    Ok(usage.unwrap_or_default())
+}
+
+pub async fn add_daily_usage<'c>(
+    db_conn: impl Executor<'c, Database = Sqlite>,
+    subject: &str,
+    subject_id: i64,
+    day: &str,
+    messages: i64,
+    tokens: i64,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"INSERT INTO daily_usage (subject, subject_id, day, messages, tokens)
+        VALUES(?1, ?2, ?3, ?4, ?5)
+          ON CONFLICT (subject, subject_id, day)
+          DO UPDATE SET messages = messages + excluded.messages, tokens = tokens + excluded.tokens"#,
+    )
+    .bind(subject)
+    .bind(subject_id)
+    .bind(day)
+    .bind(messages)
+    .bind(tokens)
+    .execute(db_conn)
+    .await
+    .context("Failed to add the daily usage")?;
+
+    Ok(())
+}
+
+pub async fn get_rate_limit_bucket<'c>(
+    db_conn: impl Executor<'c, Database = Sqlite>,
+    subject: &str,
+    subject_id: i64,
+) -> anyhow::Result<Option<RateLimitBucket>> {
+    let bucket: Option<RateLimitBucket> = sqlx::query_as(
+        "SELECT tokens, updated_at FROM rate_limit_buckets WHERE subject = ?1 AND subject_id = ?2",
+    )
+    .bind(subject)
+    .bind(subject_id)
+    .fetch_optional(db_conn)
+    .await
+    .context("Failed to get the rate limit bucket")?;
+
+    Ok(bucket)
+}
+
+pub async fn set_rate_limit_bucket<'c>(
+    db_conn: impl Executor<'c, Database = Sqlite>,
+    subject: &str,
+    subject_id: i64,
+    bucket: &RateLimitBucket,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"INSERT INTO rate_limit_buckets (subject, subject_id, tokens, updated_at)
+        VALUES(?1, ?2, ?3, ?4)
+          ON CONFLICT (subject, subject_id)
+          DO UPDATE SET tokens = excluded.tokens, updated_at = excluded.updated_at"#,
+    )
+    .bind(subject)
+    .bind(subject_id)
+    .bind(bucket.tokens)
+    .bind(bucket.updated_at)
+    .execute(db_conn)
+    .await
+    .context("Failed to set the rate limit bucket")?;
+
+    Ok(())
+}
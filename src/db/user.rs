@@ -0,0 +1,142 @@
+use anyhow::Context;
+use sqlx::{FromRow, Pool, Sqlite};
+
+// A user that contacted the bot but wasn't let in yet.
+pub const STATUS_PENDING: &str = "pending";
+// A user that redeemed an invite code or was approved by an admin.
+pub const STATUS_ACTIVE: &str = "active";
+// A user refused in every access mode.
+pub const STATUS_BLOCKED: &str = "blocked";
+
+#[derive(Clone, FromRow, Debug)]
+pub struct User {
+    pub id: i64,
+    pub first_name: String,
+    pub username: Option<String>,
+    pub status: String,
+    pub last_seen_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub messages_per_minute: Option<i64>,
+    pub daily_messages: Option<i64>,
+    pub daily_tokens: Option<i64>,
+}
+
+// The id is the Telegram user id. The name, the username and the last contact
+// are refreshed on every contact, and the status is kept.
+pub async fn get_or_create_user(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    first_name: &str,
+    username: Option<&str>,
+) -> anyhow::Result<User> {
+    let user: User = sqlx::query_as(
+        r#"INSERT INTO users (id, first_name, username, status, last_seen_at)
+        VALUES(?1, ?2, ?3, ?4, STRFTIME('%Y-%m-%d %H:%M:%f', 'NOW'))
+          ON CONFLICT (id)
+          DO UPDATE SET first_name = excluded.first_name, username = excluded.username,
+            last_seen_at = excluded.last_seen_at
+        RETURNING *"#,
+    )
+    .bind(id)
+    .bind(first_name)
+    .bind(username)
+    .bind(STATUS_PENDING)
+    .fetch_one(db_conn)
+    .await
+    .context("Failed to get or create the user")?;
+
+    Ok(user)
+}
+
+// The limits of the user that override the bot-wide ones.
+#[derive(Clone, Copy, Debug)]
+pub enum UserLimit {
+    MessagesPerMinute,
+    DailyMessages,
+    DailyTokens,
+}
+
+impl UserLimit {
+    fn column(&self) -> &'static str {
+        match self {
+            UserLimit::MessagesPerMinute => "messages_per_minute",
+            UserLimit::DailyMessages => "daily_messages",
+            UserLimit::DailyTokens => "daily_tokens",
+        }
+    }
+}
+
+pub async fn get_user(db_conn: &Pool<Sqlite>, id: i64) -> anyhow::Result<Option<User>> {
+    let user: Option<User> = sqlx::query_as("SELECT * FROM users WHERE id = ?1")
+        .bind(id)
+        .fetch_optional(db_conn)
+        .await
+        .context("Failed to get the user")?;
+
+    Ok(user)
+}
+
+// None falls back to the bot-wide limit, and 0 lifts the limit.
+pub async fn set_user_limit(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    limit: UserLimit,
+    value: Option<i64>,
+) -> anyhow::Result<Option<User>> {
+    let user: Option<User> = sqlx::query_as(&format!(
+        "UPDATE users SET {} = ?1 WHERE id = ?2 RETURNING *",
+        limit.column()
+    ))
+    .bind(value)
+    .bind(id)
+    .fetch_optional(db_conn)
+    .await
+    .context("Failed to set the user limit")?;
+
+    Ok(user)
+}
+
+pub async fn set_user_status(
+    db_conn: &Pool<Sqlite>,
+    id: i64,
+    status: &str,
+) -> anyhow::Result<Option<User>> {
+    let user: Option<User> =
+        sqlx::query_as("UPDATE users SET status = ?1 WHERE id = ?2 RETURNING *")
+            .bind(status)
+            .bind(id)
+            .fetch_optional(db_conn)
+            .await
+            .context("Failed to set the user status")?;
+
+    Ok(user)
+}
+
+// A user let in with an invite code gets the access back, and the others go
+// back to pending.
+pub async fn unblock_user(db_conn: &Pool<Sqlite>, id: i64) -> anyhow::Result<Option<User>> {
+    let user: Option<User> = sqlx::query_as(
+        r#"UPDATE users
+        SET status = CASE WHEN invite_code_id IS NULL THEN ?1 ELSE ?2 END
+        WHERE id = ?3 AND status = ?4
+        RETURNING *"#,
+    )
+    .bind(STATUS_PENDING)
+    .bind(STATUS_ACTIVE)
+    .bind(id)
+    .bind(STATUS_BLOCKED)
+    .fetch_optional(db_conn)
+    .await
+    .context("Failed to unblock the user")?;
+
+    Ok(user)
+}
+
+pub async fn count_users_by_status(db_conn: &Pool<Sqlite>) -> anyhow::Result<Vec<(String, i64)>> {
+    let counts: Vec<(String, i64)> =
+        sqlx::query_as("SELECT status, COUNT(*) FROM users GROUP BY status ORDER BY status")
+            .fetch_all(db_conn)
+            .await
+            .context("Failed to count the users")?;
+
+    Ok(counts)
+}
@@ -0,0 +1,314 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use std::env;
+use std::sync::OnceLock;
+
+// The encrypted values are stored as "enc:v1:<key id>:<base64 of the nonce
+// followed by the ciphertext>", so the rows written before the encryption was
+// enabled are told apart and read as they are.
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+// The stored values starting with "enc:" are never taken as plaintext, so a
+// plaintext that starts with it is escaped with this prefix.
+const RESERVED_PREFIX: &str = "enc:";
+const ESCAPED_PREFIX: &str = "enc:plain:";
+
+pub const MESSAGE_CONTENT: &str = "chat_messages.content";
+pub const CHAT_BOT_BEHAVIOR: &str = "chat_bots.behavior";
+pub const THREAD_TITLE: &str = "chat_threads.title";
+pub const MEMORY_CONTENT: &str = "memories.content";
+pub const COMPARISON_PROMPT: &str = "model_comparisons.prompt";
+pub const COMPARISON_ANSWER_CONTENT: &str = "model_comparison_answers.content";
+
+static KEYRING: OnceLock<Option<Keyring>> = OnceLock::new();
+
+struct EncryptionKey {
+    id: String,
+    key: LessSafeKey,
+}
+
+// The first key encrypts, and all of them decrypt, so a new key is put first
+// and the previous ones are kept until the rows are re-encrypted.
+pub struct Keyring {
+    keys: Vec<EncryptionKey>,
+    rng: SystemRandom,
+}
+
+impl Keyring {
+    // One "<key id>:<base64 of 32 bytes>" entry per line or comma.
+    pub fn parse(keys: &str) -> Result<Keyring> {
+        let keys = keys
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty() && !entry.starts_with('#'))
+            .map(|entry| {
+                let (id, key) = entry
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("The key entry should be <key id>:<base64 key>"))?;
+
+                if id.is_empty()
+                    || !id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                {
+                    return Err(anyhow!(
+                        "Invalid key id '{}'. Use letters, digits, '-' and '_'.",
+                        id
+                    ));
+                }
+
+                let key = BASE64
+                    .decode(key.trim())
+                    .context(format!("The key '{}' isn't valid base64", id))?;
+                let key = UnboundKey::new(&AES_256_GCM, &key)
+                    .map_err(|_| anyhow!("The key '{}' should be 32 bytes long", id))?;
+
+                Ok(EncryptionKey {
+                    id: id.to_string(),
+                    key: LessSafeKey::new(key),
+                })
+            })
+            .collect::<Result<Vec<EncryptionKey>>>()?;
+
+        if keys.is_empty() {
+            return Err(anyhow!("No encryption keys are defined"));
+        }
+
+        Ok(Keyring {
+            keys,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn active_key_id(&self) -> &str {
+        &self.keys[0].id
+    }
+
+    fn encrypt(&self, column: &str, associated_data: &str, plaintext: &str) -> Result<String> {
+        let active_key = &self.keys[0];
+        let mut nonce = [0u8; NONCE_LEN];
+
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| anyhow!("Failed to generate a nonce"))?;
+
+        let mut sealed = plaintext.as_bytes().to_vec();
+
+        active_key
+            .key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(associated_data.as_bytes()),
+                &mut sealed,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt {}", column))?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend(sealed);
+
+        Ok(format!(
+            "{}{}:{}",
+            ENCRYPTED_PREFIX,
+            active_key.id,
+            BASE64.encode(payload)
+        ))
+    }
+
+    fn decrypt(
+        &self,
+        column: &str,
+        associated_data: &str,
+        key_id: &str,
+        payload: &str,
+    ) -> Result<String> {
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.id == key_id)
+            .ok_or_else(|| anyhow!("The encryption key '{}' isn't configured", key_id))?;
+
+        let payload = BASE64
+            .decode(payload)
+            .context(format!("The encrypted {} is malformed", column))?;
+
+        if payload.len() < NONCE_LEN {
+            return Err(anyhow!("The encrypted {} is malformed", column));
+        }
+
+        let (nonce, sealed) = payload.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| anyhow!("The encrypted {} is malformed", column))?;
+        let mut sealed = sealed.to_vec();
+
+        let plaintext = key
+            .key
+            .open_in_place(nonce, Aad::from(associated_data.as_bytes()), &mut sealed)
+            .map_err(|_| anyhow!("Failed to decrypt {} with the key '{}'", column, key_id))?;
+
+        Ok(String::from_utf8(plaintext.to_vec())?)
+    }
+}
+
+// ENCRYPTION_KEYS holds the keys, or ENCRYPTION_KEYS_FILE names a file with
+// them. The encryption is disabled when neither is set.
+fn load_keyring() -> Result<Option<Keyring>> {
+    let keys = match (
+        env::var("ENCRYPTION_KEYS"),
+        env::var("ENCRYPTION_KEYS_FILE"),
+    ) {
+        (Ok(keys), _) => keys,
+        (Err(_), Ok(path)) => std::fs::read_to_string(&path)
+            .context(format!("Failed to read the encryption keys from {}", path))?,
+        (Err(_), Err(_)) => return Ok(None),
+    };
+
+    Keyring::parse(&keys).map(Some)
+}
+
+pub fn init_from_env() {
+    let keyring = load_keyring().unwrap_or_else(|e| {
+        eprintln!("Error: invalid encryption keys. {:?}", e);
+        std::process::exit(1);
+    });
+
+    let _ = KEYRING.set(keyring);
+}
+
+fn keyring() -> Option<&'static Keyring> {
+    KEYRING.get().and_then(Option::as_ref)
+}
+
+pub fn is_enabled() -> bool {
+    keyring().is_some()
+}
+
+pub fn active_key_id() -> Option<&'static str> {
+    keyring().map(Keyring::active_key_id)
+}
+
+// The column and the row are the associated data, so a value can't be moved
+// to another column or row.
+fn associated_data(column: &str, row_id: i64) -> String {
+    format!("{}:{}", column, row_id)
+}
+
+pub(crate) fn encrypt_with(
+    keyring: Option<&Keyring>,
+    column: &str,
+    row_id: i64,
+    plaintext: &str,
+) -> Result<String> {
+    match keyring {
+        Some(keyring) => keyring.encrypt(column, &associated_data(column, row_id), plaintext),
+        None if plaintext.starts_with(RESERVED_PREFIX) => {
+            Ok(format!("{}{}", ESCAPED_PREFIX, plaintext))
+        }
+        None => Ok(plaintext.to_string()),
+    }
+}
+
+fn decrypt_with(
+    keyring: Option<&Keyring>,
+    column: &str,
+    row_id: i64,
+    stored: &str,
+) -> Result<String> {
+    if let Some(plaintext) = stored.strip_prefix(ESCAPED_PREFIX) {
+        return Ok(plaintext.to_string());
+    }
+
+    let Some(encrypted) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+        return Ok(stored.to_string());
+    };
+
+    let (key_id, payload) = encrypted
+        .split_once(':')
+        .ok_or_else(|| anyhow!("The encrypted {} is malformed", column))?;
+
+    keyring
+        .ok_or_else(|| {
+            anyhow!(
+                "{} is encrypted, but no encryption keys are configured",
+                column
+            )
+        })?
+        .decrypt(column, &associated_data(column, row_id), key_id, payload)
+}
+
+// Returns the value to store for the column of the row: encrypted with the
+// active key, or the plaintext when the encryption is disabled.
+pub fn encrypt(column: &str, row_id: i64, plaintext: &str) -> Result<String> {
+    encrypt_with(keyring(), column, row_id, plaintext)
+}
+
+pub fn decrypt(column: &str, row_id: i64, stored: &str) -> Result<String> {
+    decrypt_with(keyring(), column, row_id, stored)
+}
+
+pub fn decrypt_optional(
+    column: &str,
+    row_id: i64,
+    stored: Option<String>,
+) -> Result<Option<String>> {
+    stored
+        .map(|stored| decrypt(column, row_id, &stored))
+        .transpose()
+}
+
+pub fn is_encrypted_with_active_key(stored: &str) -> bool {
+    active_key_id()
+        .is_some_and(|key_id| stored.starts_with(&format!("{}{}:", ENCRYPTED_PREFIX, key_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYS: &str = "old:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=,\nnew:AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=";
+
+    #[test]
+    fn encrypts_and_decrypts_a_value() {
+        let keyring = Keyring::parse(KEYS).unwrap();
+        let stored = encrypt_with(Some(&keyring), MESSAGE_CONTENT, 7, "Hello").unwrap();
+
+        assert!(stored.starts_with("enc:v1:old:"));
+        assert_eq!(
+            decrypt_with(Some(&keyring), MESSAGE_CONTENT, 7, &stored).unwrap(),
+            "Hello"
+        );
+    }
+
+    #[test]
+    fn binds_a_value_to_its_column_and_row() {
+        let keyring = Keyring::parse(KEYS).unwrap();
+        let stored = encrypt_with(Some(&keyring), MESSAGE_CONTENT, 7, "Hello").unwrap();
+
+        assert!(decrypt_with(Some(&keyring), MESSAGE_CONTENT, 8, &stored).is_err());
+        assert!(decrypt_with(Some(&keyring), THREAD_TITLE, 7, &stored).is_err());
+    }
+
+    #[test]
+    fn escapes_the_plaintext_that_looks_encrypted() {
+        let stored = encrypt_with(None, MESSAGE_CONTENT, 7, "enc:v1:old:AAAA").unwrap();
+
+        assert_eq!(stored, "enc:plain:enc:v1:old:AAAA");
+        assert_eq!(
+            decrypt_with(None, MESSAGE_CONTENT, 7, &stored).unwrap(),
+            "enc:v1:old:AAAA"
+        );
+        assert_eq!(
+            encrypt_with(None, MESSAGE_CONTENT, 7, "Hello").unwrap(),
+            "Hello"
+        );
+    }
+
+    #[test]
+    fn refuses_an_encrypted_value_without_the_keys() {
+        let keyring = Keyring::parse(KEYS).unwrap();
+        let stored = encrypt_with(Some(&keyring), MESSAGE_CONTENT, 7, "Hello").unwrap();
+
+        assert!(decrypt_with(None, MESSAGE_CONTENT, 7, &stored).is_err());
+    }
+}
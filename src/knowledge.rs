@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Context, Result};
+use sqlx::{Pool, Sqlite};
+
+use crate::db::document;
+use crate::llm::embeddings;
+use crate::llm::embeddings::EmbeddingsService;
+
+pub const SUPPORTED_EXTENSIONS: [&str; 3] = ["txt", "md", "pdf"];
+pub const MAX_DOCUMENT_SIZE: i64 = 10 * 1024 * 1024;
+
+const CHUNK_SIZE: usize = 1500;
+const TOP_K: usize = 3;
+
+#[derive(Clone, Debug)]
+pub struct RetrievedChunk {
+    pub document_name: String,
+    pub chunk_index: i64,
+    pub content: String,
+    pub score: f32,
+}
+
+impl RetrievedChunk {
+    pub fn citation(&self) -> String {
+        format!("{} #{}", self.document_name, self.chunk_index + 1)
+    }
+}
+
+pub fn file_extension(file_name: &str) -> Option<String> {
+    file_name
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_lowercase())
+        .filter(|extension| SUPPORTED_EXTENSIONS.contains(&extension.as_str()))
+}
+
+pub async fn extract_text(extension: &str, content: Vec<u8>) -> Result<String> {
+    match extension {
+        "pdf" => tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem(&content))
+            .await
+            .context("Failed to extract the PDF text")?
+            .context("Failed to extract the PDF text"),
+        _ => String::from_utf8(content).context("The document is not a valid UTF-8 text"),
+    }
+}
+
+// Paragraphs are packed into chunks of up to CHUNK_SIZE characters, and the
+// paragraphs that don't fit into a single chunk are split on word boundaries.
+pub fn chunk_text(text: &str) -> Vec<String> {
+    let mut chunks: Vec<String> = vec![];
+    let mut current = String::new();
+
+    for paragraph in text.split("\n\n").map(str::trim).filter(|p| !p.is_empty()) {
+        if current.chars().count() + paragraph.chars().count() + 2 > CHUNK_SIZE
+            && !current.is_empty()
+        {
+            chunks.push(std::mem::take(&mut current));
+        }
+
+        if paragraph.chars().count() > CHUNK_SIZE {
+            for word in paragraph.split_whitespace() {
+                if current.chars().count() + word.chars().count() + 1 > CHUNK_SIZE
+                    && !current.is_empty()
+                {
+                    chunks.push(std::mem::take(&mut current));
+                }
+
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(word);
+            }
+        } else {
+            if !current.is_empty() {
+                current.push_str("\n\n");
+            }
+            current.push_str(paragraph);
+        }
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+pub async fn ingest_document(
+    db_conn: &Pool<Sqlite>,
+    embeddings_service: &dyn EmbeddingsService,
+    chat_id: i64,
+    name: &str,
+    text: &str,
+) -> Result<document::Document> {
+    let chunks = chunk_text(text);
+
+    if chunks.is_empty() {
+        return Err(anyhow!("The document {} doesn't contain any text", name));
+    }
+
+    let vectors = embeddings_service
+        .embed(chunks.clone())
+        .await
+        .context("Failed to compute the document embeddings")?;
+
+    let stored_chunks: Vec<(String, Vec<u8>)> = chunks
+        .into_iter()
+        .zip(vectors.iter().map(|v| embeddings::to_blob(v)))
+        .collect();
+
+    document::insert_document(
+        db_conn,
+        chat_id,
+        name,
+        &embeddings_service.model_name(),
+        &stored_chunks,
+    )
+    .await
+}
+
+pub async fn retrieve(
+    db_conn: &Pool<Sqlite>,
+    embeddings_service: &dyn EmbeddingsService,
+    chat_id: i64,
+    query: &str,
+) -> Result<Vec<RetrievedChunk>> {
+    let chunks =
+        document::get_chat_document_chunks(db_conn, chat_id, &embeddings_service.model_name())
+            .await?;
+
+    if chunks.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let query_vector = embeddings_service
+        .embed(vec![query.to_string()])
+        .await
+        .context("Failed to compute the query embedding")?
+        .pop()
+        .ok_or_else(|| anyhow!("No query embedding returned"))?;
+
+    let mut retrieved: Vec<RetrievedChunk> = chunks
+        .into_iter()
+        .map(|c| RetrievedChunk {
+            score: embeddings::cosine_similarity(
+                &query_vector,
+                &embeddings::from_blob(&c.embedding),
+            ),
+            document_name: c.document_name,
+            chunk_index: c.chunk_index,
+            content: c.content,
+        })
+        .filter(|c| c.score > 0.0)
+        .collect();
+
+    retrieved.sort_by(|a, b| b.score.total_cmp(&a.score));
+    retrieved.truncate(TOP_K);
+
+    Ok(retrieved)
+}
+
+pub fn sources_footer(retrieved_chunks: &[RetrievedChunk]) -> Option<String> {
+    let mut citations: Vec<String> = vec![];
+
+    for chunk in retrieved_chunks {
+        let citation = chunk.citation();
+
+        if !citations.contains(&citation) {
+            citations.push(citation);
+        }
+    }
+
+    if citations.is_empty() {
+        None
+    } else {
+        Some(format!("Sources: {}", citations.join(", ")))
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::{DateTime, Utc};
+
+pub const TEMPLATE_VARIABLES: [&str; 7] = [
+    "date",
+    "time",
+    "weekday",
+    "user_first_name",
+    "chat_title",
+    "model",
+    "language",
+];
+
+#[derive(Clone, Debug)]
+pub struct BehaviorContext {
+    pub now: DateTime<Utc>,
+    pub user_first_name: Option<String>,
+    pub chat_title: Option<String>,
+    pub model: String,
+    pub language: Option<String>,
+}
+
+impl BehaviorContext {
+    pub fn new(model: &str) -> BehaviorContext {
+        BehaviorContext {
+            now: Utc::now(),
+            user_first_name: None,
+            chat_title: None,
+            model: model.to_string(),
+            language: None,
+        }
+    }
+
+    fn value_of(&self, variable: &str) -> Option<String> {
+        match variable {
+            "date" => Some(self.now.format("%Y-%m-%d").to_string()),
+            "time" => Some(self.now.format("%H:%M UTC").to_string()),
+            "weekday" => Some(self.now.format("%A").to_string()),
+            "user_first_name" => Some(
+                self.user_first_name
+                    .clone()
+                    .unwrap_or_else(|| "user".to_string()),
+            ),
+            "chat_title" => Some(self.chat_title.clone().unwrap_or_default()),
+            "model" => Some(self.model.clone()),
+            "language" => Some(self.language.clone().unwrap_or_else(|| "en".to_string())),
+            _ => None,
+        }
+    }
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Variable(&'a str),
+}
+
+// Splits the template into literal text and `{variable}` placeholders. Braces
+// that don't wrap an identifier (e.g. JSON examples in a prompt) stay literal.
+fn parse(template: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        let after_open = &rest[open + 1..];
+
+        let variable = after_open.find('}').and_then(|close| {
+            let name = &after_open[..close];
+            let is_identifier =
+                !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+            is_identifier.then_some(name)
+        });
+
+        match variable {
+            Some(name) => {
+                segments.push(Segment::Text(&rest[..open]));
+                segments.push(Segment::Variable(name));
+                rest = &after_open[name.len() + 1..];
+            }
+            None => {
+                segments.push(Segment::Text(&rest[..open + 1]));
+                rest = after_open;
+            }
+        }
+    }
+
+    segments.push(Segment::Text(rest));
+    segments
+}
+
+pub fn unknown_variables(template: &str) -> Vec<String> {
+    let mut unknown: Vec<String> = vec![];
+
+    for segment in parse(template) {
+        if let Segment::Variable(name) = segment {
+            if !TEMPLATE_VARIABLES.contains(&name) && !unknown.iter().any(|u| u == name) {
+                unknown.push(name.to_string());
+            }
+        }
+    }
+
+    unknown
+}
+
+pub fn render(template: &str, context: &BehaviorContext) -> String {
+    parse(template)
+        .iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.to_string(),
+            Segment::Variable(name) => context
+                .value_of(name)
+                .unwrap_or_else(|| format!("{{{}}}", name)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn context() -> BehaviorContext {
+        BehaviorContext {
+            now: Utc.with_ymd_and_hms(2024, 5, 1, 9, 30, 0).unwrap(),
+            user_first_name: Some("Ada".to_string()),
+            chat_title: None,
+            model: "bright".to_string(),
+            language: None,
+        }
+    }
+
+    #[test]
+    fn substitutes_the_variables() {
+        assert_eq!(
+            render(
+                "Hi {user_first_name}! It's {weekday}, {date} at {time}. You run on {model} and reply in {language}.",
+                &context()
+            ),
+            "Hi Ada! It's Wednesday, 2024-05-01 at 09:30 UTC. You run on bright and reply in en."
+        );
+        assert_eq!(render("Chat: '{chat_title}'", &context()), "Chat: ''");
+    }
+
+    #[test]
+    fn keeps_and_reports_the_unknown_variables() {
+        let template = "Hi {user_firstname}, {nickname} and {user_firstname}.";
+
+        assert_eq!(
+            render(template, &context()),
+            "Hi {user_firstname}, {nickname} and {user_firstname}."
+        );
+        assert_eq!(
+            unknown_variables(template),
+            vec!["user_firstname", "nickname"]
+        );
+        assert!(unknown_variables("Today is {date}.").is_empty());
+    }
+
+    #[test]
+    fn keeps_the_braces_that_arent_variables() {
+        let template = r#"Reply with {"answer": "..."} or {} on {date}. {unclosed"#;
+
+        assert_eq!(
+            render(template, &context()),
+            r#"Reply with {"answer": "..."} or {} on 2024-05-01. {unclosed"#
+        );
+        assert!(unknown_variables(template).is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::anyhow;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+const LOCAL_EMBEDDING_DIMENSIONS: usize = 512;
+const OPENAI_EMBEDDINGS_BATCH_SIZE: usize = 64;
+
+#[async_trait]
+pub trait EmbeddingsService: Send + Sync {
+    async fn embed(&self, inputs: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>>;
+
+    // Stored next to each vector, so that vectors produced by a different
+    // model are never compared with each other.
+    fn model_name(&self) -> String;
+}
+
+#[derive(Clone, Debug)]
+pub enum EmbeddingsConfig {
+    OpenAICompatible {
+        api_url: String,
+        api_key: String,
+        model: String,
+    },
+    Local,
+}
+
+pub fn new_embeddings_service(config: &EmbeddingsConfig) -> Box<dyn EmbeddingsService> {
+    match config {
+        EmbeddingsConfig::OpenAICompatible {
+            api_url,
+            api_key,
+            model,
+        } => Box::new(OpenAICompatibleEmbeddings {
+            api_url: api_url.clone(),
+            api_key: api_key.clone(),
+            model: model.clone(),
+        }),
+        EmbeddingsConfig::Local => Box::new(LocalEmbeddings {}),
+    }
+}
+
+pub struct OpenAICompatibleEmbeddings {
+    pub api_url: String,
+    pub api_key: String,
+    pub model: String,
+}
+
+#[derive(Debug, Serialize)]
+struct EmbeddingsRequest {
+    input: Vec<String>,
+    model: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsResponseData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingsResponseData>,
+}
+
+#[async_trait]
+impl EmbeddingsService for OpenAICompatibleEmbeddings {
+    async fn embed(&self, inputs: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+        let client = reqwest::Client::new();
+        let mut embeddings: Vec<Vec<f32>> = vec![];
+
+        for batch in inputs.chunks(OPENAI_EMBEDDINGS_BATCH_SIZE) {
+            let req_body = EmbeddingsRequest {
+                input: batch.to_vec(),
+                model: self.model.clone(),
+            };
+
+            let mut response: EmbeddingsResponse = client
+                .post(format!("{}/embeddings", self.api_url.trim_end_matches('/')))
+                .header("Authorization", format!("Bearer {}", self.api_key))
+                .json(&req_body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+
+            if response.data.len() != batch.len() {
+                return Err(anyhow!(
+                    "Expected {} embeddings, got {}",
+                    batch.len(),
+                    response.data.len()
+                ));
+            }
+
+            response.data.sort_by_key(|d| d.index);
+            embeddings.extend(response.data.into_iter().map(|d| d.embedding));
+        }
+
+        Ok(embeddings)
+    }
+
+    fn model_name(&self) -> String {
+        self.model.clone()
+    }
+}
+
+// A lexical fallback that doesn't need an API: words are hashed into a fixed
+// number of buckets, which is enough to find chunks sharing the question's terms.
+pub struct LocalEmbeddings {}
+
+#[async_trait]
+impl EmbeddingsService for LocalEmbeddings {
+    async fn embed(&self, inputs: Vec<String>) -> anyhow::Result<Vec<Vec<f32>>> {
+        Ok(inputs.iter().map(|i| hashed_embedding(i)).collect())
+    }
+
+    fn model_name(&self) -> String {
+        format!("local-hashed-{}", LOCAL_EMBEDDING_DIMENSIONS)
+    }
+}
+
+fn hashed_embedding(text: &str) -> Vec<f32> {
+    let mut vector = vec![0f32; LOCAL_EMBEDDING_DIMENSIONS];
+
+    for word in text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() > 2)
+    {
+        let bucket = (fnv1a(&word.to_lowercase()) % LOCAL_EMBEDDING_DIMENSIONS as u64) as usize;
+        vector[bucket] += 1.0;
+    }
+
+    vector
+}
+
+// The vectors are persisted, so the hash must be stable across Rust releases,
+// which `DefaultHasher` doesn't guarantee.
+fn fnv1a(word: &str) -> u64 {
+    word.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a * norm_b)
+    }
+}
+
+pub fn to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4)
+        .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect()
+}
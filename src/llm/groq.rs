@@ -4,8 +4,11 @@ use reqwest;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
+use crate::llm::GenerationParams;
+use crate::llm::LLMCompletion;
 use crate::llm::LLMService;
 use crate::llm::LLMThreadMessage;
+use crate::llm::TokenUsage;
 
 #[derive(Copy, Clone, Debug, Default)]
 pub enum GroqCompletionModel {
@@ -34,6 +37,10 @@ impl GroqCompletionModel {
         }
     }
 
+    pub fn context_window(&self) -> usize {
+        8192
+    }
+
     pub fn default_string() -> String {
         let mock_completion_model: GroqCompletionModel = Default::default();
         mock_completion_model.as_str().to_string()
@@ -43,6 +50,7 @@ impl GroqCompletionModel {
 pub struct Groq {
     pub completion_model: GroqCompletionModel,
     pub api_key: String,
+    pub generation_params: GenerationParams,
 }
 
 pub fn all_completions() -> [GroqCompletionModel; 2] {
@@ -62,6 +70,10 @@ pub struct GroqMessage {
 pub struct GroqRequest {
     messages: Vec<GroqMessage>,
     model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u16>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -99,11 +111,10 @@ struct GroqResponse {
 
 #[async_trait]
 impl LLMService for Groq {
-    fn bot_info(&self) -> String {
-        format!("Bot uses Groq with {}", self.completion_model.as_str())
-    }
-
-    async fn get_answer(&self, thread_messages: Vec<LLMThreadMessage>) -> anyhow::Result<String> {
+    async fn get_completion(
+        &self,
+        thread_messages: Vec<LLMThreadMessage>,
+    ) -> anyhow::Result<LLMCompletion> {
         let mut chat_req_messages: Vec<GroqMessage> = vec![];
 
         for msg in thread_messages {
@@ -118,6 +129,8 @@ impl LLMService for Groq {
         let req_body = GroqRequest {
             messages: chat_req_messages,
             model: self.completion_model.as_str().to_string(),
+            temperature: self.generation_params.temperature,
+            max_tokens: self.generation_params.max_tokens,
         };
 
         let client = reqwest::Client::new();
@@ -133,9 +146,17 @@ impl LLMService for Groq {
             .json()
             .await?;
 
-        match response.choices.first() {
-            Some(choice) => Ok(choice.message.content.to_owned()),
-            None => Ok("No response".to_string()),
-        }
+        let content = match response.choices.first() {
+            Some(choice) => choice.message.content.to_owned(),
+            None => "No response".to_string(),
+        };
+
+        Ok(LLMCompletion {
+            content,
+            usage: Some(TokenUsage {
+                prompt_tokens: response.usage.prompt_tokens,
+                completion_tokens: response.usage.completion_tokens,
+            }),
+        })
     }
 }
@@ -5,6 +5,13 @@ use sqlx::{Pool, Sqlite};
 
 use crate::db::chat_bot;
 use crate::db::chat_message;
+use crate::db::memory;
+use crate::db::persona;
+use crate::knowledge::RetrievedChunk;
+use crate::llm::behavior_template;
+use crate::llm::behavior_template::BehaviorContext;
+use crate::memory::memories_prompt;
+use crate::persona_catalog::PersonaExample;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct LLMThreadMessage {
@@ -12,33 +19,159 @@ pub struct LLMThreadMessage {
     pub role: String,
 }
 
-pub async fn build_llm_thread_payload(
+pub async fn build_llm_payload(
     db_conn: &Pool<Sqlite>,
     chad_id: i64,
-    chat_thread_id: i64,
+    chat_messages: &[chat_message::ChatMessage],
+    behavior_context: &BehaviorContext,
+    retrieved_chunks: &[RetrievedChunk],
 ) -> anyhow::Result<Vec<LLMThreadMessage>> {
-    let chat_bot = chat_bot::get_or_create_chat_bot(db_conn, chad_id)
+    let chat_bot = chat_bot::get_by_id(db_conn, chad_id)
         .await
-        .context("Failed to get or create chat bot")?;
+        .context("Failed to get the chat bot")?;
 
-    let initial_message = LLMThreadMessage {
-        message: chat_bot.behavior,
-        role: "system".to_string(),
+    let active_persona = match chat_bot.persona_id {
+        Some(persona_id) => persona::get_persona(db_conn, chad_id, persona_id)
+            .await
+            .context("Failed to get the active persona")?,
+        None => None,
     };
 
-    let chat_thread_messages = chat_message::get_chat_thread_messages(db_conn, chat_thread_id)
+    let examples: Vec<PersonaExample> = active_persona
+        .as_ref()
+        .and_then(|p| p.examples.as_ref())
+        .map(|e| serde_json::from_str(e))
+        .transpose()
+        .context("Failed to parse the persona examples")?
+        .unwrap_or_default();
+
+    let behavior = active_persona
+        .map(|p| p.system_prompt)
+        .unwrap_or(chat_bot.behavior);
+
+    let mut system_message = behavior_template::render(&behavior, behavior_context);
+
+    let memories = memory::get_chat_memories(db_conn, chad_id)
         .await
-        .context("Failed to get the thread")?;
+        .context("Failed to get the memories")?;
+
+    if let Some(memories_prompt) = memories_prompt(&memories) {
+        system_message = format!("{}\n\n{}", system_message, memories_prompt);
+    }
+
+    // The user messages of a group are tagged with their sender's name.
+    if chat_messages.iter().any(|m| m.sender_name.is_some()) {
+        system_message = format!(
+            "{}\n\nThis is a group chat, and each user message starts with the name of its sender.",
+            system_message
+        );
+    }
 
-    let mut payload_messages: Vec<LLMThreadMessage> = chat_thread_messages
+    let initial_message = LLMThreadMessage {
+        message: system_message,
+        role: "system".to_string(),
+    };
+
+    let mut payload_messages: Vec<LLMThreadMessage> = chat_messages
         .iter()
+        .filter(|m| m.stopped_at.is_none())
         .map(|m| LLMThreadMessage {
-            message: m.content.clone(),
+            message: match &m.sender_name {
+                Some(sender_name) => format!("{}: {}", sender_name, m.content),
+                None => m.content.clone(),
+            },
             role: m.user_role.clone(),
         })
         .collect();
 
-    payload_messages.insert(0, initial_message);
+    let example_messages = examples.into_iter().flat_map(|e| {
+        [
+            LLMThreadMessage {
+                message: e.user,
+                role: "user".to_string(),
+            },
+            LLMThreadMessage {
+                message: e.assistant,
+                role: "assistant".to_string(),
+            },
+        ]
+    });
+
+    if !retrieved_chunks.is_empty() {
+        let last_message_index = payload_messages.len().saturating_sub(1);
+        payload_messages.insert(last_message_index, knowledge_message(retrieved_chunks));
+    }
+
+    payload_messages.splice(
+        0..0,
+        std::iter::once(initial_message).chain(example_messages),
+    );
 
     Ok(payload_messages)
 }
+
+fn knowledge_message(retrieved_chunks: &[RetrievedChunk]) -> LLMThreadMessage {
+    let excerpts: Vec<String> = retrieved_chunks
+        .iter()
+        .map(|c| format!("[{}]\n{}", c.citation(), c.content))
+        .collect();
+
+    LLMThreadMessage {
+        message: format!(
+            "Use the following excerpts from the chat's documents to answer when they are relevant, and cite the excerpts you use as [document #chunk].\n\n{}",
+            excerpts.join("\n\n")
+        ),
+        role: "system".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+    use crate::db::chat_thread;
+
+    #[tokio::test]
+    async fn leaves_out_the_stopped_messages() {
+        let path = std::env::temp_dir().join(format!("llm-payload-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_conn = db::start(&format!("sqlite:{}", path.display())).await;
+        chat_bot::get_or_create_chat_bot(&db_conn, 42, "Be helpful.")
+            .await
+            .unwrap();
+        let thread = chat_thread::get_or_create_chat_thread(&db_conn, 42)
+            .await
+            .unwrap();
+
+        let stopped_id =
+            chat_message::insert_new_message(&db_conn, "First", 42, thread.id, "user", None, None)
+                .await
+                .unwrap();
+        chat_message::set_message_stopped(&db_conn, stopped_id)
+            .await
+            .unwrap();
+        chat_message::insert_new_message(&db_conn, "Second", 42, thread.id, "user", None, None)
+            .await
+            .unwrap();
+
+        let chat_messages = chat_message::get_chat_thread_history(&db_conn, thread.id)
+            .await
+            .unwrap();
+        let payload = build_llm_payload(
+            &db_conn,
+            42,
+            &chat_messages,
+            &BehaviorContext::new("mock"),
+            &[],
+        )
+        .await
+        .unwrap();
+        let turns: Vec<(&str, &str)> = payload
+            .iter()
+            .skip(1)
+            .map(|m| (m.role.as_str(), m.message.as_str()))
+            .collect();
+
+        assert_eq!(turns, vec![("user", "Second")]);
+    }
+}
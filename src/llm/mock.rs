@@ -1,5 +1,5 @@
+use crate::llm::LLMCompletion;
 use crate::llm::LLMService;
-use crate::llm::LLMServiceModel;
 use crate::llm::LLMThreadMessage;
 use async_trait::async_trait;
 use std::str::FromStr;
@@ -19,8 +19,6 @@ pub fn all_completions() -> [MockCompletionModel; 2] {
     [MockCompletionModel::Bright, MockCompletionModel::Brighter]
 }
 
-impl LLMServiceModel for MockCompletionModel {}
-
 impl MockCompletionModel {
     pub fn as_str(&self) -> &'static str {
         match *self {
@@ -29,6 +27,10 @@ impl MockCompletionModel {
         }
     }
 
+    pub fn context_window(&self) -> usize {
+        4096
+    }
+
     pub fn default_string() -> String {
         let mock_completion_model: MockCompletionModel = Default::default();
         mock_completion_model.as_str().to_string()
@@ -49,11 +51,10 @@ impl FromStr for MockCompletionModel {
 
 #[async_trait]
 impl LLMService for Mock {
-    fn bot_info(&self) -> String {
-        format!("Bot uses Mock with {}", self.completion_model.as_str())
-    }
-
-    async fn get_answer(&self, thread_messages: Vec<LLMThreadMessage>) -> anyhow::Result<String> {
+    async fn get_completion(
+        &self,
+        thread_messages: Vec<LLMThreadMessage>,
+    ) -> anyhow::Result<LLMCompletion> {
         println!(
             "Mocked request using {:?} completion model",
             self.completion_model
@@ -65,7 +66,10 @@ impl LLMService for Mock {
 
         async move {
             tokio::time::sleep(tokio::time::Duration::from_micros(1)).await;
-            Ok("Mocked answer".to_string())
+            Ok(LLMCompletion {
+                content: "Mocked answer".to_string(),
+                usage: None,
+            })
         }
         .await
     }
@@ -1,10 +1,14 @@
+pub mod behavior_template;
+pub mod embeddings;
 pub mod groq;
 pub mod llm_thread_message;
 pub mod mock;
 pub mod openai;
+use anyhow::anyhow;
 use async_trait::async_trait;
 use clap::ValueEnum;
 use std::fmt;
+use std::str::FromStr;
 
 use llm_thread_message::LLMThreadMessage;
 
@@ -28,12 +32,162 @@ impl fmt::Display for LLMServiceKind {
     }
 }
 
-#[async_trait]
-pub trait LLMServiceModel: Sync + Send {}
+impl LLMServiceKind {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            LLMServiceKind::OpenAI => "openai",
+            LLMServiceKind::Groq => "groq",
+            LLMServiceKind::Mock => "mock",
+        }
+    }
+}
 
-#[async_trait]
-pub trait LLMService: Send {
-    async fn get_answer(&self, thread_messages: Vec<LLMThreadMessage>) -> anyhow::Result<String>;
+impl FromStr for LLMServiceKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(LLMServiceKind::OpenAI),
+            "groq" => Ok(LLMServiceKind::Groq),
+            "mock" => Ok(LLMServiceKind::Mock),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModelPair {
+    pub llm_service: LLMServiceKind,
+    pub completion_model: String,
+}
+
+impl fmt::Display for ModelPair {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.llm_service, self.completion_model)
+    }
+}
+
+// Parses the "<provider>:<model>" notation, e.g. "groq:llama3-70b-8192".
+impl FromStr for ModelPair {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (llm_service, completion_model) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected <provider>:<model>, got {:?}", s))?;
+
+        let llm_service = llm_service
+            .parse::<LLMServiceKind>()
+            .map_err(|_| anyhow!("Unknown provider {:?}", llm_service))?;
+
+        if !is_valid_completion_model(llm_service, completion_model) {
+            return Err(anyhow!(
+                "Unknown {} completion model {:?}",
+                llm_service,
+                completion_model
+            ));
+        }
+
+        Ok(ModelPair {
+            llm_service,
+            completion_model: completion_model.to_string(),
+        })
+    }
+}
 
-    fn bot_info(&self) -> String;
+pub fn all_completion_models(llm_service_kind: LLMServiceKind) -> Vec<&'static str> {
+    match llm_service_kind {
+        LLMServiceKind::OpenAI => openai::all_completions()
+            .iter()
+            .map(|m| m.as_str())
+            .collect(),
+        LLMServiceKind::Groq => groq::all_completions().iter().map(|m| m.as_str()).collect(),
+        LLMServiceKind::Mock => mock::all_completions().iter().map(|m| m.as_str()).collect(),
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GenerationParams {
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u16>,
+}
+
+pub fn is_valid_completion_model(llm_service_kind: LLMServiceKind, completion_model: &str) -> bool {
+    match llm_service_kind {
+        LLMServiceKind::OpenAI => completion_model
+            .parse::<openai::OpenAICompletionModel>()
+            .is_ok(),
+        LLMServiceKind::Groq => completion_model
+            .parse::<groq::GroqCompletionModel>()
+            .is_ok(),
+        LLMServiceKind::Mock => completion_model
+            .parse::<mock::MockCompletionModel>()
+            .is_ok(),
+    }
+}
+
+// The context window in tokens, falling back to the smallest known window
+// for the models that can't be parsed.
+pub fn context_window(llm_service_kind: LLMServiceKind, completion_model: &str) -> usize {
+    match llm_service_kind {
+        LLMServiceKind::OpenAI => completion_model
+            .parse::<openai::OpenAICompletionModel>()
+            .map(|m| m.context_window()),
+        LLMServiceKind::Groq => completion_model
+            .parse::<groq::GroqCompletionModel>()
+            .map(|m| m.context_window()),
+        LLMServiceKind::Mock => completion_model
+            .parse::<mock::MockCompletionModel>()
+            .map(|m| m.context_window()),
+    }
+    .unwrap_or(4096)
+}
+
+pub fn new_llm_service(
+    llm_service_kind: LLMServiceKind,
+    completion_model: &str,
+    groq_api_key: Option<String>,
+    generation_params: GenerationParams,
+) -> anyhow::Result<Box<dyn LLMService>> {
+    match llm_service_kind {
+        LLMServiceKind::OpenAI => Ok(Box::new(openai::OpenAI {
+            completion_model: completion_model
+                .parse::<openai::OpenAICompletionModel>()
+                .map_err(|_| anyhow!("Invalid OpenAI completion model {}", completion_model))?,
+            generation_params,
+        })),
+        LLMServiceKind::Groq => Ok(Box::new(groq::Groq {
+            completion_model: completion_model
+                .parse::<groq::GroqCompletionModel>()
+                .map_err(|_| anyhow!("Invalid Groq completion model {}", completion_model))?,
+            api_key: groq_api_key.ok_or_else(|| anyhow!("Groq API key is not set"))?,
+            generation_params,
+        })),
+        LLMServiceKind::Mock => Ok(Box::new(mock::Mock {
+            completion_model: completion_model
+                .parse::<mock::MockCompletionModel>()
+                .map_err(|_| anyhow!("Invalid Mock completion model {}", completion_model))?,
+        })),
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct TokenUsage {
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct LLMCompletion {
+    pub content: String,
+    pub usage: Option<TokenUsage>,
+}
+
+#[async_trait]
+pub trait LLMService: Send + Sync {
+    async fn get_completion(
+        &self,
+        thread_messages: Vec<LLMThreadMessage>,
+    ) -> anyhow::Result<LLMCompletion>;
 }
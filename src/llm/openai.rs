@@ -1,5 +1,8 @@
+use crate::llm::GenerationParams;
+use crate::llm::LLMCompletion;
 use crate::llm::LLMService;
 use crate::llm::LLMThreadMessage;
+use crate::llm::TokenUsage;
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
 use std::str::FromStr;
@@ -49,6 +52,15 @@ impl OpenAICompletionModel {
         }
     }
 
+    pub fn context_window(&self) -> usize {
+        match *self {
+            OpenAICompletionModel::Gpt4 | OpenAICompletionModel::Gpt4_0613 => 8192,
+            OpenAICompletionModel::Gpt4_32k | OpenAICompletionModel::Gpt4_32k0613 => 32768,
+            OpenAICompletionModel::Gpt3_5turbo | OpenAICompletionModel::Gpt3_5turbo0613 => 4096,
+            OpenAICompletionModel::Gpt3_5turbo16k0613 => 16384,
+        }
+    }
+
     pub fn default_string() -> String {
         let mock_completion_model: OpenAICompletionModel = Default::default();
         mock_completion_model.as_str().to_string()
@@ -74,15 +86,15 @@ impl FromStr for OpenAICompletionModel {
 
 pub struct OpenAI {
     pub completion_model: OpenAICompletionModel,
+    pub generation_params: GenerationParams,
 }
 
 #[async_trait]
 impl LLMService for OpenAI {
-    fn bot_info(&self) -> String {
-        format!("Bot uses OpenAI with {}", self.completion_model.as_str())
-    }
-
-    async fn get_answer(&self, thread_messages: Vec<LLMThreadMessage>) -> anyhow::Result<String> {
+    async fn get_completion(
+        &self,
+        thread_messages: Vec<LLMThreadMessage>,
+    ) -> anyhow::Result<LLMCompletion> {
         let mut chat_req_messages: Vec<ChatCompletionRequestMessage> = vec![];
 
         for msg in thread_messages {
@@ -94,8 +106,12 @@ impl LLMService for OpenAI {
             chat_req_messages.push(comp_req);
         }
 
-        let chat_completion_response =
-            fetch_response(chat_req_messages, self.completion_model).await;
+        let chat_completion_response = fetch_response(
+            chat_req_messages,
+            self.completion_model,
+            self.generation_params,
+        )
+        .await;
         chat_completion_response.and_then(get_first_choice)
     }
 }
@@ -103,14 +119,22 @@ impl LLMService for OpenAI {
 async fn fetch_response(
     chat_req_messages: Vec<ChatCompletionRequestMessage>,
     completion_model: OpenAICompletionModel,
+    generation_params: GenerationParams,
 ) -> Result<CreateChatCompletionResponse, anyhow::Error> {
     let client = Client::new();
 
-    let request = CreateChatCompletionRequestArgs::default()
-        .max_tokens(512u16)
+    let mut request_args = CreateChatCompletionRequestArgs::default();
+
+    request_args
+        .max_tokens(generation_params.max_tokens.unwrap_or(512u16))
         .model(completion_model.as_str())
-        .messages(chat_req_messages)
-        .build()?;
+        .messages(chat_req_messages);
+
+    if let Some(temperature) = generation_params.temperature {
+        request_args.temperature(temperature);
+    }
+
+    let request = request_args.build()?;
 
     let response = client
         .chat()
@@ -121,17 +145,27 @@ async fn fetch_response(
     Ok(response)
 }
 
-fn get_first_choice(resp: CreateChatCompletionResponse) -> anyhow::Result<String> {
-    resp.choices
+fn get_first_choice(resp: CreateChatCompletionResponse) -> anyhow::Result<LLMCompletion> {
+    let content = resp
+        .choices
         .first()
         .and_then(|choice| choice.message.content.clone())
-        .ok_or_else(|| anyhow!("Message content not found"))
+        .ok_or_else(|| anyhow!("Message content not found"))?;
+
+    Ok(LLMCompletion {
+        content,
+        usage: resp.usage.map(|usage| TokenUsage {
+            prompt_tokens: usage.prompt_tokens.into(),
+            completion_tokens: usage.completion_tokens.into(),
+        }),
+    })
 }
 
 fn to_role(role: &str) -> Role {
     match role {
         "system" => Role::System,
         "user" => Role::User,
+        "assistant" => Role::Assistant,
         _ => Role::User,
     }
 }
@@ -1,13 +1,34 @@
+use clap::Parser;
 use std::env;
 
 mod bot;
+mod code_attachments;
 mod config;
 mod db;
+mod encryption;
+mod knowledge;
 mod llm;
+mod memory;
+mod persona_catalog;
+mod retention;
+mod telegram_html;
+mod thread_export;
+mod thread_import;
+mod thread_title;
+mod usage_limits;
+mod web_page;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
-    let config = config::create_config();
+    let cli = config::Cli::parse();
+
+    encryption::init_from_env();
+
+    if let Some(config::Command::RotateKeys) = cli.command {
+        return rotate_keys().await;
+    }
+
+    let config = config::create_config(&cli);
 
     println!(
         "Stared the telegram bot assistant. Version: {:?}. LLM Service: {:?}",
@@ -27,3 +48,22 @@ async fn main() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+async fn rotate_keys() -> anyhow::Result<()> {
+    let Some(key_id) = encryption::active_key_id() else {
+        anyhow::bail!("Please, set ENCRYPTION_KEYS or ENCRYPTION_KEYS_FILE to rotate the keys.");
+    };
+
+    let db_url = env::var("DATABASE_URL")
+        .map_err(|_| anyhow::anyhow!("Please, set DATABASE_URL to rotate the keys."))?;
+    let db_pool = db::start(&db_url).await;
+
+    for (encrypted_column, count) in db::reencryption::reencrypt_all(&db_pool).await? {
+        println!(
+            "Re-encrypted {} values of {} with the key '{}'.",
+            count, encrypted_column, key_id
+        );
+    }
+
+    Ok(())
+}
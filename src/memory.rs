@@ -0,0 +1,108 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::{Pool, Sqlite};
+
+use crate::db::chat_message;
+use crate::db::memory;
+use crate::llm::llm_thread_message::LLMThreadMessage;
+use crate::llm::LLMService;
+use crate::usage_limits;
+use crate::usage_limits::Subject;
+
+pub const MAX_INJECTED_MEMORIES: usize = 30;
+pub const MAX_MEMORY_LENGTH: usize = 300;
+
+const MAX_TRANSCRIPT_LENGTH: usize = 12000;
+
+const EXTRACTION_PROMPT: &str = "You extract durable facts about the user from a conversation: preferences, personal details, projects and tools they use. Ignore one-off requests and anything about the assistant. Reply with one fact per line, each starting with \"- \", written in the third person (e.g. \"- The user is vegetarian.\"). Don't repeat the already known facts. Reply with NONE if there is nothing worth remembering.";
+
+pub fn memories_prompt(memories: &[memory::Memory]) -> Option<String> {
+    if memories.is_empty() {
+        return None;
+    }
+
+    let facts: Vec<String> = memories
+        .iter()
+        .rev()
+        .take(MAX_INJECTED_MEMORIES)
+        .rev()
+        .map(|m| format!("- {}", m.content))
+        .collect();
+
+    Some(format!(
+        "What you know about the user from previous conversations:\n{}",
+        facts.join("\n")
+    ))
+}
+
+fn parse_extracted_facts(answer: &str) -> Vec<String> {
+    answer
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("- "))
+        .map(|fact| fact.trim().to_string())
+        .filter(|fact| !fact.is_empty() && fact.chars().count() <= MAX_MEMORY_LENGTH)
+        .collect()
+}
+
+// The used tokens are counted against the subjects.
+pub async fn extract_thread_memories(
+    db_conn: &Pool<Sqlite>,
+    llm_api_client: &dyn LLMService,
+    subjects: &[Subject],
+    chat_id: i64,
+    chat_thread_id: i64,
+) -> Result<Vec<memory::Memory>> {
+    let thread_messages = chat_message::get_chat_thread_messages(db_conn, chat_thread_id)
+        .await
+        .context("Failed to get the thread")?;
+
+    if !thread_messages.iter().any(|m| m.user_role == "user") {
+        return Ok(vec![]);
+    }
+
+    let mut transcript: String = thread_messages
+        .iter()
+        .map(|m| format!("{}: {}", m.user_role, m.content))
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    if transcript.chars().count() > MAX_TRANSCRIPT_LENGTH {
+        transcript = transcript
+            .chars()
+            .skip(transcript.chars().count() - MAX_TRANSCRIPT_LENGTH)
+            .collect();
+    }
+
+    let known_memories = memory::get_chat_memories(db_conn, chat_id).await?;
+    let known_facts = memories_prompt(&known_memories).unwrap_or_default();
+
+    let thread_messages = vec![
+        LLMThreadMessage {
+            message: EXTRACTION_PROMPT.to_string(),
+            role: "system".to_string(),
+        },
+        LLMThreadMessage {
+            message: format!("{}\n\nConversation:\n{}", known_facts, transcript),
+            role: "user".to_string(),
+        },
+    ];
+
+    let completion = llm_api_client
+        .get_completion(thread_messages.clone())
+        .await
+        .context("Failed to extract the memories")?;
+
+    let used_tokens =
+        usage_limits::used_tokens(&thread_messages, &completion.content, completion.usage);
+    usage_limits::record_tokens(db_conn, subjects, Utc::now(), used_tokens).await?;
+
+    let mut new_memories: Vec<memory::Memory> = vec![];
+
+    for fact in parse_extracted_facts(&completion.content) {
+        if let Some(new_memory) = memory::insert_memory(db_conn, chat_id, &fact, "auto").await? {
+            new_memories.push(new_memory);
+        }
+    }
+
+    Ok(new_memories)
+}
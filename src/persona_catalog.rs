@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+use crate::llm;
+use crate::llm::behavior_template;
+use crate::llm::LLMServiceKind;
+
+pub const DEFAULT_BEHAVIOR: &str = "You're a helpful assistant.";
+pub const DEFAULT_CATALOG_PATH: &str = "personas.toml";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PersonaExample {
+    pub user: String,
+    pub assistant: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CatalogPersona {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    pub system_prompt: String,
+    #[serde(default)]
+    pub examples: Vec<PersonaExample>,
+    #[serde(default)]
+    pub llm_service: Option<String>,
+    #[serde(default)]
+    pub completion_model: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub max_tokens: Option<u16>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct PersonaCatalog {
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub personas: Vec<CatalogPersona>,
+}
+
+impl PersonaCatalog {
+    pub fn get(&self, name: &str) -> Option<&CatalogPersona> {
+        self.personas.iter().find(|p| p.name == name)
+    }
+
+    pub fn default_behavior(&self) -> &str {
+        self.default
+            .as_ref()
+            .and_then(|name| self.get(name))
+            .map(|p| p.system_prompt.as_str())
+            .unwrap_or(DEFAULT_BEHAVIOR)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (index, persona) in self.personas.iter().enumerate() {
+            // The name is used as the inline keyboard callback data, which is
+            // limited to 64 bytes including the prefix.
+            if persona.name.is_empty() || persona.name.len() > 32 {
+                return Err(anyhow!(
+                    "Persona #{} must have a name of 1-32 characters",
+                    index + 1
+                ));
+            }
+
+            if self.personas[..index]
+                .iter()
+                .any(|p| p.name == persona.name)
+            {
+                return Err(anyhow!("Duplicate persona name '{}'", persona.name));
+            }
+
+            let llm_service = match &persona.llm_service {
+                Some(s) => Some(s.parse::<LLMServiceKind>().map_err(|_| {
+                    anyhow!("Unknown provider '{}' for persona '{}'", s, persona.name)
+                })?),
+                None => None,
+            };
+
+            if let (Some(llm_service), Some(completion_model)) =
+                (llm_service, &persona.completion_model)
+            {
+                if !llm::is_valid_completion_model(llm_service, completion_model) {
+                    return Err(anyhow!(
+                        "Unknown {} completion model '{}' for persona '{}'",
+                        llm_service,
+                        completion_model,
+                        persona.name
+                    ));
+                }
+            }
+
+            // A typo in a variable would otherwise reach the model as it is.
+            let unknown_variables = behavior_template::unknown_variables(&persona.system_prompt);
+
+            if !unknown_variables.is_empty() {
+                return Err(anyhow!(
+                    "Unknown template variables {} in the system prompt of persona '{}'",
+                    unknown_variables
+                        .iter()
+                        .map(|v| format!("{{{}}}", v))
+                        .collect::<Vec<String>>()
+                        .join(", "),
+                    persona.name
+                ));
+            }
+        }
+
+        if let Some(default) = &self.default {
+            if self.get(default).is_none() {
+                return Err(anyhow!(
+                    "Default persona '{}' is not in the catalog",
+                    default
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn load(path: &str) -> Result<PersonaCatalog> {
+    let content =
+        fs::read_to_string(path).context(format!("Failed to read the persona catalog {}", path))?;
+
+    let catalog: PersonaCatalog = toml::from_str(&content)
+        .context(format!("Failed to parse the persona catalog {}", path))?;
+
+    catalog.validate()?;
+
+    Ok(catalog)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Result<PersonaCatalog> {
+        let catalog: PersonaCatalog = toml::from_str(content)?;
+        catalog.validate()?;
+
+        Ok(catalog)
+    }
+
+    #[test]
+    fn refuses_the_unknown_template_variables() {
+        let error = parse(
+            r#"
+            [[personas]]
+            name = "tutor"
+            system_prompt = "You teach {user_firstname} on {date}."
+            "#,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Unknown template variables {user_firstname} in the system prompt of persona 'tutor'"
+        );
+        assert!(parse(
+            r#"
+            [[personas]]
+            name = "tutor"
+            system_prompt = "You teach {user_first_name} on {date}. Reply with {\"answer\": ...}."
+            "#,
+        )
+        .is_ok());
+    }
+}
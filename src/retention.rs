@@ -0,0 +1,28 @@
+use sqlx::{Pool, Sqlite};
+use std::time::Duration;
+
+use crate::db::chat_data;
+
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Runs for the whole life of the bot, since the chats may set their own
+// retention even when there is no default one. A failed run is only logged and
+// retried on the next tick.
+pub fn spawn_retention_task(db_conn: Pool<Sqlite>, default_retention_days: Option<u32>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match chat_data::delete_expired_chat_data(&db_conn, default_retention_days).await {
+                Ok(expired) if !expired.is_empty() => println!(
+                    "Deleted the expired data: {} messages, {} threads, {} model comparisons",
+                    expired.chat_messages, expired.chat_threads, expired.model_comparisons
+                ),
+                Ok(_) => {}
+                Err(e) => println!("Failed to delete the expired data: {:?}", e),
+            }
+        }
+    });
+}
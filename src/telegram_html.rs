@@ -0,0 +1,539 @@
+use pulldown_cmark::{Alignment, CodeBlockKind, Event, Options, Parser, Tag, TagEnd};
+
+// Telegram refuses the messages longer than this, counted in UTF-16 code units.
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+// Telegram's HTML parse mode only needs these characters escaped, which is
+// also enough for the exported HTML documents.
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+struct Table {
+    alignments: Vec<Alignment>,
+    rows: Vec<Vec<String>>,
+    cell: String,
+}
+
+// Telegram has no tables, so they are drawn with padded columns in a
+// preformatted block.
+impl Table {
+    fn render(&self) -> String {
+        let columns = self.rows.iter().map(|r| r.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .filter_map(|r| r.get(column))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+
+        let render_row = |row: &Vec<String>| {
+            widths
+                .iter()
+                .enumerate()
+                .map(|(column, width)| {
+                    let cell = row.get(column).map(String::as_str).unwrap_or_default();
+                    let padding = width - cell.chars().count();
+
+                    match self.alignments.get(column) {
+                        Some(Alignment::Right) => format!("{}{}", " ".repeat(padding), cell),
+                        Some(Alignment::Center) => format!(
+                            "{}{}{}",
+                            " ".repeat(padding / 2),
+                            cell,
+                            " ".repeat(padding - padding / 2)
+                        ),
+                        _ => format!("{}{}", cell, " ".repeat(padding)),
+                    }
+                })
+                .collect::<Vec<String>>()
+                .join(" | ")
+                .trim_end()
+                .to_string()
+        };
+
+        let mut lines: Vec<String> = vec![];
+
+        for (index, row) in self.rows.iter().enumerate() {
+            lines.push(render_row(row));
+
+            if index == 0 {
+                lines.push(
+                    widths
+                        .iter()
+                        .map(|width| "-".repeat(*width))
+                        .collect::<Vec<String>>()
+                        .join("-|-"),
+                );
+            }
+        }
+
+        lines.join("\n")
+    }
+}
+
+#[derive(Default)]
+struct HtmlWriter {
+    html: String,
+    // The next number of each open list, None for the bulleted ones.
+    lists: Vec<Option<u64>>,
+    table: Option<Table>,
+    code_block_closing: Option<&'static str>,
+}
+
+impl HtmlWriter {
+    fn ensure_newline(&mut self) {
+        if !self.html.is_empty() && !self.html.ends_with('\n') {
+            self.html.push('\n');
+        }
+    }
+
+    // The blocks are separated by an empty line, except in the list items.
+    fn end_block(&mut self) {
+        self.html.truncate(self.html.trim_end().len());
+
+        if self.html.is_empty() {
+            return;
+        }
+
+        self.html
+            .push_str(if self.lists.is_empty() { "\n\n" } else { "\n" });
+    }
+
+    fn text(&mut self, text: &str) {
+        match &mut self.table {
+            Some(table) => table.cell.push_str(text),
+            None => self.html.push_str(&escape(text)),
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        if self.table.is_some() {
+            if let Tag::TableCell = tag {
+                if let Some(table) = &mut self.table {
+                    table.cell.clear();
+                }
+            } else if let Tag::TableHead | Tag::TableRow = tag {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(vec![]);
+                }
+            }
+
+            return;
+        }
+
+        match tag {
+            Tag::Heading { .. } => self.html.push_str("<b>"),
+            Tag::BlockQuote(_) => self.html.push_str("<blockquote>"),
+            Tag::CodeBlock(kind) => {
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info
+                        .split_whitespace()
+                        .next()
+                        .map(|language| language.to_string()),
+                    CodeBlockKind::Indented => None,
+                };
+
+                match language {
+                    Some(language) => {
+                        self.html.push_str(&format!(
+                            "<pre><code class=\"language-{}\">",
+                            escape(&language)
+                        ));
+                        self.code_block_closing = Some("</code></pre>");
+                    }
+                    None => {
+                        self.html.push_str("<pre>");
+                        self.code_block_closing = Some("</pre>");
+                    }
+                }
+            }
+            Tag::List(start) => {
+                self.ensure_newline();
+                self.lists.push(start);
+            }
+            Tag::Item => {
+                self.ensure_newline();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+
+                let marker = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+
+                self.html.push_str(&format!("{}{}", indent, marker));
+            }
+            Tag::Table(alignments) => {
+                self.table = Some(Table {
+                    alignments,
+                    rows: vec![],
+                    cell: String::new(),
+                });
+            }
+            Tag::Emphasis => self.html.push_str("<i>"),
+            Tag::Strong => self.html.push_str("<b>"),
+            Tag::Strikethrough => self.html.push_str("<s>"),
+            Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => self
+                .html
+                .push_str(&format!("<a href=\"{}\">", escape(&dest_url))),
+            _ => {}
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        if let Some(table) = &mut self.table {
+            match tag {
+                TagEnd::TableCell => {
+                    let cell = table.cell.trim().to_string();
+
+                    if let Some(row) = table.rows.last_mut() {
+                        row.push(cell);
+                    }
+                }
+                TagEnd::Table => {
+                    let rendered = table.render();
+                    self.table = None;
+                    self.html
+                        .push_str(&format!("<pre>{}</pre>", escape(&rendered)));
+                    self.end_block();
+                }
+                _ => {}
+            }
+
+            return;
+        }
+
+        match tag {
+            TagEnd::Paragraph => self.end_block(),
+            TagEnd::Heading(_) => {
+                self.html.push_str("</b>");
+                self.end_block();
+            }
+            TagEnd::BlockQuote(_) => {
+                self.html.truncate(self.html.trim_end().len());
+                self.html.push_str("</blockquote>");
+                self.end_block();
+            }
+            TagEnd::CodeBlock => {
+                self.html.truncate(self.html.trim_end_matches('\n').len());
+                self.html
+                    .push_str(self.code_block_closing.take().unwrap_or("</pre>"));
+                self.end_block();
+            }
+            TagEnd::List(_) => {
+                self.lists.pop();
+                self.end_block();
+            }
+            TagEnd::Item => self.ensure_newline(),
+            TagEnd::Emphasis => self.html.push_str("</i>"),
+            TagEnd::Strong => self.html.push_str("</b>"),
+            TagEnd::Strikethrough => self.html.push_str("</s>"),
+            TagEnd::Link | TagEnd::Image => self.html.push_str("</a>"),
+            _ => {}
+        }
+    }
+
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(tag) => self.end(tag),
+            Event::Text(text) | Event::Html(text) | Event::InlineHtml(text) => self.text(&text),
+            Event::Code(code) | Event::InlineMath(code) => match &mut self.table {
+                Some(table) => table.cell.push_str(&code),
+                None => self
+                    .html
+                    .push_str(&format!("<code>{}</code>", escape(&code))),
+            },
+            Event::DisplayMath(math) => {
+                self.html.push_str(&format!("<pre>{}</pre>", escape(&math)));
+                self.end_block();
+            }
+            Event::FootnoteReference(name) => self.text(&format!("[{}]", name)),
+            Event::SoftBreak | Event::HardBreak => match &mut self.table {
+                Some(table) => table.cell.push(' '),
+                None => self.html.push('\n'),
+            },
+            Event::Rule => {
+                self.end_block();
+                self.html.push_str("──────────");
+                self.end_block();
+            }
+            Event::TaskListMarker(checked) => self.text(if checked { "☑ " } else { "☐ " }),
+        }
+    }
+}
+
+// Renders the Markdown of the models' answers with the subset of HTML that
+// Telegram supports.
+pub fn markdown_to_html(markdown: &str) -> String {
+    let options =
+        Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH | Options::ENABLE_TASKLISTS;
+    let mut writer = HtmlWriter::default();
+
+    for event in Parser::new_ext(markdown, options) {
+        writer.event(event);
+    }
+
+    writer.html.trim_end().to_string()
+}
+
+enum Piece<'a> {
+    Open { name: &'a str, tag: &'a str },
+    Close { name: &'a str, tag: &'a str },
+    Text(&'a str),
+}
+
+impl Piece<'_> {
+    fn as_str(&self) -> &str {
+        match self {
+            Piece::Open { tag, .. } => tag,
+            Piece::Close { tag, .. } => tag,
+            Piece::Text(text) => text,
+        }
+    }
+}
+
+fn utf16_len(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+// The tags, and the text split into characters with the entities kept whole.
+fn pieces(html: &str) -> Vec<Piece<'_>> {
+    let mut pieces = vec![];
+    let mut rest = html;
+
+    while let Some(first) = rest.chars().next() {
+        let end = match first {
+            '<' => rest.find('>').map_or(rest.len(), |i| i + 1),
+            '&' => rest.find(';').filter(|i| *i <= 8).map_or(1, |i| i + 1),
+            _ => first.len_utf8(),
+        };
+        let (piece, remaining) = rest.split_at(end);
+
+        pieces.push(if let Some(name) = piece.strip_prefix("</") {
+            Piece::Close {
+                name: name.trim_end_matches('>'),
+                tag: piece,
+            }
+        } else if first == '<' && end > 1 {
+            let name = piece[1..]
+                .split(|c: char| c == '>' || c.is_whitespace())
+                .next()
+                .unwrap_or_default();
+
+            Piece::Open { name, tag: piece }
+        } else {
+            Piece::Text(piece)
+        });
+
+        rest = remaining;
+    }
+
+    pieces
+}
+
+fn closing_tags(open_tags: &[(&str, &str)]) -> String {
+    open_tags
+        .iter()
+        .rev()
+        .map(|(name, _)| format!("</{}>", name))
+        .collect()
+}
+
+// The last place where the message can be cut, with the tags open there.
+struct Cut<'a> {
+    piece_index: usize,
+    length: usize,
+    open_tags: Vec<(&'a str, &'a str)>,
+}
+
+// Splits the HTML into messages of at most max_length, preferably between the
+// paragraphs and the code blocks, then between the lines and then between the
+// words. The tags open at a cut are closed at the end of the message and
+// opened again at the start of the next one.
+pub fn split_message(html: &str, max_length: usize) -> Vec<String> {
+    let pieces = pieces(html);
+    let mut messages = vec![];
+    let mut open_tags: Vec<(&str, &str)> = vec![];
+    let mut index = 0;
+
+    while index < pieces.len() {
+        let mut message: String = open_tags.iter().map(|(_, tag)| *tag).collect();
+        let prefix_length = message.len();
+        let mut length = utf16_len(&message);
+        let mut tags = open_tags.clone();
+        let mut cuts: [Option<Cut>; 3] = [None, None, None];
+        let mut next = index;
+
+        while next < pieces.len() {
+            let piece = &pieces[next];
+            let mut next_tags = tags.clone();
+
+            match piece {
+                Piece::Open { name, tag } => next_tags.push((*name, *tag)),
+                Piece::Close { name, .. } => {
+                    if let Some(position) = next_tags.iter().rposition(|(n, _)| n == name) {
+                        next_tags.truncate(position);
+                    }
+                }
+                Piece::Text(_) => {}
+            }
+
+            let piece_length = utf16_len(piece.as_str());
+
+            if message.len() > prefix_length
+                && length + piece_length + utf16_len(&closing_tags(&next_tags)) > max_length
+            {
+                break;
+            }
+
+            message.push_str(piece.as_str());
+            length += piece_length;
+            tags = next_tags;
+            next += 1;
+
+            let cut_level = match piece {
+                Piece::Text("\n") if tags.is_empty() && message.ends_with("\n\n") => Some(0),
+                Piece::Text("\n") => Some(1),
+                Piece::Text(" ") => Some(2),
+                _ => None,
+            };
+
+            // A message isn't cut before any of its text.
+            if let Some(level) = cut_level.filter(|_| !message[prefix_length..].trim().is_empty()) {
+                cuts[level] = Some(Cut {
+                    piece_index: next,
+                    length: message.len(),
+                    open_tags: tags.clone(),
+                });
+            }
+        }
+
+        if next < pieces.len() {
+            if let Some(cut) = cuts.into_iter().flatten().next() {
+                message.truncate(cut.length);
+                tags = cut.open_tags;
+                next = cut.piece_index;
+            }
+        }
+
+        if !message[prefix_length..].trim().is_empty() {
+            messages.push(format!("{}{}", message.trim_end(), closing_tags(&tags)));
+        }
+
+        open_tags = tags;
+        index = next;
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_nested_formatting() {
+        assert_eq!(
+            markdown_to_html("**bold _italic ~~struck~~_** and `code`"),
+            "<b>bold <i>italic <s>struck</s></i></b> and <code>code</code>"
+        );
+        assert_eq!(
+            markdown_to_html("> **quoted**\n\n1. one\n   - nested"),
+            "<blockquote><b>quoted</b></blockquote>\n\n1. one\n  • nested"
+        );
+    }
+
+    #[test]
+    fn keeps_the_unbalanced_markers_as_text() {
+        assert_eq!(markdown_to_html("**bold"), "**bold");
+        assert_eq!(markdown_to_html("_italic **bold_"), "<i>italic **bold</i>");
+    }
+
+    #[test]
+    fn escapes_the_html_characters() {
+        assert_eq!(
+            markdown_to_html("a < b && c > d"),
+            "a &lt; b &amp;&amp; c &gt; d"
+        );
+        assert_eq!(
+            markdown_to_html("<script>alert(1)</script>"),
+            "&lt;script&gt;alert(1)&lt;/script&gt;"
+        );
+        assert_eq!(
+            markdown_to_html("```rust\nif a < b && c > d {}\n```"),
+            "<pre><code class=\"language-rust\">if a &lt; b &amp;&amp; c &gt; d {}</code></pre>"
+        );
+        assert_eq!(
+            markdown_to_html("[link](https://example.com/?a=1&b=\"2\")"),
+            "<a href=\"https://example.com/?a=1&amp;b=&quot;2&quot;\">link</a>"
+        );
+    }
+
+    #[test]
+    fn keeps_a_short_message_whole() {
+        assert_eq!(
+            split_message("<b>Hello</b> world", MAX_MESSAGE_LENGTH),
+            vec!["<b>Hello</b> world"]
+        );
+    }
+
+    #[test]
+    fn counts_the_surrogate_pairs_as_two_units() {
+        let messages = split_message("😀😀😀 😀😀", 5);
+
+        assert_eq!(messages, vec!["😀😀", "😀", "😀😀"]);
+        assert!(messages.iter().all(|m| utf16_len(m) <= 5));
+    }
+
+    #[test]
+    fn prefers_the_paragraph_breaks() {
+        assert_eq!(
+            split_message("first paragraph\n\nsecond one here", 25),
+            vec!["first paragraph", "second one here"]
+        );
+    }
+
+    #[test]
+    fn reopens_the_tags_of_a_split_pre_block() {
+        let html =
+            "<pre><code class=\"language-rust\">let a = 1;\nlet b = 2;\nlet c = 3;</code></pre>";
+        let messages = split_message(html, 60);
+
+        assert_eq!(
+            messages,
+            vec![
+                "<pre><code class=\"language-rust\">let a = 1;</code></pre>",
+                "<pre><code class=\"language-rust\">let b = 2;</code></pre>",
+                "<pre><code class=\"language-rust\">let c = 3;</code></pre>",
+            ]
+        );
+        assert!(messages.iter().all(|m| utf16_len(m) <= 60));
+    }
+
+    #[test]
+    fn keeps_the_entities_whole() {
+        let messages = split_message("&amp;&lt;&gt;&amp;", 10);
+
+        assert_eq!(messages, vec!["&amp;&lt;", "&gt;&amp;"]);
+    }
+
+    #[test]
+    fn survives_the_unbalanced_tags() {
+        assert_eq!(
+            split_message("<b>open one two", 12),
+            vec!["<b>open</b>", "<b>one</b>", "<b>two</b>"]
+        );
+        assert_eq!(split_message("one</i> two", 8), vec!["one</i>", "two"]);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::llm::LLMServiceKind;
+use crate::telegram_html;
+use anyhow::{anyhow, Result};
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Bumped on every incompatible change of the JSON export, which is read back
+// by the import.
+pub const EXPORT_FORMAT: &str = "telegram-llm-assistant/thread";
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match *self {
+            ExportFormat::Markdown => "text/markdown",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Html => "text/html",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "md" | "markdown" => Ok(ExportFormat::Markdown),
+            "json" => Ok(ExportFormat::Json),
+            "html" => Ok(ExportFormat::Html),
+            _ => Err(anyhow!("Unknown export format {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ThreadExport {
+    pub format: String,
+    pub version: u32,
+    pub exported_at: String,
+    pub thread: ExportedThread,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedThread {
+    pub title: String,
+    pub llm_service: String,
+    pub completion_model: String,
+    pub persona: Option<String>,
+    pub behavior: String,
+    pub messages: Vec<ExportedMessage>,
+}
+
+// The timestamps are RFC 3339 strings in UTC.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ExportedMessage {
+    pub role: String,
+    pub content: String,
+    pub inserted_at: String,
+}
+
+impl ThreadExport {
+    pub fn new(thread: ExportedThread) -> Self {
+        Self {
+            format: EXPORT_FORMAT.to_string(),
+            version: EXPORT_VERSION,
+            exported_at: chrono::Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            thread,
+        }
+    }
+
+    pub fn file_name(&self, format: ExportFormat) -> String {
+        let slug = self
+            .thread
+            .title
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|w| !w.is_empty())
+            .collect::<Vec<&str>>()
+            .join("-")
+            .chars()
+            .take(40)
+            .collect::<String>();
+
+        format!(
+            "{}.{}",
+            if slug.is_empty() { "thread" } else { &slug },
+            format.extension()
+        )
+    }
+
+    pub fn render(&self, format: ExportFormat) -> Result<String> {
+        match format {
+            ExportFormat::Markdown => Ok(self.render_markdown()),
+            ExportFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            ExportFormat::Html => Ok(self.render_html()),
+        }
+    }
+
+    fn model(&self) -> String {
+        let llm_service = self
+            .thread
+            .llm_service
+            .parse::<LLMServiceKind>()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|_| self.thread.llm_service.clone());
+
+        format!("{} {}", llm_service, self.thread.completion_model)
+    }
+
+    fn render_markdown(&self) -> String {
+        let mut document = format!("# {}\n\n", self.thread.title);
+
+        document.push_str(&format!("- Exported: {}\n", self.exported_at));
+        document.push_str(&format!("- Model: {}\n", self.model()));
+
+        if let Some(persona) = &self.thread.persona {
+            document.push_str(&format!("- Persona: {}\n", persona));
+        }
+
+        document.push_str("- Behavior:\n\n");
+
+        for line in self.thread.behavior.lines() {
+            document.push_str(&format!("  > {}\n", line));
+        }
+
+        for message in &self.thread.messages {
+            document.push_str(&format!(
+                "\n---\n\n### {} · {}\n\n{}\n",
+                role_label(&message.role),
+                message.inserted_at,
+                message.content
+            ));
+        }
+
+        document
+    }
+
+    fn render_html(&self) -> String {
+        let mut document = format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 48em; margin: 2em auto; padding: 0 1em; }}
+.meta {{ color: #666; }}
+.message {{ border-top: 1px solid #ddd; padding: 0.5em 0; }}
+.content {{ white-space: pre-wrap; }}
+.user .role {{ color: #1a5fb4; }}
+.assistant .role {{ color: #26a269; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+<ul class="meta">
+<li>Exported: {exported_at}</li>
+<li>Model: {model}</li>
+"#,
+            title = telegram_html::escape(&self.thread.title),
+            exported_at = telegram_html::escape(&self.exported_at),
+            model = telegram_html::escape(&self.model()),
+        );
+
+        if let Some(persona) = &self.thread.persona {
+            document.push_str(&format!(
+                "<li>Persona: {}</li>\n",
+                telegram_html::escape(persona)
+            ));
+        }
+
+        document.push_str(&format!(
+            "<li>Behavior: <div class=\"content\">{}</div></li>\n</ul>\n",
+            telegram_html::escape(&self.thread.behavior)
+        ));
+
+        for message in &self.thread.messages {
+            document.push_str(&format!(
+                "<div class=\"message {}\">\n<p><strong class=\"role\">{}</strong> <span class=\"meta\">{}</span></p>\n<div class=\"content\">{}</div>\n</div>\n",
+                telegram_html::escape(&message.role),
+                role_label(&message.role),
+                telegram_html::escape(&message.inserted_at),
+                telegram_html::escape(&message.content)
+            ));
+        }
+
+        document.push_str("</body>\n</html>\n");
+
+        document
+    }
+}
+
+fn role_label(role: &str) -> &str {
+    match role {
+        "user" => "User",
+        "assistant" => "Assistant",
+        "system" => "System",
+        _ => role,
+    }
+}
@@ -0,0 +1,415 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde_json::Value;
+
+use crate::thread_export::{ThreadExport, EXPORT_FORMAT, EXPORT_VERSION};
+
+// The largest file the Telegram bot API lets bots download.
+pub const MAX_IMPORT_SIZE: i64 = 20 * 1024 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct ImportedConversation {
+    pub title: String,
+    pub messages: Vec<ImportedMessage>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportedMessage {
+    pub role: String,
+    pub content: String,
+    pub inserted_at: DateTime<Utc>,
+}
+
+// Reads either a thread exported with /export json or the conversations.json
+// of a ChatGPT data export. The conversations without user or assistant
+// messages are left out.
+pub fn parse_conversations(content: &[u8]) -> Result<Vec<ImportedConversation>> {
+    let value: Value =
+        serde_json::from_slice(content).map_err(|e| anyhow!("The file isn't valid JSON: {}", e))?;
+
+    let conversations = match &value {
+        Value::Object(fields)
+            if fields.get("format").and_then(Value::as_str) == Some(EXPORT_FORMAT) =>
+        {
+            vec![parse_thread_export(value)?]
+        }
+        Value::Array(items) => items
+            .iter()
+            .filter_map(parse_chatgpt_conversation)
+            .collect(),
+        _ => {
+            return Err(anyhow!(
+                "The file is neither a ChatGPT conversations.json nor a thread export."
+            ))
+        }
+    };
+
+    Ok(conversations
+        .into_iter()
+        .filter(|c| !c.messages.is_empty())
+        .map(with_increasing_timestamps)
+        .collect())
+}
+
+fn parse_thread_export(value: Value) -> Result<ImportedConversation> {
+    let export: ThreadExport = serde_json::from_value(value)
+        .map_err(|e| anyhow!("The thread export is malformed: {}", e))?;
+
+    if export.version > EXPORT_VERSION {
+        return Err(anyhow!(
+            "The thread export has the version {}, while the bot reads up to the version {}.",
+            export.version,
+            EXPORT_VERSION
+        ));
+    }
+
+    let exported_at = DateTime::parse_from_rfc3339(&export.exported_at)
+        .map(|d| d.with_timezone(&Utc))
+        .unwrap_or_else(|_| Utc::now());
+
+    Ok(ImportedConversation {
+        title: export.thread.title,
+        messages: export
+            .thread
+            .messages
+            .into_iter()
+            .filter(|m| is_conversation_role(&m.role))
+            .map(|m| ImportedMessage {
+                inserted_at: DateTime::parse_from_rfc3339(&m.inserted_at)
+                    .map(|d| d.with_timezone(&Utc))
+                    .unwrap_or(exported_at),
+                role: m.role,
+                content: m.content,
+            })
+            .collect(),
+    })
+}
+
+// A ChatGPT conversation is a tree of messages keyed by id, and the shown
+// branch is the path from `current_node` up to the root.
+fn parse_chatgpt_conversation(conversation: &Value) -> Option<ImportedConversation> {
+    let mapping = conversation.get("mapping")?.as_object()?;
+    let created_at = conversation
+        .get("create_time")
+        .and_then(timestamp)
+        .unwrap_or_else(Utc::now);
+
+    let mut nodes: Vec<(String, String, Option<DateTime<Utc>>)> = vec![];
+    let mut node_id = conversation.get("current_node")?.as_str()?;
+
+    // The visited nodes are bounded by the mapping size to survive cycles.
+    for _ in 0..mapping.len() {
+        let node = mapping.get(node_id)?;
+
+        if let Some(message) = node.get("message").and_then(parse_chatgpt_message) {
+            nodes.push(message);
+        }
+
+        match node.get("parent").and_then(Value::as_str) {
+            Some(parent_id) => node_id = parent_id,
+            None => break,
+        }
+    }
+
+    // The messages without a timestamp take the one of the previous message.
+    let mut previous_inserted_at = created_at;
+    let messages: Vec<ImportedMessage> = nodes
+        .into_iter()
+        .rev()
+        .map(|(role, content, inserted_at)| {
+            previous_inserted_at = inserted_at.unwrap_or(previous_inserted_at);
+
+            ImportedMessage {
+                role,
+                content,
+                inserted_at: previous_inserted_at,
+            }
+        })
+        .collect();
+
+    Some(ImportedConversation {
+        title: conversation
+            .get("title")
+            .and_then(Value::as_str)
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .unwrap_or("ChatGPT conversation")
+            .to_string(),
+        messages,
+    })
+}
+
+fn parse_chatgpt_message(message: &Value) -> Option<(String, String, Option<DateTime<Utc>>)> {
+    let role = message.get("author")?.get("role")?.as_str()?;
+
+    if !is_conversation_role(role) {
+        return None;
+    }
+
+    let is_hidden = message
+        .get("metadata")
+        .and_then(|m| m.get("is_visually_hidden_from_conversation"))
+        .and_then(Value::as_bool)
+        .unwrap_or(false);
+
+    if is_hidden {
+        return None;
+    }
+
+    // Only the text parts are kept: images and other attachments are objects.
+    let content = message
+        .get("content")?
+        .get("parts")?
+        .as_array()?
+        .iter()
+        .filter_map(Value::as_str)
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim()
+        .to_string();
+
+    if content.is_empty() {
+        return None;
+    }
+
+    Some((
+        role.to_string(),
+        content,
+        message.get("create_time").and_then(timestamp),
+    ))
+}
+
+fn timestamp(value: &Value) -> Option<DateTime<Utc>> {
+    let seconds = value.as_f64()?;
+
+    Utc.timestamp_millis_opt((seconds * 1000.0) as i64).single()
+}
+
+fn is_conversation_role(role: &str) -> bool {
+    role == "user" || role == "assistant"
+}
+
+// The messages of a thread are ordered by their timestamps, so equal or
+// decreasing ones are moved a millisecond after the previous message.
+fn with_increasing_timestamps(mut conversation: ImportedConversation) -> ImportedConversation {
+    let mut previous_inserted_at: Option<DateTime<Utc>> = None;
+
+    for message in conversation.messages.iter_mut() {
+        if let Some(previous_inserted_at) = previous_inserted_at {
+            if message.inserted_at <= previous_inserted_at {
+                message.inserted_at = previous_inserted_at + Duration::milliseconds(1);
+            }
+        }
+
+        previous_inserted_at = Some(message.inserted_at);
+    }
+
+    conversation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roles_and_contents(conversation: &ImportedConversation) -> Vec<(&str, &str)> {
+        conversation
+            .messages
+            .iter()
+            .map(|m| (m.role.as_str(), m.content.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_shown_branch_of_a_chatgpt_conversation() {
+        let conversations = parse_conversations(
+            br#"[{
+                "title": "Monads",
+                "create_time": 1700000000.0,
+                "current_node": "answer",
+                "mapping": {
+                    "root": {"message": null, "parent": null},
+                    "system": {
+                        "parent": "root",
+                        "message": {"author": {"role": "system"}, "content": {"parts": ["You are ChatGPT"]}}
+                    },
+                    "hidden": {
+                        "parent": "system",
+                        "message": {
+                            "author": {"role": "user"},
+                            "content": {"parts": ["custom instructions"]},
+                            "metadata": {"is_visually_hidden_from_conversation": true}
+                        }
+                    },
+                    "question": {
+                        "parent": "hidden",
+                        "message": {
+                            "author": {"role": "user"},
+                            "create_time": 1700000010.5,
+                            "content": {"parts": ["What is a monad?", {"asset_pointer": "file-1"}]}
+                        }
+                    },
+                    "other_answer": {
+                        "parent": "question",
+                        "message": {"author": {"role": "assistant"}, "content": {"parts": ["A burrito."]}}
+                    },
+                    "answer": {
+                        "parent": "question",
+                        "message": {
+                            "author": {"role": "assistant"},
+                            "create_time": 1700000020.0,
+                            "content": {"parts": ["A monoid in the category of endofunctors."]}
+                        }
+                    }
+                }
+            }]"#,
+        )
+        .unwrap();
+
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].title, "Monads");
+        assert_eq!(
+            roles_and_contents(&conversations[0]),
+            vec![
+                ("user", "What is a monad?"),
+                ("assistant", "A monoid in the category of endofunctors.")
+            ]
+        );
+        assert_eq!(
+            conversations[0].messages[0].inserted_at,
+            Utc.timestamp_millis_opt(1700000010500).unwrap()
+        );
+    }
+
+    #[test]
+    fn survives_a_cycle_in_the_chatgpt_tree() {
+        let conversations = parse_conversations(
+            br#"[{
+                "title": "  ",
+                "current_node": "a",
+                "mapping": {
+                    "a": {
+                        "parent": "b",
+                        "message": {"author": {"role": "assistant"}, "content": {"parts": ["Hi!"]}}
+                    },
+                    "b": {
+                        "parent": "a",
+                        "message": {"author": {"role": "user"}, "content": {"parts": ["Hello"]}}
+                    }
+                }
+            }]"#,
+        )
+        .unwrap();
+
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].title, "ChatGPT conversation");
+        assert_eq!(
+            roles_and_contents(&conversations[0]),
+            vec![("user", "Hello"), ("assistant", "Hi!")]
+        );
+    }
+
+    #[test]
+    fn leaves_out_the_conversations_without_messages() {
+        let conversations = parse_conversations(
+            br#"[{
+                "title": "Empty",
+                "current_node": "root",
+                "mapping": {"root": {"message": null, "parent": null}}
+            }]"#,
+        )
+        .unwrap();
+
+        assert!(conversations.is_empty());
+    }
+
+    #[test]
+    fn reads_a_thread_export() {
+        let conversations = parse_conversations(
+            br#"{
+                "format": "telegram-llm-assistant/thread",
+                "version": 1,
+                "exported_at": "2024-05-01T10:00:00.000Z",
+                "thread": {
+                    "title": "Trip",
+                    "llm_service": "groq",
+                    "completion_model": "llama3-70b-8192",
+                    "persona": null,
+                    "behavior": "You are a travel agent.",
+                    "messages": [
+                        {"role": "system", "content": "You are a travel agent.", "inserted_at": "2024-05-01T09:00:00.000Z"},
+                        {"role": "user", "content": "Where to go in May?", "inserted_at": "2024-05-01T09:00:00.000Z"},
+                        {"role": "assistant", "content": "Lisbon.", "inserted_at": "not a date"}
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(conversations.len(), 1);
+        assert_eq!(conversations[0].title, "Trip");
+        assert_eq!(
+            roles_and_contents(&conversations[0]),
+            vec![("user", "Where to go in May?"), ("assistant", "Lisbon.")]
+        );
+        // The invalid timestamp falls back to the export time.
+        assert_eq!(
+            conversations[0].messages[1].inserted_at,
+            Utc.with_ymd_and_hms(2024, 5, 1, 10, 0, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn refuses_a_newer_thread_export_version() {
+        let error = parse_conversations(
+            br#"{
+                "format": "telegram-llm-assistant/thread",
+                "version": 99,
+                "exported_at": "2024-05-01T10:00:00.000Z",
+                "thread": {"title": "", "llm_service": "", "completion_model": "", "persona": null, "behavior": "", "messages": []}
+            }"#,
+        )
+        .unwrap_err();
+
+        assert!(error.to_string().contains("version 99"));
+    }
+
+    #[test]
+    fn refuses_other_json() {
+        assert!(parse_conversations(br#"{"hello": "world"}"#).is_err());
+        assert!(parse_conversations(b"not json").is_err());
+    }
+
+    #[test]
+    fn makes_the_timestamps_increasing() {
+        let at = |seconds: i64| Utc.timestamp_opt(seconds, 0).unwrap();
+        let message = |inserted_at| ImportedMessage {
+            role: "user".to_string(),
+            content: "text".to_string(),
+            inserted_at,
+        };
+
+        let conversation = with_increasing_timestamps(ImportedConversation {
+            title: "Thread".to_string(),
+            messages: vec![
+                message(at(100)),
+                message(at(100)),
+                message(at(50)),
+                message(at(200)),
+            ],
+        });
+
+        assert_eq!(
+            conversation
+                .messages
+                .iter()
+                .map(|m| m.inserted_at)
+                .collect::<Vec<DateTime<Utc>>>(),
+            vec![
+                at(100),
+                at(100) + Duration::milliseconds(1),
+                at(100) + Duration::milliseconds(2),
+                at(200)
+            ]
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sqlx::{Pool, Sqlite};
+
+use crate::db::chat_message;
+use crate::db::chat_thread;
+use crate::llm::llm_thread_message::LLMThreadMessage;
+use crate::llm::LLMService;
+use crate::usage_limits;
+use crate::usage_limits::Subject;
+
+pub const MAX_TITLE_LENGTH: usize = 60;
+
+const MAX_EXCHANGE_MESSAGE_LENGTH: usize = 2000;
+
+const TITLE_PROMPT: &str = "You name conversations. Reply only with a title of at most six words that describes the conversation, in the language of the conversation, without quotes or a final period.";
+
+fn truncate(text: &str, max_length: usize) -> String {
+    if text.chars().count() > max_length {
+        format!("{}…", text.chars().take(max_length).collect::<String>())
+    } else {
+        text.to_string()
+    }
+}
+
+// The first line of the first user message, for the threads without a title.
+pub fn fallback_title(first_message: Option<&str>) -> String {
+    first_message
+        .and_then(|m| m.lines().map(str::trim).find(|l| !l.is_empty()))
+        .map(|line| truncate(line, MAX_TITLE_LENGTH))
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+pub fn clean_title(title: &str) -> Option<String> {
+    let title = title
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())?
+        .trim_matches(|c: char| c == '"' || c == '\'' || c == '*' || c == '#')
+        .trim_end_matches('.')
+        .trim();
+
+    if title.is_empty() {
+        None
+    } else {
+        Some(truncate(title, MAX_TITLE_LENGTH))
+    }
+}
+
+// Names the thread after its first exchange, unless it already has a title.
+// The used tokens are counted against the subjects.
+pub async fn generate_thread_title(
+    db_conn: &Pool<Sqlite>,
+    llm_api_client: &dyn LLMService,
+    subjects: &[Subject],
+    chat_thread_id: i64,
+) -> Result<Option<String>> {
+    let thread_messages = chat_message::get_chat_thread_messages(db_conn, chat_thread_id)
+        .await
+        .context("Failed to get the thread")?;
+
+    let Some(question_position) = thread_messages.iter().position(|m| m.user_role == "user") else {
+        return Ok(None);
+    };
+
+    let Some(answer) = thread_messages[question_position..]
+        .iter()
+        .find(|m| m.user_role == "assistant")
+    else {
+        return Ok(None);
+    };
+
+    let title_messages = vec![
+        LLMThreadMessage {
+            message: TITLE_PROMPT.to_string(),
+            role: "system".to_string(),
+        },
+        LLMThreadMessage {
+            message: format!(
+                "user: {}\nassistant: {}",
+                truncate(
+                    &thread_messages[question_position].content,
+                    MAX_EXCHANGE_MESSAGE_LENGTH
+                ),
+                truncate(&answer.content, MAX_EXCHANGE_MESSAGE_LENGTH)
+            ),
+            role: "user".to_string(),
+        },
+    ];
+
+    let completion = llm_api_client
+        .get_completion(title_messages.clone())
+        .await
+        .context("Failed to generate the thread title")?;
+
+    let used_tokens =
+        usage_limits::used_tokens(&title_messages, &completion.content, completion.usage);
+    usage_limits::record_tokens(db_conn, subjects, Utc::now(), used_tokens).await?;
+
+    let Some(title) = clean_title(&completion.content) else {
+        return Ok(None);
+    };
+
+    chat_thread::set_missing_chat_thread_title(db_conn, chat_thread_id, &title).await?;
+
+    Ok(Some(title))
+}
+
+// The title isn't needed for the answer, so it's generated in the background
+// and a failure is only logged.
+pub fn spawn_thread_title_generation(
+    db_conn: Pool<Sqlite>,
+    llm_api_client: Box<dyn LLMService>,
+    subjects: Vec<Subject>,
+    chat_thread_id: i64,
+) {
+    tokio::spawn(async move {
+        if let Err(e) =
+            generate_thread_title(&db_conn, llm_api_client.as_ref(), &subjects, chat_thread_id)
+                .await
+        {
+            println!(
+                "Failed to generate the title of the thread {}: {:?}",
+                chat_thread_id, e
+            );
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Limits;
+    use crate::db;
+    use crate::db::usage;
+    use crate::llm::mock::{Mock, MockCompletionModel};
+
+    #[tokio::test]
+    async fn counts_the_tokens_of_the_title() {
+        let path = std::env::temp_dir().join(format!("thread-title-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let db_conn = db::start(&format!("sqlite:{}", path.display())).await;
+        let thread = chat_thread::get_or_create_chat_thread(&db_conn, 42)
+            .await
+            .unwrap();
+
+        for (content, user_role) in [("Hi!", "user"), ("Hello!", "assistant")] {
+            chat_message::insert_new_message(
+                &db_conn, content, 42, thread.id, user_role, None, None,
+            )
+            .await
+            .unwrap();
+        }
+
+        let subjects = [usage::SUBJECT_USER, usage::SUBJECT_CHAT].map(|kind| Subject {
+            kind,
+            id: 42,
+            limits: Limits::default(),
+        });
+        let llm_api_client = Mock {
+            completion_model: MockCompletionModel::Bright,
+        };
+
+        generate_thread_title(&db_conn, &llm_api_client, &subjects, thread.id)
+            .await
+            .unwrap();
+
+        let today = Utc::now().format("%Y-%m-%d").to_string();
+        for subject in subjects {
+            let daily_usage = usage::get_daily_usage(&db_conn, subject.kind, 42, &today)
+                .await
+                .unwrap();
+
+            assert_eq!(daily_usage.messages, 0);
+            assert!(daily_usage.tokens > 0);
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use sqlx::{Pool, Sqlite, SqliteConnection};
+
+use crate::config::Limits;
+use crate::db::usage;
+use crate::db::usage::{DailyUsage, RateLimitBucket};
+use crate::llm::llm_thread_message::LLMThreadMessage;
+use crate::llm::TokenUsage;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LimitKind {
+    MessagesPerMinute,
+    DailyMessages,
+    DailyTokens,
+}
+
+// The limit a message ran into, and when the next one will be allowed.
+#[derive(Clone, Debug)]
+pub struct Limited {
+    pub subject: &'static str,
+    pub kind: LimitKind,
+    pub retry_at: DateTime<Utc>,
+}
+
+// A user or a chat, with the limits that apply to it.
+#[derive(Clone, Copy, Debug)]
+pub struct Subject {
+    pub kind: &'static str,
+    pub id: i64,
+    pub limits: Limits,
+}
+
+pub struct Allowance {
+    pub usage: DailyUsage,
+    pub available_messages: Option<u32>,
+}
+
+// The daily quotas are reset at midnight UTC.
+fn day(now: DateTime<Utc>) -> String {
+    now.format("%Y-%m-%d").to_string()
+}
+
+pub fn next_day(now: DateTime<Utc>) -> DateTime<Utc> {
+    (now.date_naive() + Duration::days(1))
+        .and_time(NaiveTime::MIN)
+        .and_utc()
+}
+
+fn timestamp(now: DateTime<Utc>) -> f64 {
+    now.timestamp_millis() as f64 / 1000.0
+}
+
+// A token bucket holding up to messages_per_minute messages, which starts full
+// and refills continuously, so a burst of messages is allowed after a pause.
+fn available_tokens(bucket: Option<&RateLimitBucket>, messages_per_minute: u32, now: f64) -> f64 {
+    let capacity = f64::from(messages_per_minute);
+
+    match bucket {
+        Some(bucket) => {
+            let refill = (now - bucket.updated_at).max(0.0) * capacity / 60.0;
+            (bucket.tokens + refill).min(capacity)
+        }
+        None => capacity,
+    }
+}
+
+fn over_quota(used: i64, limit: Option<u32>) -> bool {
+    limit.is_some_and(|limit| used >= i64::from(limit))
+}
+
+// Counts a message answered by the model against every subject's limits.
+// Nothing is counted when one of them is reached.
+pub async fn consume_message(
+    db_conn: &Pool<Sqlite>,
+    subjects: &[Subject],
+    now: DateTime<Utc>,
+) -> anyhow::Result<Option<Limited>> {
+    let mut conn = db_conn.acquire().await?;
+
+    // The write lock is taken before the usage is read, so the messages sent
+    // at the same time are counted one after the other and can't all pass the
+    // last allowed one.
+    sqlx::query("BEGIN IMMEDIATE").execute(&mut *conn).await?;
+
+    let limited = count_message(&mut conn, subjects, now).await;
+
+    sqlx::query(if matches!(limited, Ok(None)) {
+        "COMMIT"
+    } else {
+        "ROLLBACK"
+    })
+    .execute(&mut *conn)
+    .await?;
+
+    limited
+}
+
+async fn count_message(
+    conn: &mut SqliteConnection,
+    subjects: &[Subject],
+    now: DateTime<Utc>,
+) -> anyhow::Result<Option<Limited>> {
+    let today = day(now);
+    let now_timestamp = timestamp(now);
+    let mut available = vec![];
+
+    for subject in subjects {
+        let daily_usage =
+            usage::get_daily_usage(&mut *conn, subject.kind, subject.id, &today).await?;
+
+        let exhausted_quota = if over_quota(daily_usage.messages, subject.limits.daily_messages) {
+            Some(LimitKind::DailyMessages)
+        } else if over_quota(daily_usage.tokens, subject.limits.daily_tokens) {
+            Some(LimitKind::DailyTokens)
+        } else {
+            None
+        };
+
+        if let Some(kind) = exhausted_quota {
+            return Ok(Some(Limited {
+                subject: subject.kind,
+                kind,
+                retry_at: next_day(now),
+            }));
+        }
+
+        let Some(messages_per_minute) = subject.limits.messages_per_minute else {
+            available.push(None);
+            continue;
+        };
+
+        let bucket = usage::get_rate_limit_bucket(&mut *conn, subject.kind, subject.id).await?;
+        let tokens = available_tokens(bucket.as_ref(), messages_per_minute, now_timestamp);
+
+        if tokens < 1.0 {
+            let wait_ms = (1.0 - tokens) * 60_000.0 / f64::from(messages_per_minute);
+
+            return Ok(Some(Limited {
+                subject: subject.kind,
+                kind: LimitKind::MessagesPerMinute,
+                retry_at: now + Duration::milliseconds(wait_ms.ceil() as i64),
+            }));
+        }
+
+        available.push(Some(tokens));
+    }
+
+    for (subject, tokens) in subjects.iter().zip(available) {
+        if let Some(tokens) = tokens {
+            let bucket = RateLimitBucket {
+                tokens: tokens - 1.0,
+                updated_at: now_timestamp,
+            };
+
+            usage::set_rate_limit_bucket(&mut *conn, subject.kind, subject.id, &bucket).await?;
+        }
+
+        usage::add_daily_usage(&mut *conn, subject.kind, subject.id, &today, 1, 0).await?;
+    }
+
+    Ok(None)
+}
+
+// The providers that don't report the usage are counted at ~4 characters per
+// token.
+pub fn used_tokens(
+    thread_messages: &[LLMThreadMessage],
+    content: &str,
+    usage: Option<TokenUsage>,
+) -> i64 {
+    match usage {
+        Some(usage) => usage.prompt_tokens + usage.completion_tokens,
+        None => {
+            let characters: usize = thread_messages
+                .iter()
+                .map(|m| m.message.chars().count())
+                .sum::<usize>()
+                + content.chars().count();
+
+            (characters / 4) as i64
+        }
+    }
+}
+
+pub async fn record_tokens(
+    db_conn: &Pool<Sqlite>,
+    subjects: &[Subject],
+    now: DateTime<Utc>,
+    tokens: i64,
+) -> anyhow::Result<()> {
+    let today = day(now);
+
+    for subject in subjects {
+        usage::add_daily_usage(db_conn, subject.kind, subject.id, &today, 0, tokens).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn allowance(
+    db_conn: &Pool<Sqlite>,
+    subject: &Subject,
+    now: DateTime<Utc>,
+) -> anyhow::Result<Allowance> {
+    let usage = usage::get_daily_usage(db_conn, subject.kind, subject.id, &day(now)).await?;
+
+    let available_messages = match subject.limits.messages_per_minute {
+        Some(messages_per_minute) => {
+            let bucket = usage::get_rate_limit_bucket(db_conn, subject.kind, subject.id).await?;
+            let tokens = available_tokens(bucket.as_ref(), messages_per_minute, timestamp(now));
+
+            Some(tokens.floor() as u32)
+        }
+        None => None,
+    };
+
+    Ok(Allowance {
+        usage,
+        available_messages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    async fn test_db(name: &str) -> Pool<Sqlite> {
+        let path =
+            std::env::temp_dir().join(format!("usage-limits-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        db::start(&format!("sqlite:{}", path.display())).await
+    }
+
+    fn user(limits: Limits) -> Subject {
+        Subject {
+            kind: usage::SUBJECT_USER,
+            id: 42,
+            limits,
+        }
+    }
+
+    #[tokio::test]
+    async fn counts_the_messages_sent_at_the_same_time_one_after_the_other() {
+        let db_conn = test_db("concurrent").await;
+        let subjects = [user(Limits {
+            daily_messages: Some(3),
+            ..Limits::default()
+        })];
+        let now = Utc::now();
+
+        let consume = || consume_message(&db_conn, &subjects, now);
+        let results = tokio::join!(consume(), consume(), consume(), consume(), consume());
+
+        let allowed = [results.0, results.1, results.2, results.3, results.4]
+            .into_iter()
+            .map(Result::unwrap)
+            .filter(Option::is_none)
+            .count();
+        let usage = usage::get_daily_usage(&db_conn, usage::SUBJECT_USER, 42, &day(now))
+            .await
+            .unwrap();
+
+        assert_eq!(allowed, 3);
+        assert_eq!(usage.messages, 3);
+    }
+
+    #[tokio::test]
+    async fn counts_nothing_when_a_limit_is_reached() {
+        let db_conn = test_db("limited").await;
+        let now = Utc::now();
+        let limited_chat = Subject {
+            kind: usage::SUBJECT_CHAT,
+            id: 7,
+            limits: Limits {
+                messages_per_minute: Some(1),
+                ..Limits::default()
+            },
+        };
+        let subjects = [user(Limits::default()), limited_chat];
+
+        assert!(consume_message(&db_conn, &subjects, now)
+            .await
+            .unwrap()
+            .is_none());
+
+        let limited = consume_message(&db_conn, &subjects, now)
+            .await
+            .unwrap()
+            .unwrap();
+        let usage = usage::get_daily_usage(&db_conn, usage::SUBJECT_USER, 42, &day(now))
+            .await
+            .unwrap();
+
+        assert_eq!(limited.kind, LimitKind::MessagesPerMinute);
+        assert_eq!(limited.subject, usage::SUBJECT_CHAT);
+        assert_eq!(usage.messages, 1);
+    }
+}
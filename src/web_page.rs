@@ -0,0 +1,561 @@
+use anyhow::{anyhow, Context, Result};
+use std::net::IpAddr;
+use std::time::Duration;
+
+pub const MAX_PAGE_SIZE: usize = 5 * 1024 * 1024;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+const MAX_REDIRECTS: usize = 10;
+
+// The page text shares the context with the behavior, the thread history and
+// the answer, so it gets about half of the window at ~4 characters per token.
+const CHARS_PER_CONTEXT_TOKEN: usize = 2;
+
+const SKIPPED_TAGS: [&str; 11] = [
+    "script", "style", "noscript", "svg", "head", "nav", "footer", "aside", "form", "iframe",
+    "template",
+];
+
+const BLOCK_TAGS: [&str; 22] = [
+    "p",
+    "div",
+    "br",
+    "li",
+    "ul",
+    "ol",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "tr",
+    "table",
+    "section",
+    "article",
+    "main",
+    "header",
+    "blockquote",
+    "pre",
+    "hr",
+    "dd",
+];
+
+#[derive(Clone, Debug)]
+pub struct WebPage {
+    pub url: String,
+    pub title: Option<String>,
+    pub text: String,
+}
+
+// The redirects aren't followed by the client, so that fetch can check where
+// each of them leads.
+pub fn new_http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(reqwest::redirect::Policy::none())
+        .user_agent(format!(
+            "telegram-llm-assistant/{}",
+            env!("CARGO_PKG_VERSION")
+        ))
+        .build()
+        .unwrap_or_default()
+}
+
+pub fn is_single_url(text: &str) -> bool {
+    let text = text.trim();
+
+    !text.contains(char::is_whitespace)
+        && (text.starts_with("http://") || text.starts_with("https://"))
+        && reqwest::Url::parse(text).is_ok()
+}
+
+// The pages are fetched on behalf of the users, so the bot's host must not be
+// usable to reach the services of its own network.
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || first == 0
+                // The shared address space of the carrier-grade NATs.
+                || (first == 100 && (second & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(IpAddr::V4(ip)),
+            None => {
+                let first_segment = ip.segments()[0];
+
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    // The unique local and the link-local addresses.
+                    || (first_segment & 0xfe00) == 0xfc00
+                    || (first_segment & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+async fn check_url(url: &reqwest::Url, is_allowed: fn(IpAddr) -> bool) -> Result<()> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(anyhow!("Only http and https URLs are supported"));
+    }
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| anyhow!("The URL {} has no host", url))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or(80);
+
+    let addresses: Vec<IpAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .context(format!("Failed to resolve {}", host))?
+        .map(|address| address.ip())
+        .collect();
+
+    if addresses.is_empty() || !addresses.into_iter().all(is_allowed) {
+        return Err(anyhow!("The address of {} isn't allowed", host));
+    }
+
+    Ok(())
+}
+
+pub async fn fetch(http_client: &reqwest::Client, url: &str) -> Result<WebPage> {
+    fetch_checked(http_client, url, is_public_address).await
+}
+
+// Every redirect is checked like the first URL, and so is the address the
+// response came from, in case the host resolved differently meanwhile.
+async fn fetch_checked(
+    http_client: &reqwest::Client,
+    url: &str,
+    is_allowed: fn(IpAddr) -> bool,
+) -> Result<WebPage> {
+    let mut url = reqwest::Url::parse(url).context(format!("Invalid URL {}", url))?;
+    let mut redirects = 0;
+
+    let mut response = loop {
+        check_url(&url, is_allowed).await?;
+
+        let response = http_client
+            .get(url.clone())
+            .send()
+            .await
+            .context(format!("Failed to fetch {}", url))?;
+
+        if response
+            .remote_addr()
+            .is_some_and(|address| !is_allowed(address.ip()))
+        {
+            return Err(anyhow!("The address of {} isn't allowed", url));
+        }
+
+        if !response.status().is_redirection() {
+            break response
+                .error_for_status()
+                .context(format!("Failed to fetch {}", url))?;
+        }
+
+        redirects += 1;
+
+        if redirects > MAX_REDIRECTS {
+            return Err(anyhow!("Too many redirects from {}", url));
+        }
+
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| anyhow!("The redirect from {} has no location", url))?;
+
+        url = url
+            .join(location)
+            .context(format!("Invalid redirect from {}", url))?;
+    };
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("text/html")
+        .to_lowercase();
+
+    if !content_type.starts_with("text/") && !content_type.contains("xhtml") {
+        return Err(anyhow!("Unsupported content type {}", content_type));
+    }
+
+    if response
+        .content_length()
+        .is_some_and(|length| length as usize > MAX_PAGE_SIZE)
+    {
+        return Err(anyhow!("The page is too large"));
+    }
+
+    // The length may be missing or wrong, so the body is read in chunks until
+    // it's over the limit.
+    let mut body: Vec<u8> = vec![];
+
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context(format!("Failed to read {}", url))?
+    {
+        body.extend_from_slice(&chunk);
+
+        if body.len() > MAX_PAGE_SIZE {
+            return Err(anyhow!("The page is too large"));
+        }
+    }
+
+    let body = String::from_utf8_lossy(&body);
+
+    let (title, text) = if content_type.starts_with("text/plain") {
+        (None, body.trim().to_string())
+    } else {
+        (extract_title(&body), extract_readable_text(&body))
+    };
+
+    if text.is_empty() {
+        return Err(anyhow!("The page doesn't contain any readable text"));
+    }
+
+    Ok(WebPage {
+        url: url.to_string(),
+        title,
+        text,
+    })
+}
+
+// Keeps the text of the page truncated on a word boundary, so that it fits
+// into the context window of the model.
+pub fn truncate_to_context(text: &str, context_window: usize) -> String {
+    let max_chars = context_window * CHARS_PER_CONTEXT_TOKEN;
+
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let truncated: String = text.chars().take(max_chars).collect();
+    let truncated = match truncated.rfind(char::is_whitespace) {
+        Some(end) => &truncated[..end],
+        None => truncated.as_str(),
+    };
+
+    format!("{}…", truncated.trim_end())
+}
+
+pub fn extract_title(html: &str) -> Option<String> {
+    let lowercase = html.to_ascii_lowercase();
+    let start = lowercase.find("<title")?;
+    let start = start + lowercase[start..].find('>')? + 1;
+    let end = start + lowercase[start..].find("</title")?;
+
+    Some(collapse_whitespace(&decode_entities(&html[start..end]))).filter(|t| !t.is_empty())
+}
+
+// Prefers the <article> or <main> element when the page has one, and drops
+// the markup together with the scripts, styles and navigation.
+pub fn extract_readable_text(html: &str) -> String {
+    let lowercase = html.to_ascii_lowercase();
+
+    let content = ["article", "main"]
+        .iter()
+        .find_map(|tag| {
+            let start = lowercase.find(&format!("<{}", tag))?;
+            let end = lowercase.rfind(&format!("</{}", tag))?;
+            (start < end).then(|| &html[start..end])
+        })
+        .unwrap_or(html);
+
+    decode_entities(&strip_tags(content))
+        .lines()
+        .map(collapse_whitespace)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn strip_tags(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    let mut skipped_tag: Option<String> = None;
+
+    while let Some(start) = rest.find('<') {
+        if skipped_tag.is_none() {
+            text.push_str(&rest[..start]);
+        }
+
+        let tag_start = &rest[start..];
+
+        if tag_start.starts_with("<!--") {
+            rest = tag_start
+                .find("-->")
+                .map(|end| &tag_start[end + 3..])
+                .unwrap_or("");
+            continue;
+        }
+
+        let Some(end) = tag_start.find('>') else {
+            rest = "";
+            break;
+        };
+
+        let tag = &tag_start[1..end];
+        rest = &tag_start[end + 1..];
+
+        let is_closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        if let Some(skipped) = &skipped_tag {
+            if is_closing && name == *skipped {
+                skipped_tag = None;
+            }
+            continue;
+        }
+
+        if !is_closing && !tag.ends_with('/') && SKIPPED_TAGS.contains(&name.as_str()) {
+            skipped_tag = Some(name);
+        } else if BLOCK_TAGS.contains(&name.as_str()) {
+            text.push('\n');
+        } else if name == "td" || name == "th" {
+            text.push(' ');
+        }
+    }
+
+    if skipped_tag.is_none() {
+        text.push_str(rest);
+    }
+
+    text
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+
+        let entity_start = &rest[start..];
+        let entity = entity_start
+            .find(';')
+            .filter(|end| *end <= 10)
+            .and_then(|end| decode_entity(&entity_start[1..end]).map(|c| (c, end)));
+
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &entity_start[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &entity_start[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        "mdash" => Some('—'),
+        "ndash" => Some('–'),
+        "hellip" => Some('…'),
+        _ => {
+            let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|d| d.parse().ok()),
+            };
+
+            code.and_then(char::from_u32)
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // Answers each connection with the next response, and returns the port.
+    fn serve(responses: Vec<Vec<u8>>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            for response in responses {
+                let Ok((mut stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                let _ = stream.write_all(&response);
+            }
+        });
+
+        port
+    }
+
+    fn response(headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nConnection: close\r\n{}\r\n\r\n",
+            headers
+        )
+        .into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    fn allow_all(_: IpAddr) -> bool {
+        true
+    }
+
+    fn allow_localhost(ip: IpAddr) -> bool {
+        ip == IpAddr::from([127, 0, 0, 1])
+    }
+
+    #[tokio::test]
+    async fn fetches_the_readable_text_of_a_page() {
+        let body = "<html><head><title>A &amp; B</title></head><body><nav>Menu</nav><article><p>First</p><p>Second</p></article></body></html>";
+        let port = serve(vec![response(
+            &format!(
+                "Content-Type: text/html; charset=utf-8\r\nContent-Length: {}",
+                body.len()
+            ),
+            body.as_bytes(),
+        )]);
+
+        let page = fetch_checked(
+            &new_http_client(),
+            &format!("http://127.0.0.1:{}/page", port),
+            allow_all,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(page.title.as_deref(), Some("A & B"));
+        assert_eq!(page.text, "First\nSecond");
+    }
+
+    #[tokio::test]
+    async fn refuses_a_page_over_the_size_limit_without_a_length() {
+        let port = serve(vec![response(
+            "Content-Type: text/html",
+            &vec![b'a'; MAX_PAGE_SIZE + 1],
+        )]);
+
+        let error = fetch_checked(
+            &new_http_client(),
+            &format!("http://127.0.0.1:{}/", port),
+            allow_all,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "The page is too large");
+    }
+
+    #[tokio::test]
+    async fn refuses_the_content_that_isnt_text() {
+        let port = serve(vec![response(
+            "Content-Type: image/png\r\nContent-Length: 4",
+            b"\x89PNG",
+        )]);
+
+        let error = fetch_checked(
+            &new_http_client(),
+            &format!("http://127.0.0.1:{}/image.png", port),
+            allow_all,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "Unsupported content type image/png");
+    }
+
+    #[tokio::test]
+    async fn refuses_the_internal_addresses() {
+        let port = serve(vec![response(
+            "Content-Type: text/plain\r\nContent-Length: 6",
+            b"secret",
+        )]);
+
+        for url in [
+            format!("http://127.0.0.1:{}/", port),
+            format!("http://localhost:{}/", port),
+            "http://[::1]/".to_string(),
+            "http://169.254.169.254/latest/meta-data/".to_string(),
+            "http://10.0.0.1/".to_string(),
+        ] {
+            let error = fetch(&new_http_client(), &url).await.unwrap_err();
+
+            assert!(error.to_string().contains("isn't allowed"), "{}", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_every_redirect() {
+        let port = serve(vec![
+            b"HTTP/1.1 302 Found\r\nConnection: close\r\nLocation: http://127.0.0.2/admin\r\nContent-Length: 0\r\n\r\n".to_vec(),
+        ]);
+
+        let error = fetch_checked(
+            &new_http_client(),
+            &format!("http://127.0.0.1:{}/", port),
+            allow_localhost,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(error.to_string(), "The address of 127.0.0.2 isn't allowed");
+    }
+
+    #[test]
+    fn tells_the_public_addresses() {
+        for address in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_address(address.parse().unwrap()), "{}", address);
+        }
+
+        for address in ["93.184.216.34", "1.1.1.1", "2606:4700::1111"] {
+            assert!(is_public_address(address.parse().unwrap()), "{}", address);
+        }
+    }
+}